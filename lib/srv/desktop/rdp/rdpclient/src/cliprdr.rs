@@ -21,7 +21,9 @@ use num_traits::FromPrimitive;
 use rdp::core::{mcs, tpkt};
 use rdp::model::error::*;
 use rdp::try_let;
-use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
 use std::io::{Cursor, Read, Seek, SeekFrom, Write};
 
 pub const CHANNEL_NAME: &str = "cliprdr";
@@ -97,6 +99,65 @@ struct FileDescriptor {
     file_name: String,
 }
 
+impl FileDescriptor {
+    fn from_metadata(meta: FileMetadata) -> Self {
+        let mut file_attributes = FileAttributesFlags::empty();
+        if meta.is_directory {
+            file_attributes |= FileAttributesFlags::FILE_ATTRIBUTE_DIRECTORY;
+        }
+
+        Self {
+            flags: FileDescriptorFlags::FD_ATTRIBUTES
+                | FileDescriptorFlags::FD_FILESIZE
+                | FileDescriptorFlags::FD_WRITESTIME,
+            file_attributes,
+            last_write_time: meta.last_write_time,
+            file_size: meta.size,
+            file_name: meta.name,
+        }
+    }
+
+    /// Encodes this descriptor as a 592-byte packed CLIPRDR_FILEDESCRIPTOR record, the mirror of
+    /// the decode loop in handle_file_list. file_size is clamped to 32 bits unless
+    /// huge_file_support_enabled (the negotiated CB_HUGE_FILE_SUPPORT_ENABLED capability).
+    fn encode(&self, huge_file_support_enabled: bool) -> RdpResult<Vec<u8>> {
+        let file_size = clamp_file_size(self.file_size, huge_file_support_enabled);
+
+        let mut w = Vec::with_capacity(592);
+        w.write_u32::<LittleEndian>(self.flags.bits())?;
+        w.write_all(&[0u8; 32])?; // reserved1
+        w.write_u32::<LittleEndian>(self.file_attributes.bits())?;
+        w.write_all(&[0u8; 16])?; // reserved2
+        w.write_u64::<LittleEndian>(self.last_write_time)?;
+        w.write_u32::<LittleEndian>((file_size >> 32) as u32)?; // file_size_high
+        w.write_u32::<LittleEndian>(file_size as u32)?; // file_size_low
+
+        // A null-terminated, 260 wide-char (520-byte) UTF-16 name field, truncated if too long
+        // and null-padded out to the full width either way.
+        let mut name: Vec<u16> = self.file_name.encode_utf16().take(259).collect();
+        name.push(0);
+        name.resize(260, 0);
+        for c in name {
+            w.write_u16::<LittleEndian>(c)?;
+        }
+
+        Ok(w)
+    }
+}
+
+/// Metadata for one file offered to the RDP server via `update_clipboard_files`, simplified from
+/// the wire-level `FileDescriptor` down to what a caller has on hand (e.g. from a directory
+/// listing) - the descriptor's flags are derived from it internally.
+#[derive(Debug, Clone)]
+pub struct FileMetadata {
+    pub name: String,
+    pub size: u64,
+    /// Number of 100-nanosecond intervals since 1 January 1601 (Windows FILETIME), matching the
+    /// wire encoding of FileDescriptor::last_write_time.
+    pub last_write_time: u64,
+    pub is_directory: bool,
+}
+
 /// FileListManager manages the global state necessary to handle
 /// transferring files via the clipboard channel.
 #[derive(Debug)]
@@ -115,24 +176,173 @@ struct FileListManager {
     file_list: Vec<FileDescriptor>,
 }
 
+/// One entry of a Format List PDU, simplified from the wire-level LongFormatName/ShortFormatName
+/// representation down to what a `ClipboardBackend` actually needs to know.
+#[derive(Debug, Clone)]
+pub struct ClipboardFormat {
+    pub id: u32,
+    pub name: Option<String>,
+}
+
+/// Everything CLIPRDR-protocol-specific (framing, format negotiation, file list parsing) lives in
+/// `Client`; a `ClipboardBackend` is what turns its events into an actual clipboard - reading
+/// from and writing to the local OS clipboard, a headless buffer, a test double, or anything else
+/// an embedder wants. All methods take `&mut self` since most non-trivial backends need to record
+/// state (what the server just advertised, what's pending a delayed render, ...).
+pub trait ClipboardBackend {
+    /// The CB_CLIP_CAPS exchange with the server has completed; called once, from
+    /// handle_server_caps, before the format-list/format-data traffic that exchange unblocks
+    /// starts flowing. huge_file_support_enabled reflects Client::huge_file_support_enabled - a
+    /// backend that chunks large file reads can use it to decide how big a chunk it's safe to
+    /// promise in a single FILECONTENTS_RANGE reply.
+    fn on_capabilities_negotiated(&mut self, huge_file_support_enabled: bool);
+
+    /// The server copied data in these formats; called once per CB_FORMAT_LIST PDU.
+    fn on_format_list(&mut self, formats: &[ClipboardFormat]);
+
+    /// The server is pasting and wants our data in format_id, which we previously advertised.
+    /// Returns `None` if the backend has nothing to offer for that format (rendering can be
+    /// delayed all the way until this call, rather than eagerly precomputed).
+    fn on_format_data_request(&mut self, format_id: u32) -> Option<Vec<u8>>;
+
+    /// The server replied to a format data request we sent (see `ClipboardFormat` delivered via
+    /// `on_format_list`) with data in format_id.
+    fn on_format_data_response(&mut self, format_id: u32, data: Vec<u8>);
+
+    /// The server replied to a `request_file_contents` call for the file at list_index.
+    fn on_file_contents_response(&mut self, list_index: u32, data: FileContentsData);
+
+    /// The server replied to a format data request for CF_DIB/CF_DIBV5 with `data`, a standalone
+    /// .bmp-compatible image (handle_format_data_response has already prepended the
+    /// BITMAPFILEHEADER that the raw DIB on the wire lacks).
+    fn on_image_data_response(&mut self, data: Vec<u8>);
+
+    /// The server is requesting `length` bytes starting at `position` from the file at
+    /// `list_index` in the list most recently advertised via `update_clipboard_files`. Returns
+    /// `None` if the range can't be read (e.g. the file changed or disappeared underneath us).
+    fn read_file_contents(
+        &mut self,
+        list_index: u32,
+        position: u64,
+        length: u32,
+    ) -> Option<Vec<u8>>;
+}
+
 /// Client implements a client for the clipboard virtual channel
 /// (CLIPRDR) extension, as defined in:
 /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpeclip/fb9b7e0b-6db4-41c2-b83c-f889c1ee7688
 pub struct Client {
+    /// Eagerly-rendered data offered via update_clipboard, keyed by format_id. Consulted only if
+    /// the backend doesn't have an answer for the requested format - most backends that care about
+    /// delayed rendering won't need this at all.
     clipboard: HashMap<u32, Vec<u8>>,
     pending: PendingData,
-    on_remote_copy: Box<dyn Fn(Vec<u8>)>,
+    backend: Box<dyn ClipboardBackend>,
     file_list_manager: FileListManager,
+    /// In-flight CB_FILECONTENTS_REQUESTs, keyed by the stream_id we generated for them, so the
+    /// matching CB_FILECONTENTS_RESPONSE can be decoded the right way (a file size vs. a byte
+    /// range) and attributed back to the file that was asked for.
+    pending_file_contents_requests: HashMap<u32, PendingFileContentsRequest>,
+    /// Monotonically increasing source of stream_ids for FileContentsRequestPDU, so concurrent
+    /// requests for different files/ranges never collide.
+    next_stream_id: u32,
+    /// Set by handle_format_list when a text format was the one we requested (as opposed to the
+    /// file list format), so handle_format_data_response knows whether/how to transcode the
+    /// response before handing it to the backend. None while a file list is in flight, or before
+    /// any format has been requested.
+    requested_text_format: Option<ClipboardFormatId>,
+    /// Set by handle_format_list when CF_DIB/CF_DIBV5 was the format we requested, so
+    /// handle_format_data_response knows to reconstruct a .bmp (rather than treat the response as
+    /// text). None while a text or file list request is in flight, or before any format has been
+    /// requested.
+    requested_image_format: Option<ClipboardFormatId>,
+    /// The format_id we most recently sent a CB_FORMAT_DATA_REQUEST for, so
+    /// handle_format_data_response knows which format to report to the backend (the response PDU
+    /// itself doesn't carry a format_id).
+    requested_format_id: Option<u32>,
+    /// Allocator for the clipDataId carried by CB_LOCK_CLIPDATA; incremented each time a file
+    /// list transfer begins.
+    next_clip_data_id: u32,
+    /// The clipDataId we locked (and sent to the server via CB_LOCK_CLIPDATA) for the file list
+    /// transfer currently in progress, if any. Threaded through every FileContentsRequestPDU
+    /// issued while it's set, and cleared by unlocking it (see finish_file_transfer).
+    locked_clip_data_id: Option<u32>,
+    /// clipDataIds the server has told us (via CB_LOCK_CLIPDATA) it has locked.
+    server_locked_clip_data_ids: HashSet<u32>,
+    /// The files most recently advertised via update_clipboard_files, served back to the server
+    /// in response to CB_FORMAT_DATA_REQUEST (as a packed file list) and CB_FILECONTENTS_REQUEST
+    /// (as individual file sizes/ranges).
+    local_file_list: Vec<FileDescriptor>,
+    /// Set by handle_format_list when CLIPBOARD_FORMAT_NAME_HTML was the format we requested, so
+    /// handle_format_data_response knows to parse the response as a CF_HTML blob (banner + HTML
+    /// document) rather than treat it as plain text.
+    expecting_html: bool,
+    /// The CF_HTML blob (banner plus wrapped document) most recently advertised via
+    /// update_clipboard_html, served back to the server in response to a CB_FORMAT_DATA_REQUEST
+    /// for LOCAL_HTML_FORMAT_ID.
+    local_html: Option<Vec<u8>>,
+    /// Whether both peers advertised CB_HUGE_FILE_SUPPORT_ENABLED in the CB_CLIP_CAPS exchange
+    /// (we always advertise it ourselves; this records whether the server did too, as observed in
+    /// handle_server_caps). File sizes - in file descriptors and FILECONTENTS_SIZE responses - are
+    /// treated as full 64-bit values when this is set, and clamped to 32 bits otherwise.
+    huge_file_support_enabled: bool,
+    /// Whether the server's CB_CLIP_CAPS advertised CB_USE_LONG_FORMAT_NAMES. Defaults to true
+    /// (we always advertise it and nearly everything supports it) until handle_server_caps runs
+    /// and finds out otherwise; gates whether outgoing format lists are encoded with
+    /// LongFormatName or the fixed-width ShortFormatName.
+    long_format_names_supported: bool,
+    /// Whether the server's CB_CLIP_CAPS advertised CB_STREAM_FILECLIP_ENABLED. Defaults to true
+    /// until handle_server_caps says otherwise; gates whether update_clipboard_files advertises
+    /// the file list format at all, since a server that can't stream file contents can't honor a
+    /// paste of it anyway.
+    file_transfer_supported: bool,
+    /// A content fingerprint (DefaultHasher digest) per format group, keyed by the group's
+    /// representative format id (CF_UNICODETEXT for text, CF_DIB for images), recorded every time
+    /// update_clipboard/update_clipboard_image caches a payload. An RDP clipboard owner
+    /// re-announces its format list whenever the *local* clipboard changes, including when that
+    /// change originated from us relaying the remote clipboard - without this, that echo would
+    /// bounce back and forth between client and server forever. Tracked per group rather than
+    /// globally so copying an image doesn't suppress a still-pending text update and vice versa.
+    clipboard_hashes: HashMap<u32, u64>,
+}
+
+struct PendingFileContentsRequest {
+    list_index: u32,
+    dw_flags: FileContentsFlags,
+}
+
+/// A `ClipboardBackend` that drops every event. Used as Client's default so tests and callers
+/// that don't care about clipboard contents don't need to supply one.
+#[derive(Default)]
+struct NoopClipboardBackend;
+
+impl ClipboardBackend for NoopClipboardBackend {
+    fn on_capabilities_negotiated(&mut self, _huge_file_support_enabled: bool) {}
+    fn on_format_list(&mut self, _formats: &[ClipboardFormat]) {}
+    fn on_format_data_request(&mut self, _format_id: u32) -> Option<Vec<u8>> {
+        None
+    }
+    fn on_format_data_response(&mut self, _format_id: u32, _data: Vec<u8>) {}
+    fn on_file_contents_response(&mut self, _list_index: u32, _data: FileContentsData) {}
+    fn on_image_data_response(&mut self, _data: Vec<u8>) {}
+    fn read_file_contents(
+        &mut self,
+        _list_index: u32,
+        _position: u64,
+        _length: u32,
+    ) -> Option<Vec<u8>> {
+        None
+    }
 }
 
 impl Default for Client {
     fn default() -> Self {
-        Self::new(Box::new(|_| {}))
+        Self::new(Box::<NoopClipboardBackend>::default())
     }
 }
 
 impl Client {
-    pub fn new(on_remote_copy: Box<dyn Fn(Vec<u8>)>) -> Self {
+    pub fn new(backend: Box<dyn ClipboardBackend>) -> Self {
         Client {
             clipboard: HashMap::new(),
             pending: PendingData {
@@ -140,14 +350,119 @@ impl Client {
                 total_length: 0,
                 clipboard_header: None,
             },
-            on_remote_copy,
+            backend,
             file_list_manager: FileListManager {
                 is_expecting_file_list: false,
                 file_list: Vec::new(),
             },
+            pending_file_contents_requests: HashMap::new(),
+            next_stream_id: 0,
+            requested_text_format: None,
+            requested_image_format: None,
+            requested_format_id: None,
+            next_clip_data_id: 0,
+            locked_clip_data_id: None,
+            server_locked_clip_data_ids: HashSet::new(),
+            local_file_list: Vec::new(),
+            expecting_html: false,
+            local_html: None,
+            huge_file_support_enabled: false,
+            long_format_names_supported: true,
+            file_transfer_supported: true,
+            clipboard_hashes: HashMap::new(),
         }
     }
 
+    /// Hashes `content`, and if it matches the last hash recorded under `group` returns true
+    /// without recording anything (the caller should skip re-announcing); otherwise records the
+    /// new hash under `group` and returns false. `group` is a representative format id standing
+    /// in for a whole family of formats derived from the same content (e.g. CF_UNICODETEXT for
+    /// the text formats, CF_DIB for images), so unrelated groups never suppress each other.
+    fn is_duplicate_clipboard_update(&mut self, group: u32, content: &[u8]) -> bool {
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        if self.clipboard_hashes.get(&group) == Some(&hash) {
+            return true;
+        }
+        self.clipboard_hashes.insert(group, hash);
+        false
+    }
+
+    /// Whether both peers negotiated CB_HUGE_FILE_SUPPORT_ENABLED in the CB_CLIP_CAPS exchange,
+    /// so callers (e.g. deciding how to chunk a large file read) know whether file sizes above
+    /// 4 GB can be represented on the wire.
+    pub fn huge_file_support_enabled(&self) -> bool {
+        self.huge_file_support_enabled
+    }
+
+    /// Issues a CB_FILECONTENTS_REQUEST for either the size (dw_flags = FILECONTENTS_SIZE, which
+    /// per 2.2.5.3.1 requires cb_requested=8 and zeroed positions) or a byte range (dw_flags =
+    /// FILECONTENTS_RANGE, at the 64-bit offset formed from position_high/position_low, up to
+    /// cb_requested bytes) of the file_list_manager.file_list entry at list_index. The eventual
+    /// CB_FILECONTENTS_RESPONSE is delivered to on_file_contents_response.
+    pub fn request_file_contents(
+        &mut self,
+        list_index: u32,
+        dw_flags: FileContentsFlags,
+        position_low: u32,
+        position_high: u32,
+        cb_requested: u32,
+    ) -> RdpResult<Vec<Vec<u8>>> {
+        let stream_id = self.next_stream_id;
+
+        let pdu = FileContentsRequestPDU {
+            stream_id,
+            list_index,
+            dw_flags,
+            n_position_low: position_low,
+            n_position_high: position_high,
+            cb_requested,
+            clip_data_id: self.locked_clip_data_id.unwrap_or(0),
+        };
+        let encoded = pdu.encode()?;
+
+        self.next_stream_id = self.next_stream_id.wrapping_add(1);
+        self.pending_file_contents_requests.insert(
+            stream_id,
+            PendingFileContentsRequest {
+                list_index,
+                dw_flags,
+            },
+        );
+
+        encode_message(ClipboardPDUType::CB_FILECONTENTS_REQUEST, encoded)
+    }
+
+    /// Allocates a fresh clipDataId, locks it for the file list transfer that's about to begin
+    /// (recorded in locked_clip_data_id so request_file_contents can thread it through), and
+    /// encodes the CB_LOCK_CLIPDATA PDU that tells the server about it.
+    fn lock_file_list_clip_data(&mut self) -> RdpResult<Vec<Vec<u8>>> {
+        let clip_data_id = self.next_clip_data_id;
+        self.next_clip_data_id = self.next_clip_data_id.wrapping_add(1);
+        self.locked_clip_data_id = Some(clip_data_id);
+
+        encode_message(
+            ClipboardPDUType::CB_LOCK_CLIPDATA,
+            LockClipDataPDU { clip_data_id }.encode()?,
+        )
+    }
+
+    /// Ends the file list transfer currently locked (if any), telling the server via
+    /// CB_UNLOCK_CLIPDATA that its clipDataId is no longer needed. Safe to call whether the
+    /// transfer finished normally or was abandoned (e.g. a new copy superseded it).
+    pub fn finish_file_transfer(&mut self) -> RdpResult<Vec<Vec<u8>>> {
+        let Some(clip_data_id) = self.locked_clip_data_id.take() else {
+            return Ok(vec![]);
+        };
+
+        encode_message(
+            ClipboardPDUType::CB_UNLOCK_CLIPDATA,
+            LockClipDataPDU { clip_data_id }.encode()?,
+        )
+    }
+
     pub fn read<S: Read + Write>(
         &mut self,
         payload: tpkt::Payload,
@@ -212,6 +527,16 @@ impl Client {
                     vec![]
                 }
             }
+            ClipboardPDUType::CB_FILECONTENTS_REQUEST => {
+                self.handle_file_contents_request(payload)?
+            }
+            ClipboardPDUType::CB_FILECONTENTS_RESPONSE => self.handle_file_contents_response(
+                payload,
+                header.data_len,
+                header.msg_flags.contains(ClipboardHeaderFlags::CB_RESPONSE_OK),
+            )?,
+            ClipboardPDUType::CB_LOCK_CLIPDATA => self.handle_lock_clipdata(payload)?,
+            ClipboardPDUType::CB_UNLOCK_CLIPDATA => self.handle_unlock_clipdata(payload)?,
             _ => {
                 warn!(
                     "CLIPRDR message {:?} not implemented, ignoring",
@@ -233,53 +558,144 @@ impl Client {
     /// It updates the local clipboard cache and returns the encoded message
     /// that should be sent to the RDP server.
     pub fn update_clipboard(&mut self, data: Vec<u8>) -> RdpResult<Vec<Vec<u8>>> {
-        const CR: u8 = 13;
-        const LF: u8 = 10;
-
-        // convert LF to CRLF, as required by CF_OEMTEXT
-        let len_orig = data.len();
-        let mut converted = Vec::with_capacity(len_orig);
-        for i in 0..len_orig {
-            match data[i] {
-                LF => {
-                    // convert LF to CRLF, so long as the previous character
-                    // wasn't CR (in which case there's no conversion necessary)
-                    if i == 0 || (data[i - 1] != CR) {
-                        converted.push(CR);
-                    }
-                    converted.push(LF);
-                }
-                _ => converted.push(data[i]),
-            }
-        }
-        // Windows requires a null terminator, so add one if necessary
-        if !converted.is_empty() && converted[converted.len() - 1] != 0x00 {
-            converted.push(0x00);
+        if self.is_duplicate_clipboard_update(ClipboardFormatId::CF_UNICODETEXT as u32, &data) {
+            return Ok(vec![]);
         }
 
+        let converted = convert_lf_to_crlf(&data);
+
+        // CF_UNICODETEXT: UTF-16LE, null-terminated wide char. Listed first so servers that
+        // understand it (i.e. anything recent) prefer it over the legacy formats below.
+        let mut unicodetext: Vec<u8> = String::from_utf8_lossy(&converted)
+            .encode_utf16()
+            .flat_map(|u| u.to_le_bytes())
+            .collect();
+        unicodetext.extend_from_slice(&[0x00, 0x00]);
         self.clipboard
-            .insert(ClipboardFormatId::CF_OEMTEXT as u32, converted);
+            .insert(ClipboardFormatId::CF_UNICODETEXT as u32, unicodetext);
 
-        encode_message(
-            ClipboardPDUType::CB_FORMAT_LIST,
-            FormatListPDU {
-                format_names: vec![LongFormatName::id(ClipboardFormatId::CF_OEMTEXT as u32)],
-            }
-            .encode()?,
-        )
+        // CF_TEXT / CF_OEMTEXT: the same CRLF-converted bytes, null-terminated. Identical under
+        // the hood since we don't do any codepage conversion for either.
+        let mut oemtext = converted;
+        if !oemtext.is_empty() && oemtext[oemtext.len() - 1] != 0x00 {
+            oemtext.push(0x00);
+        }
+        self.clipboard
+            .insert(ClipboardFormatId::CF_TEXT as u32, oemtext.clone());
+        self.clipboard
+            .insert(ClipboardFormatId::CF_OEMTEXT as u32, oemtext);
+
+        self.encode_format_list(vec![
+            (ClipboardFormatId::CF_UNICODETEXT as u32, None),
+            (ClipboardFormatId::CF_TEXT as u32, None),
+            (ClipboardFormatId::CF_OEMTEXT as u32, None),
+        ])
+    }
+
+    /// update_clipboard_files is the file-transfer analogue of update_clipboard: invoked from Go
+    /// when the user copies one or more files, it records their metadata and advertises them to
+    /// the RDP server under the CLIPBOARD_FORMAT_NAME_FILE_LIST long format name. The server
+    /// pastes them by issuing a CB_FORMAT_DATA_REQUEST for that format id (answered by
+    /// handle_format_data_request with a packed CLIPRDR_FILELIST) followed by a
+    /// CB_FILECONTENTS_REQUEST per file (answered by handle_file_contents_request, which reads
+    /// the actual bytes via ClipboardBackend::read_file_contents).
+    pub fn update_clipboard_files(&mut self, files: Vec<FileMetadata>) -> RdpResult<Vec<Vec<u8>>> {
+        self.local_file_list = files.into_iter().map(FileDescriptor::from_metadata).collect();
+
+        // A server that didn't negotiate CB_STREAM_FILECLIP_ENABLED can't stream file contents
+        // back to us, so there's no point advertising a file list it couldn't honor a paste of.
+        if !self.file_transfer_supported {
+            return Ok(vec![]);
+        }
+
+        self.encode_format_list(vec![(
+            LOCAL_FILE_LIST_FORMAT_ID,
+            Some(CLIPBOARD_FORMAT_NAME_FILE_LIST.to_string()),
+        )])
+    }
+
+    /// Encodes local_file_list as the packed CLIPRDR_FILELIST (a u32 count followed by that many
+    /// 592-byte FileDescriptor records) served in response to a CB_FORMAT_DATA_REQUEST for
+    /// LOCAL_FILE_LIST_FORMAT_ID, the mirror of the parsing loop in handle_file_list.
+    fn encode_file_list(&self) -> RdpResult<Vec<u8>> {
+        let mut w = Vec::new();
+        w.write_u32::<LittleEndian>(self.local_file_list.len() as u32)?;
+        for file in &self.local_file_list {
+            w.extend(file.encode(self.huge_file_support_enabled)?);
+        }
+        Ok(w)
+    }
+
+    /// update_clipboard_image is the image analogue of update_clipboard: invoked from Go when the
+    /// user copies an image, it stores the raw DIB under CF_DIB and advertises it to the RDP
+    /// server. CF_DIB never carries a BITMAPFILEHEADER on the wire, so if the caller handed us a
+    /// full .bmp file (recognizable by its "BM" magic), that 14-byte header is stripped first.
+    pub fn update_clipboard_image(&mut self, data: Vec<u8>) -> RdpResult<Vec<Vec<u8>>> {
+        const BITMAPFILEHEADER_LEN: usize = 14;
+        let dib = match data.get(0..2) {
+            Some(b"BM") => data[BITMAPFILEHEADER_LEN..].to_vec(),
+            _ => data,
+        };
+
+        if self.is_duplicate_clipboard_update(ClipboardFormatId::CF_DIB as u32, &dib) {
+            return Ok(vec![]);
+        }
+
+        self.clipboard.insert(ClipboardFormatId::CF_DIB as u32, dib);
+
+        self.encode_format_list(vec![(ClipboardFormatId::CF_DIB as u32, None)])
+    }
+
+    /// The RGBA analogue of update_clipboard_image: accepts a decoded, top-down RGBA pixel
+    /// buffer (e.g. a screenshot copied in a GUI client) instead of an existing DIB/BMP blob,
+    /// packs it into a CF_DIB-compatible DIB via rgba_to_dib, and otherwise advertises/caches it
+    /// exactly like update_clipboard_image does.
+    pub fn update_clipboard_image_from_rgba(
+        &mut self,
+        width: u32,
+        height: u32,
+        rgba: Vec<u8>,
+    ) -> RdpResult<Vec<Vec<u8>>> {
+        self.update_clipboard_image(rgba_to_dib(width, height, &rgba)?)
+    }
+
+    /// update_clipboard_html is the CF_HTML analogue of update_clipboard: invoked from Go when the
+    /// user copies rich text/markup, it wraps the given UTF-8 HTML fragment in the CF_HTML banner
+    /// (see encode_cf_html) and advertises it to the RDP server under the CLIPBOARD_FORMAT_NAME_HTML
+    /// long format name, since (like the file list format) it has no fixed numeric ID.
+    pub fn update_clipboard_html(&mut self, fragment: Vec<u8>) -> RdpResult<Vec<Vec<u8>>> {
+        self.local_html = Some(encode_cf_html(&fragment));
+
+        self.encode_format_list(vec![(
+            LOCAL_HTML_FORMAT_ID,
+            Some(CLIPBOARD_FORMAT_NAME_HTML.to_string()),
+        )])
     }
 
     /// Handles the server capabilities message, which is the first message sent from the server
     /// to the client during the initialization sequence. Described in section 1.3.2.1.
-    fn handle_server_caps(&self, payload: &mut Payload) -> RdpResult<Vec<Vec<u8>>> {
+    fn handle_server_caps(&mut self, payload: &mut Payload) -> RdpResult<Vec<Vec<u8>>> {
         let caps = ClipboardCapabilitiesPDU::decode(payload)?;
         if let Some(general) = caps.general {
             // our capabilities are minimal, so we log the server
             // capabilities for debug purposes, but don't otherwise care
             // (the server will be forced into working with us)
             info!("RDP server clipboard capabilities: {:?}", general);
+
+            self.huge_file_support_enabled = general
+                .flags
+                .contains(ClipboardGeneralCapabilityFlags::CB_HUGE_FILE_SUPPORT_ENABLED);
+            self.long_format_names_supported = general
+                .flags
+                .contains(ClipboardGeneralCapabilityFlags::CB_USE_LONG_FORMAT_NAMES);
+            self.file_transfer_supported = general
+                .flags
+                .contains(ClipboardGeneralCapabilityFlags::CB_STREAM_FILECLIP_ENABLED);
         }
 
+        self.backend
+            .on_capabilities_negotiated(self.huge_file_support_enabled);
+
         // we don't send our capabilities here, they get sent as a response
         // to the monitor ready PDU below
         Ok(vec![])
@@ -300,22 +716,65 @@ impl Client {
                 general: Some(GeneralClipboardCapabilitySet {
                     version: CB_CAPS_VERSION_2,
                     flags: ClipboardGeneralCapabilityFlags::CB_USE_LONG_FORMAT_NAMES
-                        | ClipboardGeneralCapabilityFlags::CB_STREAM_FILECLIP_ENABLED,
+                        | ClipboardGeneralCapabilityFlags::CB_STREAM_FILECLIP_ENABLED
+                        | ClipboardGeneralCapabilityFlags::CB_CAN_LOCK_CLIPDATA
+                        | ClipboardGeneralCapabilityFlags::CB_HUGE_FILE_SUPPORT_ENABLED,
                 }),
             }
             .encode()?,
         )?;
-        result.extend(encode_message(
-            ClipboardPDUType::CB_FORMAT_LIST,
-            FormatListPDU::<LongFormatName> {
-                format_names: vec![LongFormatName::id(0)],
-            }
-            .encode()?,
-        )?);
+        result.extend(self.encode_format_list(vec![(0, None)])?);
 
         Ok(result)
     }
 
+    /// Encodes a CB_FORMAT_LIST advertising `entries` (format id, optional registered name),
+    /// using LongFormatName if the server's CB_CLIP_CAPS advertised CB_USE_LONG_FORMAT_NAMES
+    /// (the common case - everything from Windows XP SP1 on supports it) and falling back to the
+    /// fixed-width ShortFormatName wire format otherwise, per MS-RDPECLIP section 2.2.3.1.
+    fn encode_format_list(&self, entries: Vec<(u32, Option<String>)>) -> RdpResult<Vec<Vec<u8>>> {
+        if self.long_format_names_supported {
+            encode_message(
+                ClipboardPDUType::CB_FORMAT_LIST,
+                FormatListPDU::<LongFormatName> {
+                    format_names: entries
+                        .into_iter()
+                        .map(|(format_id, format_name)| LongFormatName {
+                            format_id,
+                            format_name,
+                        })
+                        .collect(),
+                }
+                .encode()?,
+            )
+        } else {
+            let mut format_names = Vec::with_capacity(entries.len());
+            for (format_id, format_name) in entries {
+                format_names.push(match format_name {
+                    Some(name) => ShortFormatName::from_str(format_id, &name)?,
+                    None => ShortFormatName::id(format_id),
+                });
+            }
+            encode_message(
+                ClipboardPDUType::CB_FORMAT_LIST,
+                FormatListPDU::<ShortFormatName> { format_names }.encode()?,
+            )
+        }
+    }
+
+    /// Mirrors Win32's GetPriorityClipboardFormat: given the format ids a peer's FormatListPDU
+    /// advertised and a priority-ordered slice of the formats we're able to handle (richest
+    /// first), returns the first one actually present, or None if the peer offered none of them.
+    fn priority_clipboard_format(
+        advertised_format_ids: &[u32],
+        priority: &[ClipboardFormatId],
+    ) -> Option<ClipboardFormatId> {
+        priority
+            .iter()
+            .copied()
+            .find(|want| advertised_format_ids.contains(&(*want as u32)))
+    }
+
     /// Handles the format list PDU, which is a notification from the server
     /// that some data was copied and can be requested at a later date.
     fn handle_format_list(
@@ -332,6 +791,17 @@ impl Client {
                 .collect::<Vec<u32>>()
         );
 
+        self.backend.on_format_list(
+            &list
+                .format_names
+                .iter()
+                .map(|n| ClipboardFormat {
+                    id: n.format_id,
+                    name: n.format_name.clone(),
+                })
+                .collect::<Vec<ClipboardFormat>>(),
+        );
+
         // if we want to support a variety of formats, we should clear
         // and re-initialize some local state (Clipboard Format ID Map)
         //
@@ -341,6 +811,10 @@ impl Client {
         // see section 3.1.1.1 for details
 
         let mut result = encode_message(ClipboardPDUType::CB_FORMAT_LIST_RESPONSE, vec![])?;
+        // CB_LOCK_CLIPDATA/CB_UNLOCK_CLIPDATA PDUs emitted while handling a file list format
+        // below, appended to `result` at the end (request_data's closure already holds `result`
+        // borrowed mutably for the rest of this function, so these can't be pushed in directly).
+        let mut file_transfer_messages = Vec::new();
 
         let mut request_data = |format_id: u32| -> RdpResult<()> {
             result.extend(encode_message(
@@ -351,15 +825,59 @@ impl Client {
             Ok(())
         };
 
+        let advertised_format_ids = list
+            .format_names
+            .iter()
+            .map(|n| n.format_id)
+            .collect::<Vec<u32>>();
+
+        // Several text formats may be advertised at once (e.g. a Windows app offering
+        // CF_UNICODETEXT, CF_TEXT and CF_OEMTEXT for the same copy); request only the richest one
+        // we can decode, in preference order, rather than round-tripping for every one of them.
+        let best_text_format = Self::priority_clipboard_format(
+            &advertised_format_ids,
+            &[
+                ClipboardFormatId::CF_UNICODETEXT,
+                ClipboardFormatId::CF_TEXT,
+                ClipboardFormatId::CF_OEMTEXT,
+            ],
+        );
+
+        if let Some(format_id) = best_text_format {
+            self.file_list_manager.is_expecting_file_list = false;
+            self.requested_text_format = Some(format_id);
+            self.requested_image_format = None;
+            self.expecting_html = false;
+            self.requested_format_id = Some(format_id as u32);
+            // request the data by imitating a paste event
+            request_data(format_id as u32)?;
+        }
+
+        // CF_DIBV5 carries richer color/alpha information than plain CF_DIB, so prefer it when
+        // both are offered; dib_to_bmp handles either header shape identically.
+        let best_image_format = Self::priority_clipboard_format(
+            &advertised_format_ids,
+            &[ClipboardFormatId::CF_DIBV5, ClipboardFormatId::CF_DIB],
+        );
+
+        if let Some(format_id) = best_image_format {
+            self.file_list_manager.is_expecting_file_list = false;
+            self.requested_text_format = None;
+            self.requested_image_format = Some(format_id);
+            self.expecting_html = false;
+            self.requested_format_id = Some(format_id as u32);
+            request_data(format_id as u32)?;
+        }
+
         for name in list.format_names {
             // TODO(isaiah): this match mess can probably be cleaned up somehow.
-            // Check for supported, standard clipboard formats.
             match FromPrimitive::from_u32(name.format_id) {
-                // TODO(zmb3): support CF_TEXT, CF_UNICODETEXT, ...
-                Some(ClipboardFormatId::CF_OEMTEXT) => {
-                    self.file_list_manager.is_expecting_file_list = false;
-                    // request the data by imitating a paste event
-                    request_data(name.format_id)?;
+                Some(ClipboardFormatId::CF_TEXT)
+                | Some(ClipboardFormatId::CF_OEMTEXT)
+                | Some(ClipboardFormatId::CF_UNICODETEXT)
+                | Some(ClipboardFormatId::CF_DIB)
+                | Some(ClipboardFormatId::CF_DIBV5) => {
+                    // Already requested (or skipped in favor of a richer format) above.
                 }
                 _ => match name.format_name {
                     // No supported, standard clipboard format was found,
@@ -367,10 +885,32 @@ impl Client {
                     Some(format_name) => match format_name.as_str() {
                         CLIPBOARD_FORMAT_NAME_FILE_LIST => {
                             self.file_list_manager.is_expecting_file_list = true;
+                            self.requested_text_format = None;
+                            self.requested_image_format = None;
+                            self.expecting_html = false;
+                            self.requested_format_id = Some(name.format_id);
+
+                            // A new file list paste is beginning. If a previous one is still
+                            // locked (e.g. it was abandoned before finish_file_transfer was
+                            // called), unlock it before locking a fresh clipDataId for this one.
+                            file_transfer_messages.extend(self.finish_file_transfer()?);
+                            file_transfer_messages.extend(self.lock_file_list_clip_data()?);
+
                             // Request the File List by sending a Format Data Request
                             // with the system-dependent format id that was sent to us
                             request_data(name.format_id)?;
                         }
+                        CLIPBOARD_FORMAT_NAME_HTML => {
+                            self.file_list_manager.is_expecting_file_list = false;
+                            self.requested_text_format = None;
+                            self.requested_image_format = None;
+                            self.expecting_html = true;
+                            self.requested_format_id = Some(name.format_id);
+
+                            // Request the CF_HTML blob by sending a Format Data Request with the
+                            // system-dependent format id that was sent to us.
+                            request_data(name.format_id)?;
+                        }
                         _ => {
                             info!("detected unsupported format name: {:?}", format_name);
                         }
@@ -382,6 +922,8 @@ impl Client {
             }
         }
 
+        result.extend(file_transfer_messages);
+
         Ok(result)
     }
 
@@ -403,10 +945,25 @@ impl Client {
     /// caused by the receipt of a CB_FORMAT_LIST PDU sent by us. IOW, it will only be sent
     /// if the latest cut/copy was done on the client side (and is therefore held by us in
     /// client.clipboard)
-    fn handle_format_data_request(&self, payload: &mut Payload) -> RdpResult<Vec<Vec<u8>>> {
+    fn handle_format_data_request(&mut self, payload: &mut Payload) -> RdpResult<Vec<Vec<u8>>> {
         let req = FormatDataRequestPDU::decode(payload)?;
-        let data = match self.clipboard.get(&req.format_id) {
-            Some(d) => d.clone(),
+
+        let data = if req.format_id == LOCAL_FILE_LIST_FORMAT_ID {
+            Some(self.encode_file_list()?)
+        } else if req.format_id == LOCAL_HTML_FORMAT_ID {
+            self.local_html.clone()
+        } else {
+            // Ask the backend first, so embedders doing delayed rendering only produce the bytes
+            // once the server actually pastes. Fall back to whatever update_clipboard eagerly
+            // cached, for backends (and the default, no-op one) that don't implement delayed
+            // rendering.
+            self.backend
+                .on_format_data_request(req.format_id)
+                .or_else(|| self.clipboard.get(&req.format_id).cloned())
+        };
+
+        let data = match data {
+            Some(d) => d,
             // TODO(zmb3): send empty FORMAT_DATA_RESPONSE with RESPONSE_FAIL flag set in header
             None => {
                 return Err(invalid_data_error(
@@ -439,10 +996,29 @@ impl Client {
             data_len, resp.data
         );
 
+        if self.requested_image_format.is_some() {
+            // Binary image data, not text - skip the null-terminator trim and UTF decoding below
+            // entirely, and report it through the dedicated image callback instead.
+            let bmp = dib_to_bmp(resp.data)?;
+            self.backend.on_image_data_response(bmp);
+            return Ok(vec![]);
+        }
+
+        if self.expecting_html {
+            // The CF_HTML blob is a banner plus a wrapped HTML document, not bare text - pull just
+            // the fragment back out and report it through the usual format-data callback, skipping
+            // the null-terminator trim and UTF-16 decoding below (CF_HTML is always UTF-8/ASCII).
+            let fragment = decode_cf_html(&resp.data)?;
+            if let Some(format_id) = self.requested_format_id {
+                self.backend.on_format_data_response(format_id, fragment);
+            }
+            return Ok(vec![]);
+        }
+
         let mut text_for_client_clipboard = if self.file_list_manager.is_expecting_file_list {
-            // TODO(isaiah): write a function that parses file list and returns the [first] file name,
-            // and updates Client.
             self.handle_file_list(resp)?
+        } else if self.requested_text_format == Some(ClipboardFormatId::CF_UNICODETEXT) {
+            decode_utf16le_to_utf8(&resp.data)
         } else {
             resp.data
         };
@@ -450,10 +1026,14 @@ impl Client {
         // trim the null-terminator, if it exists
         // (but don't worry about CRLF conversion, most non-Windows systems can handle CRLF well enough)
         if let Some(0x00) = text_for_client_clipboard.last() {
-            text_for_client_clipboard.truncate(data_len - 1);
+            let new_len = text_for_client_clipboard.len() - 1;
+            text_for_client_clipboard.truncate(new_len);
         }
 
-        (self.on_remote_copy)(text_for_client_clipboard);
+        if let Some(format_id) = self.requested_format_id {
+            self.backend
+                .on_format_data_response(format_id, text_for_client_clipboard);
+        }
 
         Ok(vec![])
     }
@@ -485,7 +1065,10 @@ impl Client {
             // An unsigned 32-bit integer that contains the least significant 4 bytes of the file size.
             let file_size_low = data.read_u32::<LittleEndian>()?;
             // (Why would RDP do this to us? Just make it a little endian u64 instead!)
-            let file_size = (u64::from(file_size_high) << 32) + u64::from(file_size_low);
+            let file_size = clamp_file_size(
+                (u64::from(file_size_high) << 32) + u64::from(file_size_low),
+                self.huge_file_support_enabled,
+            );
 
             // A null-terminated 260 character Unicode string that contains the name of the file.
             // read_unicode_to_string will return upon finding the null terminator, so won't
@@ -507,10 +1090,128 @@ impl Client {
 
         debug!("file list updated: {:?}", self.file_list_manager.file_list);
 
+        // Surface the first file's name to the backend, same as we do for plain text. Retrieving
+        // the rest of the list (and its contents) is driven separately through
+        // file_list_manager.file_list and request_file_contents.
+        Ok(self
+            .file_list_manager
+            .file_list
+            .first()
+            .map(|f| f.file_name.clone().into_bytes())
+            .unwrap_or_default())
+    }
+
+    /// Handles a CB_FILECONTENTS_REQUEST from the server for the size or a byte range of one of
+    /// the files we advertised via update_clipboard_files - the mirror of request_file_contents.
+    /// Sizes are answered directly out of local_file_list; byte ranges are read through
+    /// ClipboardBackend::read_file_contents.
+    fn handle_file_contents_request(&mut self, payload: &mut Payload) -> RdpResult<Vec<Vec<u8>>> {
+        let req = FileContentsRequestPDU::decode(payload)?;
+
+        let data = if req.dw_flags.contains(FileContentsFlags::FILECONTENTS_SIZE) {
+            let size = self
+                .local_file_list
+                .get(req.list_index as usize)
+                .map(|f| f.file_size)
+                .ok_or_else(|| {
+                    invalid_data_error(&format!(
+                        "no local file at list_index {}",
+                        req.list_index
+                    ))
+                })?;
+            clamp_file_size(size, self.huge_file_support_enabled)
+                .to_le_bytes()
+                .to_vec()
+        } else {
+            let position = (u64::from(req.n_position_high) << 32) | u64::from(req.n_position_low);
+            self.backend
+                .read_file_contents(req.list_index, position, req.cb_requested)
+                .ok_or_else(|| {
+                    invalid_data_error(&format!(
+                        "failed to read file contents for list_index {}",
+                        req.list_index
+                    ))
+                })?
+        };
+
+        encode_message(
+            ClipboardPDUType::CB_FILECONTENTS_RESPONSE,
+            FileContentsResponsePDU {
+                stream_id: req.stream_id,
+                data,
+            }
+            .encode()?,
+        )
+    }
+
+    /// Receives the reply to a CB_FILECONTENTS_REQUEST we previously sent (see
+    /// request_file_contents), matches it back to that request via stream_id, decodes it
+    /// according to whether the request asked for a size or a range, and hands the result to
+    /// on_file_contents_response.
+    fn handle_file_contents_response(
+        &mut self,
+        payload: &mut Payload,
+        length: u32,
+        ok: bool,
+    ) -> RdpResult<Vec<Vec<u8>>> {
+        let resp = FileContentsResponsePDU::decode(payload, length)?;
+
+        let Some(req) = self.pending_file_contents_requests.remove(&resp.stream_id) else {
+            warn!(
+                "received CB_FILECONTENTS_RESPONSE for unknown stream_id {}, ignoring",
+                resp.stream_id
+            );
+            return Ok(vec![]);
+        };
+
+        if !ok {
+            warn!(
+                "RDP server failed to process file contents request for list_index {}",
+                req.list_index
+            );
+            return Ok(vec![]);
+        }
+
+        let data = if req.dw_flags.contains(FileContentsFlags::FILECONTENTS_SIZE) {
+            let mut size_bytes = Cursor::new(resp.data);
+            let size = size_bytes.read_u64::<LittleEndian>()?;
+            FileContentsData::Size(clamp_file_size(size, self.huge_file_support_enabled))
+        } else {
+            FileContentsData::Range(resp.data)
+        };
+
+        self.backend.on_file_contents_response(req.list_index, data);
+
+        Ok(vec![])
+    }
+
+    /// Handles the server locking a clipDataId (2.2.5.5 CLIPRDR_LOCK_CLIPDATA), sent when the
+    /// server is about to issue CB_FILECONTENTS_REQUESTs against a file list we advertised.
+    fn handle_lock_clipdata(&mut self, payload: &mut Payload) -> RdpResult<Vec<Vec<u8>>> {
+        let pdu = LockClipDataPDU::decode(payload)?;
+        self.server_locked_clip_data_ids.insert(pdu.clip_data_id);
+        Ok(vec![])
+    }
+
+    /// Handles the server unlocking a clipDataId (2.2.5.6 CLIPRDR_UNLOCK_CLIPDATA).
+    fn handle_unlock_clipdata(&mut self, payload: &mut Payload) -> RdpResult<Vec<Vec<u8>>> {
+        let pdu = LockClipDataPDU::decode(payload)?;
+        self.server_locked_clip_data_ids.remove(&pdu.clip_data_id);
         Ok(vec![])
     }
 }
 
+/// The outcome of a previously-issued CB_FILECONTENTS_REQUEST, delivered once its matching
+/// CB_FILECONTENTS_RESPONSE arrives.
+#[derive(Debug)]
+pub enum FileContentsData {
+    /// Reply to a FILECONTENTS_SIZE request: the file's size in bytes.
+    Size(u64),
+    /// Reply to a FILECONTENTS_RANGE request: up to cb_requested bytes starting at the
+    /// requested offset.
+    Range(Vec<u8>),
+}
+
 bitflags! {
     struct ClipboardHeaderFlags: u16 {
         /// Indicates that the assocated request was processed successfully.
@@ -731,7 +1432,6 @@ struct ShortFormatName {
     format_name: [u8; 32],
 }
 
-#[allow(dead_code)]
 impl ShortFormatName {
     fn id(id: u32) -> Self {
         Self {
@@ -844,6 +1544,287 @@ fn read_unicode_to_string(data: &mut Payload) -> String {
     string
 }
 
+/// Converts lone LF line endings to CRLF, as required by the text clipboard formats (CF_TEXT,
+/// CF_OEMTEXT, CF_UNICODETEXT all specify CRLF line endings). Leaves existing CRLF pairs alone.
+fn convert_lf_to_crlf(data: &[u8]) -> Vec<u8> {
+    const CR: u8 = 13;
+    const LF: u8 = 10;
+
+    let mut converted = Vec::with_capacity(data.len());
+    for i in 0..data.len() {
+        match data[i] {
+            LF => {
+                // convert LF to CRLF, so long as the previous character
+                // wasn't CR (in which case there's no conversion necessary)
+                if i == 0 || (data[i - 1] != CR) {
+                    converted.push(CR);
+                }
+                converted.push(LF);
+            }
+            _ => converted.push(data[i]),
+        }
+    }
+    converted
+}
+
+/// Decodes a null-terminated UTF-16LE string (the wire encoding of CF_UNICODETEXT) into UTF-8
+/// bytes, dropping the trailing wide-char null terminator.
+fn decode_utf16le_to_utf8(data: &[u8]) -> Vec<u8> {
+    std::char::decode_utf16(
+        data.chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&c| c != 0x0000),
+    )
+    .map(|c| c.unwrap_or(std::char::REPLACEMENT_CHARACTER))
+    .collect::<String>()
+    .into_bytes()
+}
+
+/// Caps `size` at what fits in 32 bits unless `huge_file_support_enabled` (both peers negotiated
+/// CB_HUGE_FILE_SUPPORT_ENABLED in the CB_CLIP_CAPS exchange), in which case it's passed through
+/// unchanged. Applied to every file size crossing the wire - file descriptors and
+/// FILECONTENTS_SIZE responses, in both directions - so a peer that didn't negotiate the
+/// capability never sees (or is asked to believe) a size it can't represent.
+fn clamp_file_size(size: u64, huge_file_support_enabled: bool) -> u64 {
+    if huge_file_support_enabled {
+        size
+    } else {
+        size.min(u32::MAX as u64)
+    }
+}
+
+/// Builds the CF_HTML banner: a short ASCII header of byte offsets into the overall CF_HTML blob
+/// (Version, then StartHTML/EndHTML/StartFragment/EndFragment, each a fixed-width 10-digit
+/// zero-padded decimal so the header's length is the same whether it holds real offsets or
+/// placeholder zeros), as specified by the CF_HTML clipboard format.
+fn format_cf_html_header(
+    start_html: u32,
+    end_html: u32,
+    start_fragment: u32,
+    end_fragment: u32,
+) -> String {
+    format!(
+        "Version:0.9\r\nStartHTML:{:010}\r\nEndHTML:{:010}\r\nStartFragment:{:010}\r\nEndFragment:{:010}\r\n",
+        start_html, end_html, start_fragment, end_fragment
+    )
+}
+
+/// Wraps an HTML fragment in the CF_HTML banner plus a minimal HTML document, producing the blob
+/// update_clipboard_html advertises under CLIPBOARD_FORMAT_NAME_HTML. The mirror of
+/// decode_cf_html.
+fn encode_cf_html(fragment: &[u8]) -> Vec<u8> {
+    const FRAGMENT_PREFIX: &[u8] = b"<html>\r\n<body>\r\n<!--StartFragment-->";
+    const FRAGMENT_SUFFIX: &[u8] = b"<!--EndFragment-->\r\n</body>\r\n</html>\r\n";
+
+    // The header's own length doesn't depend on the offsets it holds (they're fixed-width), so
+    // render it once with placeholder zeros purely to measure it.
+    let header_len = format_cf_html_header(0, 0, 0, 0).len() as u32;
+
+    let start_html = header_len;
+    let start_fragment = start_html + FRAGMENT_PREFIX.len() as u32;
+    let end_fragment = start_fragment + fragment.len() as u32;
+    let end_html = end_fragment + FRAGMENT_SUFFIX.len() as u32;
+
+    let mut out =
+        format_cf_html_header(start_html, end_html, start_fragment, end_fragment).into_bytes();
+    out.extend_from_slice(FRAGMENT_PREFIX);
+    out.extend_from_slice(fragment);
+    out.extend_from_slice(FRAGMENT_SUFFIX);
+    out
+}
+
+/// Reads the StartFragment/EndFragment offsets out of a CF_HTML blob's banner and slices out just
+/// the fragment between them, discarding the surrounding document and the banner itself. The
+/// mirror of encode_cf_html.
+fn decode_cf_html(data: &[u8]) -> RdpResult<Vec<u8>> {
+    let header = String::from_utf8_lossy(data);
+
+    let start_fragment = parse_cf_html_offset(&header, "StartFragment:")?;
+    let end_fragment = parse_cf_html_offset(&header, "EndFragment:")?;
+
+    if start_fragment > end_fragment || end_fragment as usize > data.len() {
+        return Err(invalid_data_error(
+            "CF_HTML StartFragment/EndFragment offsets out of range",
+        ));
+    }
+
+    Ok(data[start_fragment as usize..end_fragment as usize].to_vec())
+}
+
+/// Finds `field` (e.g. "StartFragment:") among the CRLF-delimited lines of a CF_HTML banner and
+/// parses the decimal offset following it.
+fn parse_cf_html_offset(header: &str, field: &str) -> RdpResult<u32> {
+    let value = header
+        .lines()
+        .find_map(|line| line.strip_prefix(field))
+        .ok_or_else(|| invalid_data_error(&format!("CF_HTML data missing {} header", field)))?;
+
+    value.trim().parse::<u32>().map_err(|_| {
+        invalid_data_error(&format!("CF_HTML {} header has an invalid offset", field))
+    })
+}
+
+/// Prepends a synthesized 14-byte BITMAPFILEHEADER to a raw DIB (a BITMAPINFOHEADER, or one of its
+/// newer BITMAPV4HEADER/BITMAPV5HEADER variants, followed by an optional color table and the
+/// pixel data) as received in a CF_DIB/CF_DIBV5 CB_FORMAT_DATA_RESPONSE, producing a standalone
+/// .bmp-compatible image. The mirror of update_clipboard_image's header stripping.
+fn dib_to_bmp(dib: Vec<u8>) -> RdpResult<Vec<u8>> {
+    const BITMAPFILEHEADER_LEN: u32 = 14;
+    const BI_BITFIELDS: u32 = 3;
+
+    if dib.len() < 40 {
+        return Err(invalid_data_error(
+            "DIB data too short to contain a BITMAPINFOHEADER",
+        ));
+    }
+
+    let bi_size = u32::from_le_bytes(dib[0..4].try_into().unwrap());
+    let bi_bit_count = u16::from_le_bytes(dib[14..16].try_into().unwrap());
+    let bi_compression = u32::from_le_bytes(dib[16..20].try_into().unwrap());
+    let bi_clr_used = u32::from_le_bytes(dib[32..36].try_into().unwrap());
+
+    // Indexed color depths (<= 8bpp) are followed by a color table of bi_clr_used entries (or
+    // 2^bi_bit_count, if bi_clr_used wasn't set), 4 bytes (RGBQUAD) each.
+    let color_table_len = if bi_bit_count <= 8 {
+        let colors = if bi_clr_used != 0 {
+            bi_clr_used
+        } else {
+            1u32 << bi_bit_count
+        };
+        colors * 4
+    } else {
+        0
+    };
+
+    // A legacy 40-byte BITMAPINFOHEADER using BI_BITFIELDS compression is followed by three
+    // DWORD color masks before the pixel data; the newer BITMAPV4HEADER/BITMAPV5HEADER formats
+    // fold the equivalent masks into the header itself (via a larger bi_size), so nothing extra
+    // applies there.
+    let bitfields_len = if bi_size == 40 && bi_compression == BI_BITFIELDS {
+        12
+    } else {
+        0
+    };
+
+    let pixel_data_offset = BITMAPFILEHEADER_LEN + bi_size + color_table_len + bitfields_len;
+
+    let mut bmp = Vec::with_capacity(BITMAPFILEHEADER_LEN as usize + dib.len());
+    bmp.write_all(b"BM")?;
+    bmp.write_u32::<LittleEndian>(BITMAPFILEHEADER_LEN + dib.len() as u32)?; // total file size
+    bmp.write_u16::<LittleEndian>(0)?; // reserved1
+    bmp.write_u16::<LittleEndian>(0)?; // reserved2
+    bmp.write_u32::<LittleEndian>(pixel_data_offset)?;
+    bmp.extend(dib);
+
+    Ok(bmp)
+}
+
+/// Builds a packed DIB (a 40-byte BITMAPINFOHEADER followed by bottom-up, 4-byte-row-aligned BGR
+/// pixel data) from a top-down RGBA buffer, the mirror of dib_to_rgba. Used by
+/// update_clipboard_image_from_rgba to turn a decoded screenshot/paste buffer back into the wire
+/// format CF_DIB expects; alpha is dropped since plain CF_DIB (unlike CF_DIBV5) has no channel for
+/// it.
+fn rgba_to_dib(width: u32, height: u32, rgba: &[u8]) -> RdpResult<Vec<u8>> {
+    const BI_RGB: u32 = 0;
+    const BYTES_PER_PIXEL: usize = 4;
+
+    if rgba.len() != width as usize * height as usize * BYTES_PER_PIXEL {
+        return Err(invalid_data_error(
+            "RGBA buffer length doesn't match width * height * 4",
+        ));
+    }
+
+    let row_len = width as usize * 3;
+    let padded_row_len = (row_len + 3) & !3;
+    let pixel_data_len = padded_row_len * height as usize;
+
+    let mut dib = Vec::with_capacity(40 + pixel_data_len);
+    dib.write_u32::<LittleEndian>(40)?; // biSize
+    dib.write_i32::<LittleEndian>(width as i32)?; // biWidth
+    dib.write_i32::<LittleEndian>(height as i32)?; // biHeight (positive: bottom-up)
+    dib.write_u16::<LittleEndian>(1)?; // biPlanes
+    dib.write_u16::<LittleEndian>(24)?; // biBitCount
+    dib.write_u32::<LittleEndian>(BI_RGB)?; // biCompression
+    dib.write_u32::<LittleEndian>(pixel_data_len as u32)?; // biSizeImage
+    dib.write_i32::<LittleEndian>(0)?; // biXPelsPerMeter
+    dib.write_i32::<LittleEndian>(0)?; // biYPelsPerMeter
+    dib.write_u32::<LittleEndian>(0)?; // biClrUsed
+    dib.write_u32::<LittleEndian>(0)?; // biClrImportant
+
+    // Bottom-up: the last RGBA row is written first, and each row is padded to a 4-byte boundary.
+    for y in (0..height as usize).rev() {
+        let row_start = y * width as usize * BYTES_PER_PIXEL;
+        for x in 0..width as usize {
+            let p = row_start + x * BYTES_PER_PIXEL;
+            dib.write_all(&[rgba[p + 2], rgba[p + 1], rgba[p]])?; // BGR
+        }
+        dib.resize(dib.len() + (padded_row_len - row_len), 0);
+    }
+
+    Ok(dib)
+}
+
+/// Decodes the pixel data of a packed DIB (as produced by rgba_to_dib, or received from a server
+/// in a CF_DIB CB_FORMAT_DATA_RESPONSE) into a top-down RGBA buffer, the mirror of rgba_to_dib.
+/// Only the common uncompressed 24bpp/32bpp cases are supported - real screenshots and GUI
+/// clipboard images are never indexed or 16bpp in practice - anything else is an error.
+fn dib_to_rgba(dib: &[u8]) -> RdpResult<(u32, u32, Vec<u8>)> {
+    const BI_RGB: u32 = 0;
+
+    if dib.len() < 40 {
+        return Err(invalid_data_error(
+            "DIB data too short to contain a BITMAPINFOHEADER",
+        ));
+    }
+
+    let width = i32::from_le_bytes(dib[4..8].try_into().unwrap());
+    let height_raw = i32::from_le_bytes(dib[8..12].try_into().unwrap());
+    let bi_bit_count = u16::from_le_bytes(dib[14..16].try_into().unwrap());
+    let bi_compression = u32::from_le_bytes(dib[16..20].try_into().unwrap());
+
+    if bi_compression != BI_RGB || (bi_bit_count != 24 && bi_bit_count != 32) {
+        return Err(invalid_data_error(
+            "dib_to_rgba only supports uncompressed 24bpp/32bpp DIBs",
+        ));
+    }
+    if width <= 0 {
+        return Err(invalid_data_error("DIB has a non-positive width"));
+    }
+
+    let width = width as usize;
+    // Positive biHeight means the rows are stored bottom-up (the common case); negative means
+    // they're already top-down.
+    let bottom_up = height_raw > 0;
+    let height = height_raw.unsigned_abs() as usize;
+
+    let bytes_per_pixel = (bi_bit_count / 8) as usize;
+    let row_len = width * bytes_per_pixel;
+    let padded_row_len = (row_len + 3) & !3;
+    let pixel_data = &dib[40..];
+
+    if pixel_data.len() < padded_row_len * height {
+        return Err(invalid_data_error(
+            "DIB pixel data shorter than its header claims",
+        ));
+    }
+
+    let mut rgba = vec![0u8; width * height * 4];
+    for y in 0..height {
+        let src_row = if bottom_up { height - 1 - y } else { y };
+        let src = &pixel_data[src_row * padded_row_len..src_row * padded_row_len + row_len];
+        for x in 0..width {
+            let sp = x * bytes_per_pixel;
+            let dp = (y * width + x) * 4;
+            rgba[dp] = src[sp + 2]; // R
+            rgba[dp + 1] = src[sp + 1]; // G
+            rgba[dp + 2] = src[sp]; // B
+            rgba[dp + 3] = if bytes_per_pixel == 4 { src[sp + 3] } else { 0xFF };
+        }
+    }
+
+    Ok((width as u32, height as u32, rgba))
+}
+
 /// All data copied to a system clipboard has to conform to a format
 /// specification. These formats are identified by unique numeric IDs,
 /// which are OS-specific.
@@ -873,6 +1854,7 @@ enum ClipboardFormatId {
     CF_ENHMETAFILE = 14, // handle to an enhanced metafile
     CF_HDROP = 15,       // identifies a list of files
     CF_LOCALE = 16,      // locale identifier, so application can lookup charset when pasting
+    CF_DIBV5 = 17,       // BITMAPV5HEADER followed by the color space and bitmap bits
 
     CF_PRIVATEFIRST = 0x0200, // range for private clipboard formats
     CF_PRIVATELAST = 0x02FF, // https://docs.microsoft.com/en-us/windows/win32/dataxchg/clipboard-formats#private-clipboard-formats
@@ -888,6 +1870,23 @@ enum ClipboardFormatId {
 /// See section 1.3.1.2.
 const CLIPBOARD_FORMAT_NAME_FILE_LIST: &str = "FileGroupDescriptorW";
 
+/// The format id we advertise CLIPBOARD_FORMAT_NAME_FILE_LIST under in update_clipboard_files.
+/// Its value is arbitrary - it only has meaning paired with that format name, per the long format
+/// name registration mechanism in section 1.3.1.2 - chosen well above the standard CF_* range so
+/// it can never collide with one.
+const LOCAL_FILE_LIST_FORMAT_ID: u32 = 49096;
+
+/// Like the File List format, "HTML Format" (the clipboard format browsers and office apps use
+/// for rich-text/markup) has no fixed numeric ID and is instead registered by this name.
+///
+/// See section 1.3.1.2.
+const CLIPBOARD_FORMAT_NAME_HTML: &str = "HTML Format";
+
+/// The format id we advertise CLIPBOARD_FORMAT_NAME_HTML under in update_clipboard_html. Chosen
+/// the same way as LOCAL_FILE_LIST_FORMAT_ID - arbitrary, meaningful only paired with the format
+/// name, and clear of the standard CF_* range.
+const LOCAL_HTML_FORMAT_ID: u32 = 49097;
+
 /// Sent as a reply to the format list PDU - used to indicate whether
 /// the format list PDU was processed succesfully.
 #[derive(Debug)]
@@ -944,29 +1943,153 @@ impl FormatDataResponsePDU {
     }
 }
 
-/// encode_message encodes a message by wrapping it in the appropriate
-/// channel header. If the payload exceeds the maximum size, the message
-/// is split into multiple messages.
-fn encode_message(msg_type: ClipboardPDUType, payload: Vec<u8>) -> RdpResult<Vec<Vec<u8>>> {
-    let msg_flags = match msg_type {
-        // the spec requires 0 for these messages
-        ClipboardPDUType::CB_CLIP_CAPS => ClipboardHeaderFlags::from_bits_truncate(0),
-        ClipboardPDUType::CB_TEMP_DIRECTORY => ClipboardHeaderFlags::from_bits_truncate(0),
-        ClipboardPDUType::CB_LOCK_CLIPDATA => ClipboardHeaderFlags::from_bits_truncate(0),
-        ClipboardPDUType::CB_UNLOCK_CLIPDATA => ClipboardHeaderFlags::from_bits_truncate(0),
-        ClipboardPDUType::CB_FORMAT_DATA_REQUEST => ClipboardHeaderFlags::from_bits_truncate(0),
-
-        // assume success for now
-        ClipboardPDUType::CB_FORMAT_DATA_RESPONSE => ClipboardHeaderFlags::CB_RESPONSE_OK,
-        ClipboardPDUType::CB_FORMAT_LIST_RESPONSE => ClipboardHeaderFlags::CB_RESPONSE_OK,
+bitflags! {
+    /// see 2.2.5.3.1 File Contents Request PDU (CLIPRDR_FILECONTENTS_REQUEST)
+    pub struct FileContentsFlags: u32 {
+        /// A request for the size of the file identified by the list_index field.
+        const FILECONTENTS_SIZE = 0x00000001;
+        /// A request for the contents of the file identified by the list_index field.
+        const FILECONTENTS_RANGE = 0x00000002;
+    }
+}
 
-        // we don't advertise support for file transfers, so the server should never send this,
-        // but if it does, ensure the response indicates a failure
-        ClipboardPDUType::CB_FILECONTENTS_RESPONSE => ClipboardHeaderFlags::CB_RESPONSE_FAIL,
+/// Sent by the recipient of a format data response containing a file list, in order to request
+/// either the size or a byte range of one of the listed files.
+///
+/// See section 2.2.5.3.1: CLIPRDR_FILECONTENTS_REQUEST
+#[derive(Debug)]
+struct FileContentsRequestPDU {
+    /// Uniquely identifies this request so the matching CB_FILECONTENTS_RESPONSE can be
+    /// attributed back to it; echoed back verbatim by the server.
+    stream_id: u32,
+    /// Index, into the file list most recently parsed by handle_file_list, of the file this
+    /// request concerns.
+    list_index: u32,
+    dw_flags: FileContentsFlags,
+    /// Low/high 32 bits of the 64-bit file offset a FILECONTENTS_RANGE request starts at; unused
+    /// for FILECONTENTS_SIZE.
+    n_position_low: u32,
+    n_position_high: u32,
+    /// Maximum number of bytes requested; only meaningful for FILECONTENTS_RANGE.
+    cb_requested: u32,
+    /// Identifies the locked clip data this file belongs to (see CB_LOCK_CLIPDATA); 0 when
+    /// locking isn't in use.
+    clip_data_id: u32,
+}
 
-        _ => ClipboardHeaderFlags::from_bits_truncate(0),
-    };
-    let mut inner = ClipboardPDUHeader::new(msg_type, msg_flags, payload.len() as u32).encode()?;
+impl FileContentsRequestPDU {
+    /// Enforces the invariant from 2.2.5.3.1: a FILECONTENTS_SIZE request must set cbRequested to
+    /// 8 (the size of the u64 it's asking for) and zero out the position fields, which otherwise
+    /// only have meaning for FILECONTENTS_RANGE.
+    fn validate(&self) -> RdpResult<()> {
+        if self.dw_flags.contains(FileContentsFlags::FILECONTENTS_SIZE)
+            && (self.cb_requested != 8 || self.n_position_low != 0 || self.n_position_high != 0)
+        {
+            return Err(invalid_data_error(
+                "a FILECONTENTS_SIZE request must set cbRequested=8 and zeroed positions",
+            ));
+        }
+        Ok(())
+    }
+
+    fn encode(&self) -> RdpResult<Vec<u8>> {
+        self.validate()?;
+
+        let mut w = Vec::with_capacity(28);
+        w.write_u32::<LittleEndian>(self.stream_id)?;
+        w.write_u32::<LittleEndian>(self.list_index)?;
+        w.write_u32::<LittleEndian>(self.dw_flags.bits())?;
+        w.write_u32::<LittleEndian>(self.n_position_low)?;
+        w.write_u32::<LittleEndian>(self.n_position_high)?;
+        w.write_u32::<LittleEndian>(self.cb_requested)?;
+        w.write_u32::<LittleEndian>(self.clip_data_id)?;
+        Ok(w)
+    }
+
+    fn decode(payload: &mut Payload) -> RdpResult<Self> {
+        let pdu = Self {
+            stream_id: payload.read_u32::<LittleEndian>()?,
+            list_index: payload.read_u32::<LittleEndian>()?,
+            dw_flags: FileContentsFlags::from_bits_truncate(payload.read_u32::<LittleEndian>()?),
+            n_position_low: payload.read_u32::<LittleEndian>()?,
+            n_position_high: payload.read_u32::<LittleEndian>()?,
+            cb_requested: payload.read_u32::<LittleEndian>()?,
+            clip_data_id: payload.read_u32::<LittleEndian>()?,
+        };
+        pdu.validate()?;
+        Ok(pdu)
+    }
+}
+
+/// Sent as a reply to the file contents request PDU: either the 8-byte little-endian file size
+/// (FILECONTENTS_SIZE) or up to cb_requested bytes of file data (FILECONTENTS_RANGE).
+///
+/// See section 2.2.5.4: CLIPRDR_FILECONTENTS_RESPONSE
+#[derive(Debug)]
+struct FileContentsResponsePDU {
+    stream_id: u32,
+    data: Vec<u8>,
+}
+
+impl FileContentsResponsePDU {
+    fn encode(&self) -> RdpResult<Vec<u8>> {
+        let mut w = Vec::with_capacity(4 + self.data.len());
+        w.write_u32::<LittleEndian>(self.stream_id)?;
+        w.extend_from_slice(&self.data);
+        Ok(w)
+    }
+
+    fn decode(payload: &mut Payload, length: u32) -> RdpResult<Self> {
+        let stream_id = payload.read_u32::<LittleEndian>()?;
+        let mut data = vec![0u8; (length as usize).saturating_sub(4)];
+        payload.read_exact(&mut data)?;
+        Ok(Self { stream_id, data })
+    }
+}
+
+/// Shared wire shape of CB_LOCK_CLIPDATA and CB_UNLOCK_CLIPDATA: just the clipDataId being
+/// (un)locked.
+///
+/// See sections 2.2.5.5 (CLIPRDR_LOCK_CLIPDATA) and 2.2.5.6 (CLIPRDR_UNLOCK_CLIPDATA).
+#[derive(Debug)]
+struct LockClipDataPDU {
+    clip_data_id: u32,
+}
+
+impl LockClipDataPDU {
+    fn encode(&self) -> RdpResult<Vec<u8>> {
+        let mut w = Vec::with_capacity(4);
+        w.write_u32::<LittleEndian>(self.clip_data_id)?;
+        Ok(w)
+    }
+
+    fn decode(payload: &mut Payload) -> RdpResult<Self> {
+        Ok(Self {
+            clip_data_id: payload.read_u32::<LittleEndian>()?,
+        })
+    }
+}
+
+/// encode_message encodes a message by wrapping it in the appropriate
+/// channel header. If the payload exceeds the maximum size, the message
+/// is split into multiple messages.
+fn encode_message(msg_type: ClipboardPDUType, payload: Vec<u8>) -> RdpResult<Vec<Vec<u8>>> {
+    let msg_flags = match msg_type {
+        // the spec requires 0 for these messages
+        ClipboardPDUType::CB_CLIP_CAPS => ClipboardHeaderFlags::from_bits_truncate(0),
+        ClipboardPDUType::CB_TEMP_DIRECTORY => ClipboardHeaderFlags::from_bits_truncate(0),
+        ClipboardPDUType::CB_LOCK_CLIPDATA => ClipboardHeaderFlags::from_bits_truncate(0),
+        ClipboardPDUType::CB_UNLOCK_CLIPDATA => ClipboardHeaderFlags::from_bits_truncate(0),
+        ClipboardPDUType::CB_FORMAT_DATA_REQUEST => ClipboardHeaderFlags::from_bits_truncate(0),
+
+        // assume success for now
+        ClipboardPDUType::CB_FORMAT_DATA_RESPONSE => ClipboardHeaderFlags::CB_RESPONSE_OK,
+        ClipboardPDUType::CB_FORMAT_LIST_RESPONSE => ClipboardHeaderFlags::CB_RESPONSE_OK,
+        ClipboardPDUType::CB_FILECONTENTS_RESPONSE => ClipboardHeaderFlags::CB_RESPONSE_OK,
+
+        _ => ClipboardHeaderFlags::from_bits_truncate(0),
+    };
+    let mut inner = ClipboardPDUHeader::new(msg_type, msg_flags, payload.len() as u32).encode()?;
     inner.extend(payload);
     let total_len = inner.len() as u32;
 
@@ -1013,7 +2136,64 @@ mod tests {
 
     use super::*;
     use std::io::Cursor;
-    use std::sync::mpsc::channel;
+    use std::sync::mpsc::{channel, Sender};
+
+    /// A `ClipboardBackend` for exercising `Client` in isolation: format-data requests are
+    /// answered out of a plain map (no delayed rendering needed for these tests), and the other
+    /// events are reported over channels so tests can assert on them.
+    #[derive(Default)]
+    struct TestBackend {
+        format_data: HashMap<u32, Vec<u8>>,
+        on_capabilities_negotiated: Option<Sender<bool>>,
+        on_format_data_response: Option<Sender<(u32, Vec<u8>)>>,
+        on_file_contents_response: Option<Sender<(u32, FileContentsData)>>,
+        on_image_data_response: Option<Sender<Vec<u8>>>,
+        file_contents: HashMap<u32, Vec<u8>>,
+    }
+
+    impl ClipboardBackend for TestBackend {
+        fn on_capabilities_negotiated(&mut self, huge_file_support_enabled: bool) {
+            if let Some(tx) = &self.on_capabilities_negotiated {
+                tx.send(huge_file_support_enabled).unwrap();
+            }
+        }
+
+        fn on_format_list(&mut self, _formats: &[ClipboardFormat]) {}
+
+        fn on_format_data_request(&mut self, format_id: u32) -> Option<Vec<u8>> {
+            self.format_data.get(&format_id).cloned()
+        }
+
+        fn on_format_data_response(&mut self, format_id: u32, data: Vec<u8>) {
+            if let Some(tx) = &self.on_format_data_response {
+                tx.send((format_id, data)).unwrap();
+            }
+        }
+
+        fn on_file_contents_response(&mut self, list_index: u32, data: FileContentsData) {
+            if let Some(tx) = &self.on_file_contents_response {
+                tx.send((list_index, data)).unwrap();
+            }
+        }
+
+        fn read_file_contents(
+            &mut self,
+            list_index: u32,
+            position: u64,
+            length: u32,
+        ) -> Option<Vec<u8>> {
+            let data = self.file_contents.get(&list_index)?;
+            let start = (position as usize).min(data.len());
+            let end = start.saturating_add(length as usize).min(data.len());
+            Some(data[start..end].to_vec())
+        }
+
+        fn on_image_data_response(&mut self, data: Vec<u8>) {
+            if let Some(tx) = &self.on_image_data_response {
+                tx.send(data).unwrap();
+            }
+        }
+    }
 
     #[test]
     fn encode_format_list_short() {
@@ -1168,6 +2348,38 @@ mod tests {
         );
     }
 
+    #[test]
+    fn file_contents_request_and_response_pdus_round_trip_through_encode_decode() {
+        let req = FileContentsRequestPDU {
+            stream_id: 7,
+            list_index: 2,
+            dw_flags: FileContentsFlags::FILECONTENTS_RANGE,
+            n_position_low: 100,
+            n_position_high: 0,
+            cb_requested: 50,
+            clip_data_id: 9,
+        };
+        let encoded = req.encode().unwrap();
+        let decoded = FileContentsRequestPDU::decode(&mut Cursor::new(encoded)).unwrap();
+        assert_eq!(decoded.stream_id, req.stream_id);
+        assert_eq!(decoded.list_index, req.list_index);
+        assert_eq!(decoded.dw_flags, req.dw_flags);
+        assert_eq!(decoded.n_position_low, req.n_position_low);
+        assert_eq!(decoded.n_position_high, req.n_position_high);
+        assert_eq!(decoded.cb_requested, req.cb_requested);
+        assert_eq!(decoded.clip_data_id, req.clip_data_id);
+
+        let resp = FileContentsResponsePDU {
+            stream_id: 7,
+            data: b"hello world".to_vec(),
+        };
+        let encoded = resp.encode().unwrap();
+        let length = encoded.len() as u32;
+        let decoded = FileContentsResponsePDU::decode(&mut Cursor::new(encoded), length).unwrap();
+        assert_eq!(decoded.stream_id, resp.stream_id);
+        assert_eq!(decoded.data, resp.data);
+    }
+
     #[test]
     fn responds_to_monitor_ready() {
         let c: Client = Default::default();
@@ -1187,6 +2399,9 @@ mod tests {
         assert_eq!(
             general.flags,
             ClipboardGeneralCapabilityFlags::CB_USE_LONG_FORMAT_NAMES
+                | ClipboardGeneralCapabilityFlags::CB_STREAM_FILECLIP_ENABLED
+                | ClipboardGeneralCapabilityFlags::CB_CAN_LOCK_CLIPDATA
+                | ClipboardGeneralCapabilityFlags::CB_HUGE_FILE_SUPPORT_ENABLED
         );
 
         // Second response - the format list PDU:
@@ -1204,6 +2419,144 @@ mod tests {
         assert_eq!(format_list.format_names[0].format_name, None);
     }
 
+    #[test]
+    fn handle_server_caps_negotiates_huge_file_support() {
+        let mut c: Client = Default::default();
+        assert!(!c.huge_file_support_enabled());
+
+        let msg = ClipboardCapabilitiesPDU {
+            general: Some(GeneralClipboardCapabilitySet {
+                version: CB_CAPS_VERSION_2,
+                flags: ClipboardGeneralCapabilityFlags::CB_HUGE_FILE_SUPPORT_ENABLED,
+            }),
+        }
+        .encode()
+        .unwrap();
+        c.handle_server_caps(&mut Cursor::new(msg)).unwrap();
+        assert!(c.huge_file_support_enabled());
+
+        // A later exchange that doesn't re-advertise the flag turns it back off.
+        let msg = ClipboardCapabilitiesPDU {
+            general: Some(GeneralClipboardCapabilitySet {
+                version: CB_CAPS_VERSION_2,
+                flags: ClipboardGeneralCapabilityFlags::from_bits_truncate(0),
+            }),
+        }
+        .encode()
+        .unwrap();
+        c.handle_server_caps(&mut Cursor::new(msg)).unwrap();
+        assert!(!c.huge_file_support_enabled());
+    }
+
+    #[test]
+    fn handle_server_caps_downgrades_outgoing_format_list_to_short_names() {
+        let mut c: Client = Default::default();
+
+        // Server only advertises short format names support.
+        let msg = ClipboardCapabilitiesPDU {
+            general: Some(GeneralClipboardCapabilitySet {
+                version: CB_CAPS_VERSION_2,
+                flags: ClipboardGeneralCapabilityFlags::from_bits_truncate(0),
+            }),
+        }
+        .encode()
+        .unwrap();
+        c.handle_server_caps(&mut Cursor::new(msg)).unwrap();
+
+        let encoded = c.update_clipboard(b"abc".to_vec()).unwrap();
+        let mut payload = Cursor::new(encoded[0].clone());
+        let _pdu_header = vchan::ChannelPDUHeader::decode(&mut payload).unwrap();
+        let header = ClipboardPDUHeader::decode(&mut payload).unwrap();
+        assert_eq!(header.msg_type, ClipboardPDUType::CB_FORMAT_LIST);
+
+        let format_list =
+            FormatListPDU::<ShortFormatName>::decode(&mut payload, header.data_len).unwrap();
+        assert_eq!(format_list.format_names.len(), 3);
+        assert_eq!(
+            format_list.format_names[0].format_id,
+            ClipboardFormatId::CF_UNICODETEXT as u32
+        );
+    }
+
+    #[test]
+    fn update_clipboard_files_skips_advertisement_when_file_transfer_unsupported() {
+        let mut c: Client = Default::default();
+
+        // Server's CB_CLIP_CAPS doesn't include CB_STREAM_FILECLIP_ENABLED.
+        let msg = ClipboardCapabilitiesPDU {
+            general: Some(GeneralClipboardCapabilitySet {
+                version: CB_CAPS_VERSION_2,
+                flags: ClipboardGeneralCapabilityFlags::from_bits_truncate(0),
+            }),
+        }
+        .encode()
+        .unwrap();
+        c.handle_server_caps(&mut Cursor::new(msg)).unwrap();
+
+        let messages = c.update_clipboard_files(vec![]).unwrap();
+        assert!(messages.is_empty());
+    }
+
+    #[test]
+    fn handle_server_caps_notifies_backend_of_negotiated_capabilities() {
+        let (send, recv) = channel();
+        let mut c = Client::new(Box::new(TestBackend {
+            on_capabilities_negotiated: Some(send),
+            ..Default::default()
+        }));
+
+        let msg = ClipboardCapabilitiesPDU {
+            general: Some(GeneralClipboardCapabilitySet {
+                version: CB_CAPS_VERSION_2,
+                flags: ClipboardGeneralCapabilityFlags::CB_HUGE_FILE_SUPPORT_ENABLED,
+            }),
+        }
+        .encode()
+        .unwrap();
+        c.handle_server_caps(&mut Cursor::new(msg)).unwrap();
+
+        assert!(recv.try_recv().unwrap());
+    }
+
+    #[test]
+    fn file_sizes_are_clamped_to_32_bits_unless_huge_file_support_is_negotiated() {
+        assert_eq!(clamp_file_size(u64::from(u32::MAX) + 100, false), u64::from(u32::MAX));
+        assert_eq!(
+            clamp_file_size(u64::from(u32::MAX) + 100, true),
+            u64::from(u32::MAX) + 100
+        );
+
+        let mut c: Client = Default::default();
+        c.update_clipboard_files(vec![FileMetadata {
+            name: String::from("huge.bin"),
+            size: u64::from(u32::MAX) + 100,
+            last_write_time: 0,
+            is_directory: false,
+        }])
+        .unwrap();
+
+        // Without the capability negotiated, the packed file list reports the clamped size.
+        let encoded = c.encode_file_list().unwrap();
+        let mut payload = Cursor::new(encoded);
+        let _count = payload.read_u32::<LittleEndian>().unwrap();
+        payload.seek(SeekFrom::Current(64)).unwrap(); // flags, reserved1, attributes, reserved2, last_write_time
+        let size_high = payload.read_u32::<LittleEndian>().unwrap();
+        let size_low = payload.read_u32::<LittleEndian>().unwrap();
+        let reported_size = (u64::from(size_high) << 32) + u64::from(size_low);
+        assert_eq!(reported_size, u64::from(u32::MAX));
+
+        // Once negotiated, the full 64-bit size is reported.
+        c.huge_file_support_enabled = true;
+        let encoded = c.encode_file_list().unwrap();
+        let mut payload = Cursor::new(encoded);
+        let _count = payload.read_u32::<LittleEndian>().unwrap();
+        payload.seek(SeekFrom::Current(64)).unwrap();
+        let size_high = payload.read_u32::<LittleEndian>().unwrap();
+        let size_low = payload.read_u32::<LittleEndian>().unwrap();
+        let reported_size = (u64::from(size_high) << 32) + u64::from(size_low);
+        assert_eq!(reported_size, u64::from(u32::MAX) + 100);
+    }
+
     #[test]
     fn encodes_large_format_data_response() {
         let mut data = Vec::new();
@@ -1263,9 +2616,11 @@ mod tests {
     fn invokes_callback_with_clipboard_data() {
         let (send, recv) = channel();
 
-        let mut c = Client::new(Box::new(move |vec| {
-            send.send(vec).unwrap();
+        let mut c = Client::new(Box::new(TestBackend {
+            on_format_data_response: Some(send),
+            ..Default::default()
         }));
+        c.requested_format_id = Some(ClipboardFormatId::CF_OEMTEXT as u32);
 
         let data_resp = FormatDataResponsePDU {
             data: String::from("abc\0").into_bytes(),
@@ -1279,7 +2634,8 @@ mod tests {
             .unwrap();
 
         // ensure that the null terminator was trimmed
-        let received = recv.try_recv().unwrap();
+        let (format_id, received) = recv.try_recv().unwrap();
+        assert_eq!(format_id, ClipboardFormatId::CF_OEMTEXT as u32);
         assert_eq!(received, String::from("abc").into_bytes());
     }
 
@@ -1291,20 +2647,28 @@ mod tests {
             .unwrap();
         let bytes = messages[0].clone();
 
-        // verify that it returns a properly encoded format list PDU
+        // verify that it returns a properly encoded format list PDU, advertising CF_UNICODETEXT
+        // ahead of the legacy text formats
         let mut payload = Cursor::new(bytes);
         let _pdu_header = vchan::ChannelPDUHeader::decode(&mut payload).unwrap();
         let header = ClipboardPDUHeader::decode(&mut payload).unwrap();
         let format_list =
             FormatListPDU::<LongFormatName>::decode(&mut payload, header.data_len as u32).unwrap();
         assert_eq!(ClipboardPDUType::CB_FORMAT_LIST, header.msg_type);
-        assert_eq!(1, format_list.format_names.len());
         assert_eq!(
-            ClipboardFormatId::CF_OEMTEXT as u32,
-            format_list.format_names[0].format_id
+            vec![
+                ClipboardFormatId::CF_UNICODETEXT as u32,
+                ClipboardFormatId::CF_TEXT as u32,
+                ClipboardFormatId::CF_OEMTEXT as u32,
+            ],
+            format_list
+                .format_names
+                .iter()
+                .map(|n| n.format_id)
+                .collect::<Vec<u32>>()
         );
 
-        // verify that the clipboard data is now cached
+        // verify that the clipboard data is now cached under every advertised format
         // (with a null-terminating character)
         assert_eq!(
             String::from("abc\0").into_bytes(),
@@ -1312,6 +2676,190 @@ mod tests {
                 .get(&(ClipboardFormatId::CF_OEMTEXT as u32))
                 .unwrap()
         );
+        assert_eq!(
+            String::from("abc\0").into_bytes(),
+            *c.clipboard.get(&(ClipboardFormatId::CF_TEXT as u32)).unwrap()
+        );
+        assert_eq!(
+            "abc\0".encode_utf16().flat_map(|u| u.to_le_bytes()).collect::<Vec<u8>>(),
+            *c.clipboard
+                .get(&(ClipboardFormatId::CF_UNICODETEXT as u32))
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn request_file_contents_then_handle_size_response() {
+        let (send, recv) = channel();
+        let mut c = Client::new(Box::new(TestBackend {
+            on_file_contents_response: Some(send),
+            ..Default::default()
+        }));
+
+        let messages = c
+            .request_file_contents(3, FileContentsFlags::FILECONTENTS_SIZE, 0, 0, 8)
+            .unwrap();
+        assert_eq!(messages.len(), 1);
+
+        let mut payload = Cursor::new(messages[0].clone());
+        let _pdu_header = vchan::ChannelPDUHeader::decode(&mut payload).unwrap();
+        let header = ClipboardPDUHeader::decode(&mut payload).unwrap();
+        assert_eq!(header.msg_type, ClipboardPDUType::CB_FILECONTENTS_REQUEST);
+        let req = FileContentsRequestPDU::decode(&mut payload).unwrap();
+        assert_eq!(req.list_index, 3);
+        assert_eq!(req.dw_flags, FileContentsFlags::FILECONTENTS_SIZE);
+
+        let resp = FileContentsResponsePDU {
+            stream_id: req.stream_id,
+            data: 42u64.to_le_bytes().to_vec(),
+        }
+        .encode()
+        .unwrap();
+        let len = resp.len() as u32;
+        c.handle_file_contents_response(&mut Cursor::new(resp), len, true)
+            .unwrap();
+
+        let (idx, data) = recv.try_recv().unwrap();
+        assert_eq!(idx, 3);
+        assert!(matches!(data, FileContentsData::Size(42)));
+    }
+
+    #[test]
+    fn file_contents_size_request_rejects_bad_cb_requested_and_positions() {
+        let mut c: Client = Default::default();
+
+        // cb_requested must be 8 for a FILECONTENTS_SIZE request.
+        assert!(c
+            .request_file_contents(0, FileContentsFlags::FILECONTENTS_SIZE, 0, 0, 1)
+            .is_err());
+
+        // positions must be zeroed for a FILECONTENTS_SIZE request.
+        assert!(c
+            .request_file_contents(0, FileContentsFlags::FILECONTENTS_SIZE, 1, 0, 8)
+            .is_err());
+        assert!(c
+            .request_file_contents(0, FileContentsFlags::FILECONTENTS_SIZE, 0, 1, 8)
+            .is_err());
+
+        // FILECONTENTS_RANGE isn't subject to the same constraint.
+        assert!(c
+            .request_file_contents(0, FileContentsFlags::FILECONTENTS_RANGE, 1, 0, 100)
+            .is_ok());
+    }
+
+    #[test]
+    fn format_list_with_file_list_locks_clip_data_and_threads_it_through_requests() {
+        let mut c: Client = Default::default();
+
+        let encoded = FormatListPDU {
+            format_names: vec![LongFormatName {
+                format_id: 1234,
+                format_name: Some(CLIPBOARD_FORMAT_NAME_FILE_LIST.to_string()),
+            }],
+        }
+        .encode()
+        .unwrap();
+        let length = encoded.len() as u32;
+
+        let messages = c
+            .handle_format_list(&mut Cursor::new(encoded), length)
+            .unwrap();
+
+        // CB_FORMAT_LIST_RESPONSE, CB_FORMAT_DATA_REQUEST, CB_LOCK_CLIPDATA
+        assert_eq!(messages.len(), 3);
+        let mut payload = Cursor::new(messages[2].clone());
+        let _pdu_header = vchan::ChannelPDUHeader::decode(&mut payload).unwrap();
+        let header = ClipboardPDUHeader::decode(&mut payload).unwrap();
+        assert_eq!(header.msg_type, ClipboardPDUType::CB_LOCK_CLIPDATA);
+        let lock = LockClipDataPDU::decode(&mut payload).unwrap();
+        assert_eq!(lock.clip_data_id, 0);
+
+        // request_file_contents should thread the locked clip_data_id through.
+        let messages = c
+            .request_file_contents(0, FileContentsFlags::FILECONTENTS_SIZE, 0, 0, 8)
+            .unwrap();
+        let mut payload = Cursor::new(messages[0].clone());
+        let _pdu_header = vchan::ChannelPDUHeader::decode(&mut payload).unwrap();
+        let _header = ClipboardPDUHeader::decode(&mut payload).unwrap();
+        let req = FileContentsRequestPDU::decode(&mut payload).unwrap();
+        assert_eq!(req.clip_data_id, 0);
+
+        // Finishing the transfer unlocks it and clears the locked id.
+        let messages = c.finish_file_transfer().unwrap();
+        assert_eq!(messages.len(), 1);
+        let mut payload = Cursor::new(messages[0].clone());
+        let _pdu_header = vchan::ChannelPDUHeader::decode(&mut payload).unwrap();
+        let header = ClipboardPDUHeader::decode(&mut payload).unwrap();
+        assert_eq!(header.msg_type, ClipboardPDUType::CB_UNLOCK_CLIPDATA);
+        let unlock = LockClipDataPDU::decode(&mut payload).unwrap();
+        assert_eq!(unlock.clip_data_id, 0);
+
+        // Calling it again with nothing locked is a no-op.
+        assert_eq!(c.finish_file_transfer().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn tracks_server_locked_clip_data_ids() {
+        let mut c: Client = Default::default();
+
+        let lock_msg = LockClipDataPDU { clip_data_id: 7 }.encode().unwrap();
+        c.handle_lock_clipdata(&mut Cursor::new(lock_msg)).unwrap();
+        assert!(c.server_locked_clip_data_ids.contains(&7));
+
+        let unlock_msg = LockClipDataPDU { clip_data_id: 7 }.encode().unwrap();
+        c.handle_unlock_clipdata(&mut Cursor::new(unlock_msg))
+            .unwrap();
+        assert!(!c.server_locked_clip_data_ids.contains(&7));
+    }
+
+    #[test]
+    fn locked_clip_data_id_stays_pinned_across_multiple_file_contents_requests() {
+        let mut c: Client = Default::default();
+
+        let encoded = FormatListPDU {
+            format_names: vec![LongFormatName {
+                format_id: 1234,
+                format_name: Some(CLIPBOARD_FORMAT_NAME_FILE_LIST.to_string()),
+            }],
+        }
+        .encode()
+        .unwrap();
+        let length = encoded.len() as u32;
+        c.handle_format_list(&mut Cursor::new(encoded), length)
+            .unwrap();
+
+        // Several files in the same file list transfer should all be requested under the one
+        // clipDataId locked for it, not a fresh one each time.
+        let clip_data_id_for = |c: &mut Client, list_index: u32| {
+            let messages = c
+                .request_file_contents(list_index, FileContentsFlags::FILECONTENTS_SIZE, 0, 0, 8)
+                .unwrap();
+            let mut payload = Cursor::new(messages[0].clone());
+            let _pdu_header = vchan::ChannelPDUHeader::decode(&mut payload).unwrap();
+            let _header = ClipboardPDUHeader::decode(&mut payload).unwrap();
+            FileContentsRequestPDU::decode(&mut payload).unwrap().clip_data_id
+        };
+
+        let first = clip_data_id_for(&mut c, 0);
+        let second = clip_data_id_for(&mut c, 1);
+        assert_eq!(first, second);
+
+        // A new file list transfer (handle_format_list unlocks the old id and locks a fresh one)
+        // gets a different clipDataId.
+        let encoded = FormatListPDU {
+            format_names: vec![LongFormatName {
+                format_id: 1234,
+                format_name: Some(CLIPBOARD_FORMAT_NAME_FILE_LIST.to_string()),
+            }],
+        }
+        .encode()
+        .unwrap();
+        let length = encoded.len() as u32;
+        c.handle_format_list(&mut Cursor::new(encoded), length)
+            .unwrap();
+
+        let third = clip_data_id_for(&mut c, 0);
+        assert_ne!(first, third);
     }
 
     #[test]
@@ -1333,6 +2881,554 @@ mod tests {
                 "testing {}",
                 input
             );
+            assert_eq!(
+                String::from(*expected).into_bytes(),
+                *c.clipboard.get(&(ClipboardFormatId::CF_TEXT as u32)).unwrap(),
+                "testing {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn handle_format_list_prefers_unicodetext_and_decodes_it() {
+        let (send, recv) = channel();
+        let mut c = Client::new(Box::new(TestBackend {
+            on_format_data_response: Some(send),
+            ..Default::default()
+        }));
+
+        // server advertises both CF_OEMTEXT and CF_UNICODETEXT for the same copy
+        let format_list = FormatListPDU {
+            format_names: vec![
+                LongFormatName::id(ClipboardFormatId::CF_OEMTEXT as u32),
+                LongFormatName::id(ClipboardFormatId::CF_UNICODETEXT as u32),
+            ],
         }
+        .encode()
+        .unwrap();
+        let len = format_list.len() as u32;
+        c.handle_format_list(&mut Cursor::new(format_list), len)
+            .unwrap();
+        assert_eq!(
+            c.requested_text_format,
+            Some(ClipboardFormatId::CF_UNICODETEXT)
+        );
+
+        // simulate the server replying with UTF-16LE data
+        let mut data: Vec<u8> = "abc".encode_utf16().flat_map(|u| u.to_le_bytes()).collect();
+        data.extend_from_slice(&[0x00, 0x00]);
+        let data_resp = FormatDataResponsePDU { data }.encode().unwrap();
+        let len = data_resp.len() as u32;
+        c.handle_format_data_response(&mut Cursor::new(data_resp), len)
+            .unwrap();
+
+        let (format_id, received) = recv.try_recv().unwrap();
+        assert_eq!(format_id, ClipboardFormatId::CF_UNICODETEXT as u32);
+        assert_eq!(received, String::from("abc").into_bytes());
+    }
+
+    #[test]
+    fn handle_format_list_selects_richest_format_by_priority() {
+        let format_list = FormatListPDU {
+            format_names: vec![
+                LongFormatName::id(ClipboardFormatId::CF_OEMTEXT as u32),
+                LongFormatName::id(ClipboardFormatId::CF_TEXT as u32),
+                LongFormatName::id(ClipboardFormatId::CF_UNICODETEXT as u32),
+            ],
+        }
+        .encode()
+        .unwrap();
+        let len = format_list.len() as u32;
+
+        let mut c: Client = Default::default();
+        c.handle_format_list(&mut Cursor::new(format_list), len)
+            .unwrap();
+
+        assert_eq!(
+            c.requested_text_format,
+            Some(ClipboardFormatId::CF_UNICODETEXT)
+        );
+    }
+
+    #[test]
+    fn priority_clipboard_format_picks_first_supported_entry_in_priority_order() {
+        let priority = [
+            ClipboardFormatId::CF_UNICODETEXT,
+            ClipboardFormatId::CF_TEXT,
+            ClipboardFormatId::CF_OEMTEXT,
+        ];
+
+        assert_eq!(
+            Client::priority_clipboard_format(
+                &[ClipboardFormatId::CF_OEMTEXT as u32, ClipboardFormatId::CF_TEXT as u32],
+                &priority,
+            ),
+            Some(ClipboardFormatId::CF_TEXT)
+        );
+
+        assert_eq!(
+            Client::priority_clipboard_format(&[ClipboardFormatId::CF_DIB as u32], &priority),
+            None
+        );
+    }
+
+    #[test]
+    fn update_clipboard_round_trips_non_ascii_text_through_cf_unicodetext() {
+        // "café 🎉" exercises both a multi-byte-but-single-UTF-16-unit codepoint (é) and a
+        // codepoint that needs a UTF-16 surrogate pair (🎉), neither of which survive a naive
+        // byte-for-byte copy the way pure ASCII would.
+        let input = "café 🎉";
+
+        let mut c: Client = Default::default();
+        c.update_clipboard(String::from(input).into_bytes()).unwrap();
+
+        let encoded = c
+            .clipboard
+            .get(&(ClipboardFormatId::CF_UNICODETEXT as u32))
+            .unwrap();
+        assert_eq!(decode_utf16le_to_utf8(encoded), input.as_bytes().to_vec());
+
+        // CF_TEXT/CF_OEMTEXT have no codepage conversion here, so non-ASCII bytes just pass
+        // through as-is (lossy on a real single-byte codepage, but that's the server's problem).
+        assert_eq!(
+            *c.clipboard.get(&(ClipboardFormatId::CF_TEXT as u32)).unwrap(),
+            format!("{}\0", input).into_bytes()
+        );
+    }
+
+    #[test]
+    fn update_clipboard_suppresses_repeat_announcements_of_identical_content() {
+        let mut c: Client = Default::default();
+
+        let first = c.update_clipboard(b"hello".to_vec()).unwrap();
+        assert!(!first.is_empty());
+
+        let second = c.update_clipboard(b"hello".to_vec()).unwrap();
+        assert!(second.is_empty());
+
+        // Different content breaks the suppression again.
+        let third = c.update_clipboard(b"world".to_vec()).unwrap();
+        assert!(!third.is_empty());
+    }
+
+    #[test]
+    fn update_clipboard_text_and_image_suppression_are_tracked_independently() {
+        let mut c: Client = Default::default();
+
+        assert!(!c.update_clipboard(b"hello".to_vec()).unwrap().is_empty());
+        assert!(c.update_clipboard(b"hello".to_vec()).unwrap().is_empty());
+
+        // Copying an image doesn't get suppressed by the still-fresh text hash, and vice versa.
+        let dib = vec![9u8; 10];
+        assert!(!c
+            .update_clipboard_image(dib.clone())
+            .unwrap()
+            .is_empty());
+        assert!(c.update_clipboard_image(dib).unwrap().is_empty());
+        assert!(c.update_clipboard(b"hello".to_vec()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn handle_format_data_request_consults_backend_before_eager_cache() {
+        let mut c = Client::new(Box::new(TestBackend {
+            format_data: HashMap::from([(
+                ClipboardFormatId::CF_TEXT as u32,
+                String::from("from backend\0").into_bytes(),
+            )]),
+            ..Default::default()
+        }));
+
+        let req = FormatDataRequestPDU::for_id(ClipboardFormatId::CF_TEXT as u32);
+        let responses = c
+            .handle_format_data_request(&mut Cursor::new(req.encode().unwrap()))
+            .unwrap();
+
+        let mut payload = Cursor::new(responses[0].clone());
+        let _pdu_header = vchan::ChannelPDUHeader::decode(&mut payload).unwrap();
+        let header = ClipboardPDUHeader::decode(&mut payload).unwrap();
+        let resp = FormatDataResponsePDU::decode(&mut payload, header.data_len).unwrap();
+        assert_eq!(resp.data, String::from("from backend\0").into_bytes());
+    }
+
+    #[test]
+    fn update_clipboard_files_advertises_file_list_format() {
+        let mut c: Client = Default::default();
+
+        let messages = c
+            .update_clipboard_files(vec![FileMetadata {
+                name: String::from("foo.txt"),
+                size: 42,
+                last_write_time: 0,
+                is_directory: false,
+            }])
+            .unwrap();
+        assert_eq!(messages.len(), 1);
+
+        let mut payload = Cursor::new(messages[0].clone());
+        let _pdu_header = vchan::ChannelPDUHeader::decode(&mut payload).unwrap();
+        let header = ClipboardPDUHeader::decode(&mut payload).unwrap();
+        assert_eq!(header.msg_type, ClipboardPDUType::CB_FORMAT_LIST);
+
+        let format_list =
+            FormatListPDU::<LongFormatName>::decode(&mut payload, header.data_len).unwrap();
+        assert_eq!(format_list.format_names.len(), 1);
+        assert_eq!(
+            format_list.format_names[0].format_id,
+            LOCAL_FILE_LIST_FORMAT_ID
+        );
+        assert_eq!(
+            format_list.format_names[0].format_name,
+            Some(String::from(CLIPBOARD_FORMAT_NAME_FILE_LIST))
+        );
+    }
+
+    #[test]
+    fn handle_format_data_request_serves_packed_file_list() {
+        let mut c: Client = Default::default();
+        c.update_clipboard_files(vec![
+            FileMetadata {
+                name: String::from("foo.txt"),
+                size: 42,
+                last_write_time: 0,
+                is_directory: false,
+            },
+            FileMetadata {
+                name: String::from("bar"),
+                size: 0,
+                last_write_time: 0,
+                is_directory: true,
+            },
+        ])
+        .unwrap();
+
+        let req = FormatDataRequestPDU::for_id(LOCAL_FILE_LIST_FORMAT_ID);
+        let responses = c
+            .handle_format_data_request(&mut Cursor::new(req.encode().unwrap()))
+            .unwrap();
+
+        let mut payload = Cursor::new(responses[0].clone());
+        let _pdu_header = vchan::ChannelPDUHeader::decode(&mut payload).unwrap();
+        let header = ClipboardPDUHeader::decode(&mut payload).unwrap();
+        let resp = FormatDataResponsePDU::decode(&mut payload, header.data_len).unwrap();
+
+        let mut data = Cursor::new(resp.data);
+        assert_eq!(data.read_u32::<LittleEndian>().unwrap(), 2);
+        // Each packed FileDescriptor is exactly 592 bytes.
+        assert_eq!(
+            data.get_ref().len() as u64 - data.position(),
+            2 * 592
+        );
+    }
+
+    #[test]
+    fn handle_file_contents_request_answers_size_and_range() {
+        let mut c = Client::new(Box::new(TestBackend {
+            file_contents: HashMap::from([(0, b"hello world".to_vec())]),
+            ..Default::default()
+        }));
+        c.update_clipboard_files(vec![FileMetadata {
+            name: String::from("foo.txt"),
+            size: 11,
+            last_write_time: 0,
+            is_directory: false,
+        }])
+        .unwrap();
+
+        // FILECONTENTS_SIZE is answered straight out of local_file_list.
+        let req = FileContentsRequestPDU {
+            stream_id: 1,
+            list_index: 0,
+            dw_flags: FileContentsFlags::FILECONTENTS_SIZE,
+            n_position_low: 0,
+            n_position_high: 0,
+            cb_requested: 8,
+            clip_data_id: 0,
+        };
+        let responses = c
+            .handle_file_contents_request(&mut Cursor::new(req.encode().unwrap()))
+            .unwrap();
+        let mut payload = Cursor::new(responses[0].clone());
+        let _pdu_header = vchan::ChannelPDUHeader::decode(&mut payload).unwrap();
+        let header = ClipboardPDUHeader::decode(&mut payload).unwrap();
+        assert_eq!(header.msg_flags, ClipboardHeaderFlags::CB_RESPONSE_OK);
+        let resp = FileContentsResponsePDU::decode(&mut payload, header.data_len).unwrap();
+        assert_eq!(
+            resp.data,
+            11u64.to_le_bytes().to_vec()
+        );
+
+        // FILECONTENTS_RANGE is read through the backend.
+        let req = FileContentsRequestPDU {
+            stream_id: 2,
+            list_index: 0,
+            dw_flags: FileContentsFlags::FILECONTENTS_RANGE,
+            n_position_low: 6,
+            n_position_high: 0,
+            cb_requested: 5,
+            clip_data_id: 0,
+        };
+        let responses = c
+            .handle_file_contents_request(&mut Cursor::new(req.encode().unwrap()))
+            .unwrap();
+        let mut payload = Cursor::new(responses[0].clone());
+        let _pdu_header = vchan::ChannelPDUHeader::decode(&mut payload).unwrap();
+        let header = ClipboardPDUHeader::decode(&mut payload).unwrap();
+        let resp = FileContentsResponsePDU::decode(&mut payload, header.data_len).unwrap();
+        assert_eq!(resp.data, b"world".to_vec());
+    }
+
+    #[test]
+    fn dib_to_bmp_prepends_bitmapfileheader() {
+        let mut dib = vec![0u8; 40]; // a bare BITMAPINFOHEADER, no color table
+        dib[0..4].copy_from_slice(&40u32.to_le_bytes()); // biSize
+        dib[14..16].copy_from_slice(&24u16.to_le_bytes()); // biBitCount
+        dib.extend_from_slice(&[1, 2, 3, 4, 5, 6]); // pixel data
+
+        let bmp = dib_to_bmp(dib.clone()).unwrap();
+
+        assert_eq!(&bmp[0..2], b"BM");
+        assert_eq!(
+            u32::from_le_bytes(bmp[2..6].try_into().unwrap()),
+            14 + dib.len() as u32
+        );
+        assert_eq!(u32::from_le_bytes(bmp[10..14].try_into().unwrap()), 14 + 40);
+        assert_eq!(&bmp[14..], dib.as_slice());
+    }
+
+    #[test]
+    fn dib_to_bmp_accounts_for_palettized_color_table() {
+        // An 8bpp DIB with a 4-entry (16-byte) color table, biClrUsed explicitly set.
+        let mut dib = vec![0u8; 40];
+        dib[0..4].copy_from_slice(&40u32.to_le_bytes()); // biSize
+        dib[14..16].copy_from_slice(&8u16.to_le_bytes()); // biBitCount
+        dib[32..36].copy_from_slice(&4u32.to_le_bytes()); // biClrUsed
+        dib.extend_from_slice(&[0, 0, 0, 0, 255, 255, 255, 0, 1, 2, 3, 0, 4, 5, 6, 0]); // color table
+        dib.extend_from_slice(&[7, 8, 9]); // pixel data
+
+        let bmp = dib_to_bmp(dib.clone()).unwrap();
+
+        // pixel-data offset = 14 (BITMAPFILEHEADER) + 40 (biSize) + 16 (4 RGBQUAD entries)
+        assert_eq!(u32::from_le_bytes(bmp[10..14].try_into().unwrap()), 14 + 40 + 16);
+        assert_eq!(&bmp[14..], dib.as_slice());
+    }
+
+    #[test]
+    fn dib_to_bmp_defaults_color_table_size_from_bit_count_when_bi_clr_used_is_zero() {
+        // A 1bpp DIB with biClrUsed left at 0 implies the full 2-entry (8-byte) palette.
+        let mut dib = vec![0u8; 40];
+        dib[0..4].copy_from_slice(&40u32.to_le_bytes()); // biSize
+        dib[14..16].copy_from_slice(&1u16.to_le_bytes()); // biBitCount
+        dib.extend_from_slice(&[0, 0, 0, 0, 255, 255, 255, 0]); // color table (2 entries)
+        dib.extend_from_slice(&[0xFF]); // pixel data
+
+        let bmp = dib_to_bmp(dib.clone()).unwrap();
+
+        assert_eq!(u32::from_le_bytes(bmp[10..14].try_into().unwrap()), 14 + 40 + 8);
+        assert_eq!(&bmp[14..], dib.as_slice());
+    }
+
+    #[test]
+    fn update_clipboard_image_strips_bmp_header_and_advertises_cf_dib() {
+        let mut c: Client = Default::default();
+        let dib = vec![9u8; 10];
+        let mut bmp_file = b"BM".to_vec();
+        bmp_file.extend_from_slice(&[0u8; 12]); // rest of BITMAPFILEHEADER, contents don't matter
+        bmp_file.extend_from_slice(&dib);
+
+        let messages = c.update_clipboard_image(bmp_file).unwrap();
+
+        assert_eq!(
+            c.clipboard.get(&(ClipboardFormatId::CF_DIB as u32)),
+            Some(&dib)
+        );
+
+        let mut payload = Cursor::new(messages[0].clone());
+        let _pdu_header = vchan::ChannelPDUHeader::decode(&mut payload).unwrap();
+        let header = ClipboardPDUHeader::decode(&mut payload).unwrap();
+        let format_list =
+            FormatListPDU::<LongFormatName>::decode(&mut payload, header.data_len).unwrap();
+        assert_eq!(format_list.format_names.len(), 1);
+        assert_eq!(
+            format_list.format_names[0].format_id,
+            ClipboardFormatId::CF_DIB as u32
+        );
+    }
+
+    #[test]
+    fn rgba_to_dib_round_trips_through_dib_to_rgba() {
+        // A 2x2 bitmap, one distinct color per pixel, top-down row order (the order callers hand
+        // us pixel data in).
+        #[rustfmt::skip]
+        let rgba: Vec<u8> = vec![
+            255, 0, 0, 255,    0, 255, 0, 255,
+            0, 0, 255, 255,    255, 255, 0, 255,
+        ];
+
+        let dib = rgba_to_dib(2, 2, &rgba).unwrap();
+        let (width, height, decoded) = dib_to_rgba(&dib).unwrap();
+
+        assert_eq!((width, height), (2, 2));
+        assert_eq!(decoded, rgba);
+    }
+
+    #[test]
+    fn dib_to_rgba_rejects_indexed_and_compressed_formats() {
+        let mut dib = vec![0u8; 40];
+        dib[0..4].copy_from_slice(&40u32.to_le_bytes()); // biSize
+        dib[4..8].copy_from_slice(&1i32.to_le_bytes()); // biWidth
+        dib[8..12].copy_from_slice(&1i32.to_le_bytes()); // biHeight
+        dib[14..16].copy_from_slice(&8u16.to_le_bytes()); // biBitCount (indexed)
+
+        assert!(dib_to_rgba(&dib).is_err());
+    }
+
+    #[test]
+    fn update_clipboard_image_from_rgba_packs_and_advertises_a_dib() {
+        let rgba = vec![10, 20, 30, 255, 40, 50, 60, 255];
+
+        let mut c: Client = Default::default();
+        let messages = c.update_clipboard_image_from_rgba(2, 1, rgba.clone()).unwrap();
+
+        let (width, height, decoded) = dib_to_rgba(
+            c.clipboard
+                .get(&(ClipboardFormatId::CF_DIB as u32))
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!((width, height), (2, 1));
+        assert_eq!(decoded, rgba);
+
+        let mut payload = Cursor::new(messages[0].clone());
+        let _pdu_header = vchan::ChannelPDUHeader::decode(&mut payload).unwrap();
+        let header = ClipboardPDUHeader::decode(&mut payload).unwrap();
+        let format_list =
+            FormatListPDU::<LongFormatName>::decode(&mut payload, header.data_len).unwrap();
+        assert_eq!(
+            format_list.format_names[0].format_id,
+            ClipboardFormatId::CF_DIB as u32
+        );
+    }
+
+    #[test]
+    fn handle_format_list_requests_dib_and_dispatches_to_image_callback() {
+        let (send, recv) = channel();
+        let mut c = Client::new(Box::new(TestBackend {
+            on_image_data_response: Some(send),
+            ..Default::default()
+        }));
+
+        let list = FormatListPDU::<LongFormatName> {
+            format_names: vec![LongFormatName::id(ClipboardFormatId::CF_DIB as u32)],
+        };
+        let encoded = list.encode().unwrap();
+        let len = encoded.len() as u32;
+        let responses = c
+            .handle_format_list(&mut Cursor::new(encoded), len)
+            .unwrap();
+
+        // format list response + a single format data request for CF_DIB
+        assert_eq!(responses.len(), 2);
+        assert_eq!(c.requested_image_format, Some(ClipboardFormatId::CF_DIB));
+
+        let mut dib = vec![0u8; 40];
+        dib[0..4].copy_from_slice(&40u32.to_le_bytes());
+        dib[14..16].copy_from_slice(&24u16.to_le_bytes());
+        dib.extend_from_slice(&[5, 6, 7]);
+
+        let data_resp = FormatDataResponsePDU { data: dib.clone() }
+            .encode()
+            .unwrap();
+        let len = data_resp.len() as u32;
+        c.handle_format_data_response(&mut Cursor::new(data_resp), len)
+            .unwrap();
+
+        let bmp = recv.try_recv().unwrap();
+        assert_eq!(&bmp[0..2], b"BM");
+        assert_eq!(&bmp[14..], dib.as_slice());
+    }
+
+    #[test]
+    fn encode_cf_html_round_trips_through_decode_cf_html() {
+        let fragment = b"<b>hello</b>".to_vec();
+        let blob = encode_cf_html(&fragment);
+
+        assert_eq!(
+            String::from_utf8_lossy(&blob).lines().next().unwrap(),
+            "Version:0.9"
+        );
+        assert_eq!(decode_cf_html(&blob).unwrap(), fragment);
+    }
+
+    #[test]
+    fn decode_cf_html_rejects_missing_and_out_of_range_offsets() {
+        assert!(decode_cf_html(b"Version:0.9\r\n").is_err());
+
+        let bad_header = b"Version:0.9\r\nStartHTML:0000000000\r\nEndHTML:0000000010\r\nStartFragment:0000000005\r\nEndFragment:0000009999\r\n".to_vec();
+        assert!(decode_cf_html(&bad_header).is_err());
+    }
+
+    #[test]
+    fn update_clipboard_html_advertises_under_local_html_format_id() {
+        let mut c: Client = Default::default();
+
+        let messages = c.update_clipboard_html(b"<i>hi</i>".to_vec()).unwrap();
+
+        let mut payload = Cursor::new(messages[0].clone());
+        let _pdu_header = vchan::ChannelPDUHeader::decode(&mut payload).unwrap();
+        let header = ClipboardPDUHeader::decode(&mut payload).unwrap();
+        let format_list =
+            FormatListPDU::<LongFormatName>::decode(&mut payload, header.data_len).unwrap();
+        assert_eq!(format_list.format_names.len(), 1);
+        assert_eq!(format_list.format_names[0].format_id, LOCAL_HTML_FORMAT_ID);
+        assert_eq!(
+            format_list.format_names[0].format_name,
+            Some(CLIPBOARD_FORMAT_NAME_HTML.to_string())
+        );
+
+        let req = FormatDataRequestPDU::for_id(LOCAL_HTML_FORMAT_ID);
+        let responses = c
+            .handle_format_data_request(&mut Cursor::new(req.encode().unwrap()))
+            .unwrap();
+        let mut payload = Cursor::new(responses[0].clone());
+        let _pdu_header = vchan::ChannelPDUHeader::decode(&mut payload).unwrap();
+        let header = ClipboardPDUHeader::decode(&mut payload).unwrap();
+        let resp = FormatDataResponsePDU::decode(&mut payload, header.data_len).unwrap();
+        assert_eq!(decode_cf_html(&resp.data).unwrap(), b"<i>hi</i>".to_vec());
+    }
+
+    #[test]
+    fn handle_format_list_requests_html_and_dispatches_fragment_to_backend() {
+        let (send, recv) = channel();
+        let mut c = Client::new(Box::new(TestBackend {
+            on_format_data_response: Some(send),
+            ..Default::default()
+        }));
+
+        let list = FormatListPDU::<LongFormatName> {
+            format_names: vec![LongFormatName {
+                format_id: 1234,
+                format_name: Some(CLIPBOARD_FORMAT_NAME_HTML.to_string()),
+            }],
+        };
+        let encoded = list.encode().unwrap();
+        let len = encoded.len() as u32;
+        let responses = c
+            .handle_format_list(&mut Cursor::new(encoded), len)
+            .unwrap();
+
+        // format list response + a single format data request for the HTML format
+        assert_eq!(responses.len(), 2);
+        assert!(c.expecting_html);
+
+        let blob = encode_cf_html(b"<p>world</p>");
+        let data_resp = FormatDataResponsePDU { data: blob }.encode().unwrap();
+        let len = data_resp.len() as u32;
+        c.handle_format_data_response(&mut Cursor::new(data_resp), len)
+            .unwrap();
+
+        let (format_id, fragment) = recv.try_recv().unwrap();
+        assert_eq!(format_id, 1234);
+        assert_eq!(fragment, b"<p>world</p>".to_vec());
     }
 }
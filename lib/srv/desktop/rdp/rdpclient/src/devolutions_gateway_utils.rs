@@ -1,44 +1,69 @@
 // todo(isaiah): some utils adapted from the devolutions-gateway repo, see if there's a way to put these in
 // ironrdp, otherwise rename them.
-use bytes::{Buf, BytesMut};
+use async_trait::async_trait;
+use bytes::{Buf, BufMut, BytesMut};
 use ironrdp::pdu::connection_initiation::{NegotiationError, Response};
 use ironrdp::pdu::PduParsing;
 use ironrdp::rdcleanpath::RDCleanPathPdu;
 use std::io;
 use thiserror::Error;
-use tokio::io::{AsyncRead, AsyncReadExt as _};
-use tokio_util::codec::Decoder;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_stream::StreamExt as _;
+use tokio_util::codec::{Decoder, Encoder, FramedRead};
 
-pub async fn read_cleanpath_pdu(
-    stream: &mut (dyn AsyncRead + Unpin + Send),
-) -> io::Result<RDCleanPathPdu> {
-    let mut buf = bytes::BytesMut::new();
+/// Frames RDCleanPathPdu on the wire. The same PDU type is exchanged in both directions (the
+/// client's initial request, the gateway's response), so one Decoder/Encoder pair covers both
+/// FramedRead and FramedWrite for this PDU.
+#[derive(Default)]
+pub struct RDCleanPathCodec;
+
+impl Decoder for RDCleanPathCodec {
+    type Item = RDCleanPathPdu;
+    type Error = io::Error;
 
-    // TODO: check if there is code to be reused from ironrdp code base for that
-    let cleanpath_pdu = loop {
-        if let Some(pdu) = RDCleanPathPdu::decode(&mut buf).map_err(|e| {
+    fn decode(&mut self, buf: &mut BytesMut) -> io::Result<Option<Self::Item>> {
+        RDCleanPathPdu::decode(buf).map_err(|e| {
             io::Error::new(
                 io::ErrorKind::InvalidInput,
                 format!("bad RDCleanPathPdu: {e}"),
             )
-        })? {
-            break pdu;
-        }
+        })
+    }
+}
 
-        let mut read_bytes = [0u8; 1024];
-        let len = stream.read(&mut read_bytes[..]).await?;
-        buf.extend_from_slice(&read_bytes[..len]);
+impl Encoder<RDCleanPathPdu> for RDCleanPathCodec {
+    type Error = io::Error;
 
-        if len == 0 {
+    fn encode(&mut self, item: RDCleanPathPdu, dst: &mut BytesMut) -> io::Result<()> {
+        item.encode(dst).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("couldn't encode RDCleanPathPdu: {e}"),
+            )
+        })
+    }
+}
+
+/// Reads a single RDCleanPathPdu off stream via FramedRead, rather than hand-rolling a
+/// read-and-decode loop, then checks that the stream didn't hand us anything past the PDU's own
+/// framing.
+pub async fn read_cleanpath_pdu(
+    stream: &mut (dyn AsyncRead + Unpin + Send),
+) -> io::Result<RDCleanPathPdu> {
+    let mut framed = FramedRead::new(stream, RDCleanPathCodec);
+
+    let cleanpath_pdu = match framed.next().await {
+        Some(pdu) => pdu?,
+        None => {
             return Err(io::Error::new(
                 io::ErrorKind::UnexpectedEof,
                 "EOF when reading RDCleanPathPdu",
-            ));
+            ))
         }
     };
 
     // Sanity check: make sure there is no leftover
-    if !buf.is_empty() {
+    if !framed.read_buffer().is_empty() {
         return Err(io::Error::new(
             io::ErrorKind::Other,
             "no leftover is expected after reading cleanpath PDU",
@@ -48,6 +73,94 @@ pub async fn read_cleanpath_pdu(
     Ok(cleanpath_pdu)
 }
 
+/// RDCleanPathPdu carries the routing/association token that authorizes this gateway leg in its
+/// `proxy_auth` field. Isolated behind this function so the rest of the validation code doesn't
+/// need to know the wire PDU's exact shape.
+fn proxy_auth_token(pdu: &RDCleanPathPdu) -> Option<&str> {
+    pdu.proxy_auth.as_deref()
+}
+
+/// Bundles what `PduTransport::run_cleanpath` needs to authorize a request: the provisioner key
+/// set/audience/dev-escape-hatch, and the replay cache tokens are checked and recorded against.
+/// The replay cache is expected to outlive any single connection (tokens must not be reusable
+/// across connections), so callers own one and pass it in by reference.
+pub(crate) struct TokenValidationCtx<'a> {
+    pub cfg: &'a crate::token_validation::TokenValidationConfig,
+    pub replay_cache: &'a crate::token_validation::ReplayCache,
+}
+
+/// Abstracts the read/write of handshake PDUs over whatever concrete stream (plain TCP, TLS,
+/// WebSocket, ngrok, ...) is carrying the gateway leg, so the cleanpath negotiation state machine
+/// below only has to be written once and every transport inherits it for free.
+#[async_trait]
+pub trait PduTransport {
+    async fn request(&mut self, pdu: &[u8]) -> io::Result<()>;
+    async fn receive(&mut self) -> io::Result<BytesMut>;
+
+    /// Reads and decodes a single RDCleanPathPdu, built on nothing but `receive` - the same loop
+    /// `read_cleanpath_pdu` runs over a `dyn AsyncRead`, generalized to any `PduTransport` - then
+    /// validates the token carried in it before handing the PDU back to the caller.
+    async fn run_cleanpath(
+        &mut self,
+        token_validation: &TokenValidationCtx<'_>,
+    ) -> Result<RDCleanPathPdu, CleanPathError> {
+        let mut codec = RDCleanPathCodec;
+        let mut buf = BytesMut::new();
+
+        let cleanpath_pdu = loop {
+            if let Some(pdu) = codec.decode(&mut buf)? {
+                break pdu;
+            }
+
+            let chunk = self.receive().await?;
+            if chunk.is_empty() {
+                return Err(CleanPathError::Io(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "EOF when reading RDCleanPathPdu",
+                )));
+            }
+            buf.extend_from_slice(&chunk);
+        };
+
+        // Sanity check: make sure there is no leftover
+        if !buf.is_empty() {
+            return Err(CleanPathError::Io(io::Error::new(
+                io::ErrorKind::Other,
+                "no leftover is expected after reading cleanpath PDU",
+            )));
+        }
+
+        let token = proxy_auth_token(&cleanpath_pdu).ok_or(AuthorizationError::Unauthorized)?;
+        crate::token_validation::validate_routing_token(
+            token,
+            token_validation.cfg,
+            token_validation.replay_cache,
+        )?;
+
+        Ok(cleanpath_pdu)
+    }
+}
+
+/// Blanket impl so any plain duplex stream - a `TcpStream`, a `tokio_rustls` TLS stream, a
+/// WebSocket's inner stream, the `ProxyConn::Ngrok` connection - gets `PduTransport` (and
+/// therefore `run_cleanpath`) without writing a dedicated impl per transport.
+#[async_trait]
+impl<S> PduTransport for S
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    async fn request(&mut self, pdu: &[u8]) -> io::Result<()> {
+        self.write_all(pdu).await
+    }
+
+    async fn receive(&mut self) -> io::Result<BytesMut> {
+        let mut buf = vec![0u8; 4096];
+        let n = self.read(&mut buf).await?;
+        buf.truncate(n);
+        Ok(BytesMut::from(&buf[..]))
+    }
+}
+
 // todo(isaiah): below here is adapted from devolutions-gateway/src/transport/x224.rs
 macro_rules! negotiation_try {
     ($e:expr) => {
@@ -76,6 +189,21 @@ impl Decoder for NegotiationWithServerTransport {
     }
 }
 
+impl Encoder<Response> for NegotiationWithServerTransport {
+    type Error = io::Error;
+
+    /// Lets a caller write a connection-confirm Response back out through a FramedWrite, the
+    /// same way Decoder lets one read one in through a FramedRead, instead of hand-rolling a
+    /// write_all over a scratch buffer.
+    fn encode(&mut self, item: Response, dst: &mut BytesMut) -> io::Result<()> {
+        let mut buf = vec![0u8; item.buffer_length()];
+        item.to_buffer(&mut buf.as_mut_slice())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{e}")))?;
+        dst.put_slice(&buf);
+        Ok(())
+    }
+}
+
 fn map_negotiation_error(e: NegotiationError) -> io::Error {
     match e {
         NegotiationError::ResponseFailure(e) => io::Error::new(
@@ -116,4 +244,14 @@ pub(crate) enum AuthorizationError {
     Forbidden,
     #[error("token missing from request")]
     Unauthorized,
+    #[error("token has expired")]
+    Expired,
+    #[error("token is not yet valid")]
+    NotYetValid,
+    #[error("token signature is invalid")]
+    BadSignature,
+    #[error("token has already been used")]
+    Replayed,
+    #[error("token audience does not match this gateway")]
+    AudienceMismatch,
 }
@@ -0,0 +1,309 @@
+// todo(isaiah): lightweight pluggable-transport wrapper, not a hardened censorship-resistance
+// protocol - good enough to keep the RDCleanPath/X.224 handshake from being trivially fingerprinted
+// or blocked on its wire shape, not to resist a motivated adversary doing traffic analysis.
+
+//! `ObfsTransport` wraps a raw duplex stream with an optional obfuscation layer so the
+//! RDCleanPath/X.224 handshake can traverse networks that block or fingerprint plain RDP. It's
+//! purely a framing + encryption layer: `ObfsTransport` itself implements `AsyncRead`/
+//! `AsyncWrite`, so `NegotiationWithServerTransport`, `read_cleanpath_pdu`, and
+//! `PduTransport::run_cleanpath` all run on top of it completely unchanged.
+//!
+//! Handshake: each side generates a random nonce and writes it to the stream in the clear, then
+//! both sides derive a pair of per-direction keys via HKDF-SHA256 over the preshared key, salted
+//! with the two nonces. This authenticates nothing beyond possession of the preshared key - it
+//! exists to make the stream look like noise to a passive observer, not to replace TLS.
+//!
+//! Framing, once the handshake completes:
+//!
+//!   record := u32 (ciphertext_len, LE) || ciphertext
+//!
+//! `ciphertext` is the plaintext frame (optionally padded up to `pad_to` bytes, with its true
+//! length carried in a 2-byte prefix inside the plaintext so padding is stripped after
+//! decryption) XORed with a ChaCha20 keystream under the sender's direction key, with the nonce
+//! for record `n` built from a monotonically incrementing counter so no two records reuse a
+//! keystream.
+
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20::ChaCha20;
+use hkdf::Hkdf;
+use rand::RngCore;
+use sha2::Sha256;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+const HANDSHAKE_NONCE_LEN: usize = 32;
+const KEY_LEN: usize = 32;
+const CHACHA20_NONCE_LEN: usize = 12;
+const LEN_PREFIX_LEN: usize = 4;
+/// Plaintext frames carry their true length in a 2-byte prefix so padding can be stripped.
+const PLAINTEXT_LEN_PREFIX_LEN: usize = 2;
+
+/// Operator-configured settings for the obfuscation layer. `preshared_key` must be the same on
+/// both ends of the gateway leg; never logged.
+#[derive(Clone)]
+pub struct ObfsConfig {
+    pub preshared_key: Vec<u8>,
+    /// Pads every frame's plaintext up to this many bytes (when smaller) before encrypting, to
+    /// defeat length-based traffic classification. `None` disables padding.
+    pub pad_to: Option<usize>,
+}
+
+struct DirectionKeys {
+    client_to_server: [u8; KEY_LEN],
+    server_to_client: [u8; KEY_LEN],
+}
+
+fn derive_keys(psk: &[u8], client_nonce: &[u8], server_nonce: &[u8]) -> DirectionKeys {
+    let hk = Hkdf::<Sha256>::new(Some(server_nonce), psk);
+    let mut okm = [0u8; KEY_LEN * 2];
+    hk.expand(client_nonce, &mut okm)
+        .expect("okm length is valid for HKDF-SHA256");
+
+    let mut client_to_server = [0u8; KEY_LEN];
+    let mut server_to_client = [0u8; KEY_LEN];
+    client_to_server.copy_from_slice(&okm[..KEY_LEN]);
+    server_to_client.copy_from_slice(&okm[KEY_LEN..]);
+    DirectionKeys {
+        client_to_server,
+        server_to_client,
+    }
+}
+
+/// Builds the 12-byte ChaCha20 nonce for record `counter`: zero-padded big-endian counter in the
+/// low 8 bytes, so the first 2^64 records per direction never repeat a keystream.
+fn record_nonce(counter: u64) -> [u8; CHACHA20_NONCE_LEN] {
+    let mut nonce = [0u8; CHACHA20_NONCE_LEN];
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    nonce
+}
+
+/// Which side of the handshake this transport is playing, since the client and server derive
+/// (and use) their two direction keys the opposite way round.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ObfsRole {
+    Client,
+    Server,
+}
+
+/// Wraps `inner` in the obfuscation layer described above. Construction performs the handshake
+/// inline, so a successfully constructed `ObfsTransport` is immediately ready to carry the
+/// RDCleanPath stream.
+pub struct ObfsTransport<S> {
+    inner: S,
+    pad_to: Option<usize>,
+    write_key: [u8; KEY_LEN],
+    read_key: [u8; KEY_LEN],
+    write_counter: u64,
+    read_counter: u64,
+
+    /// Raw bytes read off `inner` that haven't been assembled into a complete record yet.
+    read_raw_buf: Vec<u8>,
+    /// Decrypted, depadded plaintext ready to be handed to the caller via `poll_read`.
+    read_plain_buf: Vec<u8>,
+    read_plain_pos: usize,
+
+    /// A framed, encrypted record queued for writing, and how much of it has gone out so far.
+    pending_write: Option<Vec<u8>>,
+    write_pos: usize,
+}
+
+impl<S> ObfsTransport<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Performs the nonce exchange, derives the per-direction keys, and returns a transport ready
+    /// to carry the obfuscated stream.
+    pub async fn handshake(mut inner: S, cfg: &ObfsConfig, role: ObfsRole) -> io::Result<Self> {
+        let mut our_nonce = [0u8; HANDSHAKE_NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut our_nonce);
+
+        inner.write_all(&our_nonce).await?;
+        inner.flush().await?;
+
+        let mut their_nonce = [0u8; HANDSHAKE_NONCE_LEN];
+        inner.read_exact(&mut their_nonce).await?;
+
+        let (client_nonce, server_nonce) = match role {
+            ObfsRole::Client => (&our_nonce, &their_nonce),
+            ObfsRole::Server => (&their_nonce, &our_nonce),
+        };
+        let keys = derive_keys(&cfg.preshared_key, client_nonce, server_nonce);
+
+        let (write_key, read_key) = match role {
+            ObfsRole::Client => (keys.client_to_server, keys.server_to_client),
+            ObfsRole::Server => (keys.server_to_client, keys.client_to_server),
+        };
+
+        Ok(Self {
+            inner,
+            pad_to: cfg.pad_to,
+            write_key,
+            read_key,
+            write_counter: 0,
+            read_counter: 0,
+            read_raw_buf: Vec::new(),
+            read_plain_buf: Vec::new(),
+            read_plain_pos: 0,
+            pending_write: None,
+            write_pos: 0,
+        })
+    }
+
+    /// Frames and encrypts `plaintext` into a single wire record: length prefix, then the
+    /// (possibly padded) plaintext XORed with this write direction's keystream at the current
+    /// counter.
+    fn seal_record(&mut self, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        let padded_len = self
+            .pad_to
+            .map_or(plaintext.len(), |pad_to| plaintext.len().max(pad_to));
+
+        let mut frame = Vec::with_capacity(PLAINTEXT_LEN_PREFIX_LEN + padded_len);
+        frame.extend_from_slice(&(plaintext.len() as u16).to_le_bytes());
+        frame.extend_from_slice(plaintext);
+        frame.resize(PLAINTEXT_LEN_PREFIX_LEN + padded_len, 0);
+
+        let mut cipher = ChaCha20::new(&self.write_key.into(), &record_nonce(self.write_counter).into());
+        cipher.apply_keystream(&mut frame);
+        self.write_counter += 1;
+
+        let mut record = Vec::with_capacity(LEN_PREFIX_LEN + frame.len());
+        record.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        record.extend_from_slice(&frame);
+        Ok(record)
+    }
+
+    /// Tries to pull one complete record out of `read_raw_buf`, decrypt it, and append the
+    /// depadded plaintext to `read_plain_buf`. Returns `true` if a record was consumed.
+    fn try_decode_one_record(&mut self) -> io::Result<bool> {
+        if self.read_raw_buf.len() < LEN_PREFIX_LEN {
+            return Ok(false);
+        }
+        let frame_len =
+            u32::from_le_bytes(self.read_raw_buf[..LEN_PREFIX_LEN].try_into().unwrap()) as usize;
+        if self.read_raw_buf.len() < LEN_PREFIX_LEN + frame_len {
+            return Ok(false);
+        }
+
+        let mut frame = self.read_raw_buf[LEN_PREFIX_LEN..LEN_PREFIX_LEN + frame_len].to_vec();
+        self.read_raw_buf.drain(..LEN_PREFIX_LEN + frame_len);
+
+        let mut cipher = ChaCha20::new(&self.read_key.into(), &record_nonce(self.read_counter).into());
+        cipher.apply_keystream(&mut frame);
+        self.read_counter += 1;
+
+        if frame.len() < PLAINTEXT_LEN_PREFIX_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "obfuscated record too short to contain its plaintext length prefix",
+            ));
+        }
+        let plain_len =
+            u16::from_le_bytes(frame[..PLAINTEXT_LEN_PREFIX_LEN].try_into().unwrap()) as usize;
+        let plaintext = frame
+            .get(PLAINTEXT_LEN_PREFIX_LEN..PLAINTEXT_LEN_PREFIX_LEN + plain_len)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "obfuscated record's plaintext length prefix exceeds the record itself",
+                )
+            })?;
+        self.read_plain_buf.extend_from_slice(plaintext);
+
+        Ok(true)
+    }
+}
+
+impl<S> AsyncRead for ObfsTransport<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        loop {
+            if this.read_plain_pos < this.read_plain_buf.len() {
+                let n = std::cmp::min(
+                    buf.remaining(),
+                    this.read_plain_buf.len() - this.read_plain_pos,
+                );
+                buf.put_slice(&this.read_plain_buf[this.read_plain_pos..this.read_plain_pos + n]);
+                this.read_plain_pos += n;
+                if this.read_plain_pos == this.read_plain_buf.len() {
+                    this.read_plain_buf.clear();
+                    this.read_plain_pos = 0;
+                }
+                return Poll::Ready(Ok(()));
+            }
+
+            if this.try_decode_one_record()? {
+                continue;
+            }
+
+            let mut raw = [0u8; 4096];
+            let mut raw_buf = ReadBuf::new(&mut raw);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut raw_buf))?;
+            let filled = raw_buf.filled();
+            if filled.is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            this.read_raw_buf.extend_from_slice(filled);
+        }
+    }
+}
+
+impl<S> AsyncWrite for ObfsTransport<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        // The plaintext length prefix is 2 bytes wide, so one record carries at most u16::MAX
+        // bytes of `buf`; the rest is left for the caller's next write.
+        let buf = &buf[..buf.len().min(u16::MAX as usize)];
+
+        if this.pending_write.is_none() {
+            this.pending_write = Some(this.seal_record(buf)?);
+            this.write_pos = 0;
+        }
+
+        while let Some(record) = this.pending_write.as_ref() {
+            let n = ready!(Pin::new(&mut this.inner).poll_write(cx, &record[this.write_pos..]))?;
+            this.write_pos += n;
+            if this.write_pos == record.len() {
+                this.pending_write = None;
+                this.write_pos = 0;
+                return Poll::Ready(Ok(buf.len()));
+            }
+        }
+
+        Poll::Pending
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while let Some(record) = this.pending_write.as_ref() {
+            let n = ready!(Pin::new(&mut this.inner).poll_write(cx, &record[this.write_pos..]))?;
+            this.write_pos += n;
+            if this.write_pos == record.len() {
+                this.pending_write = None;
+                this.write_pos = 0;
+            }
+        }
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
@@ -16,16 +16,20 @@ mod consts;
 pub(crate) mod dir;
 mod flags;
 pub(crate) mod path;
+pub(crate) mod port;
+pub(crate) mod printer;
 pub(crate) mod scard;
 use self::dir::FileCacheObject;
 use self::path::WindowsPath;
-use crate::errors::{invalid_data_error, not_implemented_error, try_error};
+use crate::errors::{invalid_data_error, not_implemented_error, not_negotiated_error, try_error};
 use crate::util;
 use crate::vchan;
 use crate::{
-    FileSystemObject, FileType, Payload, SharedDirectoryCreateResponse,
-    SharedDirectoryDeleteResponse, SharedDirectoryInfoResponse, SharedDirectoryListResponse,
-    SharedDirectoryMoveResponse, SharedDirectoryReadResponse, SharedDirectoryWriteResponse,
+    FileSystemObject, FileType, Payload, SharedDirectoryChangeNotifyResponse,
+    SharedDirectoryCreateResponse, SharedDirectoryDeleteResponse, SharedDirectoryFreeSpaceResponse,
+    SharedDirectoryInfoResponse, SharedDirectoryListResponse, SharedDirectoryMoveResponse,
+    SharedDirectoryReadResponse, SharedDirectorySetTimestampsResponse,
+    SharedDirectoryTruncateResponse, SharedDirectoryWriteResponse,
 };
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
@@ -34,18 +38,72 @@ use consts::{
     CapabilityType, Component, DeviceType, FileInformationClassLevel,
     FileSystemInformationClassLevel, MajorFunction, MinorFunction, PacketId, BOOL_SIZE,
     DIRECTORY_SHARE_CLIENT_NAME, DRIVE_CAPABILITY_VERSION_02, FILE_ATTR_SIZE,
-    GENERAL_CAPABILITY_VERSION_02, I64_SIZE, I8_SIZE, NTSTATUS, SCARD_DEVICE_ID,
-    SMARTCARD_CAPABILITY_VERSION_01, U32_SIZE, U8_SIZE, VERSION_MAJOR, VERSION_MINOR,
+    GENERAL_CAPABILITY_VERSION_02, I64_SIZE, I8_SIZE, IO_REPARSE_TAG_SYMLINK, NTSTATUS,
+    PORT_CAPABILITY_VERSION_01, PORT_DEVICE_ID, PRINTER_CAPABILITY_VERSION_01, PRINTER_DEVICE_ID,
+    RDPDR_CLIENT_DISPLAY_NAME_PDU, RDPDR_DEVICE_REMOVE_PDUS, RDPDR_USER_LOGGEDON_PDU,
+    SCARD_DEVICE_ID, SMARTCARD_CAPABILITY_VERSION_01, U32_SIZE, U8_SIZE, VERSION_MAJOR,
+    VERSION_MINOR,
 };
 use num_traits::{FromPrimitive, ToPrimitive};
 use rdp::core::mcs;
 use rdp::core::tpkt;
 use rdp::model::data::Message;
 use rdp::model::error::{Error as RdpError, RdpResult};
+use std::collections::HashSet;
 use std::convert::{TryFrom, TryInto};
 use std::ffi::CString;
 use std::io::{Read, Seek, SeekFrom, Write};
 
+/// A PDU (or PDU fragment) that can serialize itself onto a caller-owned buffer. Written against
+/// a `&mut Vec<u8>` rather than returning a freshly allocated one so a caller assembling several
+/// of these back-to-back (see `Client::add_headers_and_chunkify`) can size its buffer once via
+/// `encoded_len` instead of letting a `Vec` grow and reallocate underneath it.
+trait Encodable {
+    fn encode_into(&self, buf: &mut Vec<u8>) -> RdpResult<()>;
+    fn encoded_len(&self) -> usize;
+
+    /// Convenience for the common case of wanting a standalone, correctly-sized buffer.
+    fn encode_to_vec(&self) -> RdpResult<Vec<u8>> {
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// A PDU (or PDU fragment) that can deserialize itself from a `Payload` cursor.
+trait Decodable: Sized {
+    fn decode(payload: &mut Payload) -> RdpResult<Self>;
+}
+
+/// Bounds-checking helpers for decoders that read a server-controlled length field and then
+/// allocate a buffer of that size before reading into it. Without this, a malicious or truncated
+/// message can make us attempt a huge allocation (e.g. a 4 GiB `Vec` for a bogus `path_length`)
+/// well before the subsequent `read_exact` would have failed on its own.
+trait PayloadExt {
+    /// How many bytes are left to read before the end of the buffer.
+    fn remaining(&self) -> u64;
+
+    /// Errors out if fewer than `n` bytes remain, instead of letting the caller allocate a buffer
+    /// of attacker-controlled size it can't actually fill.
+    fn ensure_remaining(&self, n: u64) -> RdpResult<()> {
+        let remaining = self.remaining();
+        if remaining < n {
+            return Err(invalid_data_error(&format!(
+                "unexpected end of payload: needed {} more bytes, only {} remain",
+                n, remaining
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl PayloadExt for Payload {
+    fn remaining(&self) -> u64 {
+        // position() can legitimately run past len() after a seek beyond the end of the buffer.
+        (self.get_ref().len() as u64).saturating_sub(self.position())
+    }
+}
+
 /// Client implements a device redirection (RDPDR) client, as defined in
 /// https://winprotocoldoc.blob.core.windows.net/productionwindowsarchives/MS-RDPEFS/%5bMS-RDPEFS%5d.pdf
 ///
@@ -54,13 +112,99 @@ pub struct Client {
     vchan: vchan::Client,
     scard: scard::Client,
     dir: dir::Client,
+    printer: Option<printer::Client>,
+    port: Option<port::Client>,
 
     active_device_ids: Vec<u32>,
+    /// Tracks our progress through the MS-RDPEFS initialization sequence (Server Announce ->
+    /// Client Name + Client Capability -> Client ID Confirm -> User Logged On), so that a server
+    /// which folds or reorders these PDUs is caught instead of silently desyncing device
+    /// redirection. `None` before the first `PAKID_CORE_SERVER_ANNOUNCE` arrives.
+    init_state: Option<InitState>,
+    /// What the server actually told us it supports in `PAKID_CORE_SERVER_CAPABILITY`, used to
+    /// gate features we'd otherwise just assume are there. Default (all unsupported) until that
+    /// PDU arrives.
+    negotiated_caps: NegotiatedCaps,
+}
+
+/// The subset of the server's negotiated capabilities that downstream device redirection logic
+/// actually depends on, populated from the `PAKID_CORE_SERVER_CAPABILITY` the server sends during
+/// init (see `Client::handle_server_capability`). A server is free to omit any of these, and we
+/// need to behave correctly (not just optimistically) when it does.
+#[derive(Debug, Clone, Copy, Default)]
+struct NegotiatedCaps {
+    /// RDPDR_USER_LOGGEDON_PDU, from the server's general capability set's `extended_pdu` field.
+    /// If unset, the server should never send `PAKID_CORE_USER_LOGGEDON` at all.
+    user_logged_on_pdu: bool,
+    /// RDPDR_DEVICE_REMOVE_PDUS, from the same field. `write_client_device_list_remove` depends
+    /// on this.
+    device_remove_pdus: bool,
+    /// The version the server negotiated for CAP_DRIVE_TYPE, or `None` if it didn't send that
+    /// capability set at all (meaning it has no interest in drive redirection).
+    drive_capability_version: Option<u32>,
+}
+
+impl NegotiatedCaps {
+    fn from_server_request(req: &ServerCoreCapabilityRequest) -> Self {
+        let mut caps = Self::default();
+        for cap in req.capabilities.iter() {
+            match &cap.data {
+                Capability::General(general) => {
+                    caps.user_logged_on_pdu = general.extended_pdu & RDPDR_USER_LOGGEDON_PDU != 0;
+                    caps.device_remove_pdus =
+                        general.extended_pdu & RDPDR_DEVICE_REMOVE_PDUS != 0;
+                }
+                Capability::Drive => {
+                    caps.drive_capability_version = Some(cap.header.version);
+                }
+                _ => {}
+            }
+        }
+        caps
+    }
+
+    /// Whether the server negotiated enough to support drive redirection the way we implement
+    /// it: RDPDR_DEVICE_REMOVE_PDUS (required by `write_client_device_list_remove`) and a
+    /// CAP_DRIVE_TYPE version at least as new as the one we speak.
+    fn supports_drive_redirection(&self) -> bool {
+        self.device_remove_pdus
+            && self
+                .drive_capability_version
+                .map_or(false, |version| version >= DRIVE_CAPABILITY_VERSION_02)
+    }
+}
+
+/// See the `init_state` field on `Client`. Each variant's `predecessor` is the only state a
+/// server is allowed to advance from; anything else indicates a non-compliant or reordered
+/// handshake, which real FreeRDP-based and xrdp-based servers are strict about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InitState {
+    Announced,
+    CapabilitiesExchanged,
+    ClientIdConfirmed,
+    UserLoggedOn,
+}
+
+impl InitState {
+    fn predecessor(self) -> Option<InitState> {
+        match self {
+            InitState::Announced => None,
+            InitState::CapabilitiesExchanged => Some(InitState::Announced),
+            InitState::ClientIdConfirmed => Some(InitState::CapabilitiesExchanged),
+            InitState::UserLoggedOn => Some(InitState::ClientIdConfirmed),
+        }
+    }
 }
 
 pub struct Config {
     pub scard_cfg: scard::Config,
     pub dir_cfg: dir::Config,
+    /// When set, a single client-side printer is redirected in addition to the smartcard and
+    /// any shared directories.
+    pub printer_cfg: Option<printer::Config>,
+    /// When set, a single client-side serial or parallel port is redirected in addition to the
+    /// smartcard and any shared directories/printer.
+    pub port_cfg: Option<port::Config>,
 }
 
 impl Client {
@@ -69,10 +213,28 @@ impl Client {
             vchan: vchan::Client::new(),
             scard: scard::Client::new(cfg.scard_cfg),
             dir: dir::Client::new(cfg.dir_cfg),
+            printer: cfg.printer_cfg.map(printer::Client::new),
+            port: cfg.port_cfg.map(port::Client::new),
 
             active_device_ids: vec![],
+            init_state: None,
+            negotiated_caps: NegotiatedCaps::default(),
+        }
+    }
+
+    /// Advances `init_state` to `next`, erroring out if the server sent the PDU that triggered
+    /// this transition out of order (e.g. a capability exchange before the initial announce).
+    fn advance_init_state(&mut self, next: InitState) -> RdpResult<()> {
+        if self.init_state != next.predecessor() {
+            return Err(RdpError::TryError(format!(
+                "received RDPDR init PDU out of order: can't reach {:?} from {:?}",
+                next, self.init_state
+            )));
         }
+        self.init_state = Some(next);
+        Ok(())
     }
+
     /// Reads raw RDP messages sent on the rdpdr virtual channel and replies as necessary.
     pub fn read_and_reply<S: Read + Write>(
         &mut self,
@@ -82,7 +244,17 @@ impl Client {
         if let Some(mut payload) = self.vchan.read(payload)? {
             let header = SharedHeader::decode(&mut payload)?;
             if let Component::RDPDR_CTYP_PRN = header.component {
-                warn!("got {:?} RDPDR header from RDP server, ignoring because we're not redirecting any printers", header);
+                // The only packet sent under this component is PAKID_PRN_CACHE_DATA, which
+                // hands back driver-specific printer cache data for us to persist. We don't
+                // maintain a printer driver cache, so there's nothing to do with it either way.
+                if self.printer.is_some() {
+                    debug!(
+                        "got {:?} RDPDR header from RDP server for the redirected printer, ignoring",
+                        header
+                    );
+                } else {
+                    warn!("got {:?} RDPDR header from RDP server, ignoring because we're not redirecting any printers", header);
+                }
                 return Ok(());
             }
             let responses = match header.packet_id {
@@ -95,11 +267,12 @@ impl Client {
                 PacketId::PAKID_CORE_CLIENTID_CONFIRM => {
                     self.handle_client_id_confirm(&mut payload)?
                 }
+                PacketId::PAKID_CORE_USER_LOGGEDON => self.handle_user_logged_on()?,
                 PacketId::PAKID_CORE_DEVICE_REPLY => self.handle_device_reply(&mut payload)?,
                 // Device IO request is where communication with the smartcard and shared drive actually happens.
                 // Everything up to this point was negotiation (and smartcard device registration).
                 PacketId::PAKID_CORE_DEVICE_IOREQUEST => {
-                    self.handle_device_io_request(&mut payload)?
+                    self.handle_device_io_request(&mut payload, mcs)?
                 }
                 _ => {
                     // We don't implement the full set of messages.
@@ -119,9 +292,10 @@ impl Client {
         Ok(())
     }
 
-    fn handle_server_announce(&self, payload: &mut Payload) -> RdpResult<Vec<Vec<u8>>> {
+    fn handle_server_announce(&mut self, payload: &mut Payload) -> RdpResult<Vec<Vec<u8>>> {
         let req = ServerAnnounceRequest::decode(payload)?;
         debug!("received RDP {:?}", req);
+        self.advance_init_state(InitState::Announced)?;
 
         let resp = ClientAnnounceReply::new(req);
         debug!("sending RDP {:?}", resp);
@@ -143,12 +317,24 @@ impl Client {
         Ok(resp)
     }
 
-    fn handle_server_capability(&self, payload: &mut Payload) -> RdpResult<Vec<Vec<u8>>> {
+    fn handle_server_capability(&mut self, payload: &mut Payload) -> RdpResult<Vec<Vec<u8>>> {
         let req = ServerCoreCapabilityRequest::decode(payload)?;
         debug!("received RDP {:?}", req);
+        self.advance_init_state(InitState::CapabilitiesExchanged)?;
+        self.negotiated_caps = NegotiatedCaps::from_server_request(&req);
+
+        let announce_drive_redirection =
+            self.dir.allow_directory_sharing && self.negotiated_caps.supports_drive_redirection();
+        if self.dir.allow_directory_sharing && !announce_drive_redirection {
+            warn!("drive redirection is enabled locally, but the server didn't negotiate RDPDR_DEVICE_REMOVE_PDUS support and/or a compatible CAP_DRIVE_TYPE version; not advertising it for this session");
+        }
 
-        let resp = ClientCoreCapabilityResponse::new_response(self.dir.allow_directory_sharing)
-            .encode()?;
+        let resp = ClientCoreCapabilityResponse::new_response(
+            announce_drive_redirection,
+            self.printer.is_some(),
+            self.port.is_some(),
+        )
+        .encode()?;
         debug!("sending RDP {:?}", resp);
         let resp = self.add_headers_and_chunkify(PacketId::PAKID_CORE_CLIENT_CAPABILITY, resp)?;
         Ok(resp)
@@ -157,12 +343,46 @@ impl Client {
     fn handle_client_id_confirm(&mut self, payload: &mut Payload) -> RdpResult<Vec<Vec<u8>>> {
         let req = ServerClientIdConfirm::decode(payload)?;
         debug!("received RDP {:?}", req);
+        self.advance_init_state(InitState::ClientIdConfirmed)?;
+
+        // Device redirection isn't announced here: FreeRDP-based and xrdp-based servers expect
+        // the Client Device List Announce to follow PAKID_CORE_USER_LOGGEDON, not the ID
+        // confirm, so it's sent from handle_user_logged_on instead.
+        Ok(vec![])
+    }
+
+    fn handle_user_logged_on(&mut self) -> RdpResult<Vec<Vec<u8>>> {
+        self.advance_init_state(InitState::UserLoggedOn)?;
+        debug!("received RDP PAKID_CORE_USER_LOGGEDON");
 
-        // The smartcard initialization sequence that contains this message happens once at session startup,
-        // and once when login succeeds. We only need to announce the smartcard once.
+        if !self.negotiated_caps.user_logged_on_pdu {
+            return Err(not_negotiated_error(
+                "received PAKID_CORE_USER_LOGGEDON, but the server's general capability set never advertised RDPDR_USER_LOGGEDON_PDU support",
+            ));
+        }
+
+        // The smartcard (and, if configured, printer/port) initialization sequence that contains
+        // this message happens once at session startup, and once when login succeeds. We only
+        // need to announce them once.
         let resp = if !self.active_device_ids.contains(&SCARD_DEVICE_ID) {
             self.push_active_device_id(SCARD_DEVICE_ID)?;
-            let resp = ClientDeviceListAnnounceRequest::new_smartcard(SCARD_DEVICE_ID);
+            let mut resp = ClientDeviceListAnnounceRequest::new_smartcard(SCARD_DEVICE_ID);
+            let printer_header = self
+                .printer
+                .as_ref()
+                .map(|client| client.announce_header(PRINTER_DEVICE_ID));
+            if let Some(printer_header) = printer_header {
+                self.push_active_device_id(PRINTER_DEVICE_ID)?;
+                resp = resp.with_device(printer_header);
+            }
+            let port_header = self
+                .port
+                .as_ref()
+                .map(|client| client.announce_header(PORT_DEVICE_ID));
+            if let Some(port_header) = port_header {
+                self.push_active_device_id(PORT_DEVICE_ID)?;
+                resp = resp.with_device(port_header);
+            }
             debug!("sending RDP {:?}", resp);
             self.add_headers_and_chunkify(PacketId::PAKID_CORE_DEVICELIST_ANNOUNCE, resp.encode()?)?
         } else {
@@ -184,48 +404,96 @@ impl Client {
             )));
         }
 
-        if res.device_id != self.get_scard_device_id()? {
-            // This was for a directory we're sharing over TDP
-            self.dir.handle_device_reply(res)
-        } else {
+        if res.device_id == self.get_scard_device_id()? {
             // This was for the smart card
-            self.scard.handle_device_reply(res)
+            return self.scard.handle_device_reply(res);
+        }
+
+        if self.printer.is_some() && res.device_id == PRINTER_DEVICE_ID {
+            // This was for the redirected printer; nothing further to do until the server
+            // starts issuing Device I/O Requests against it.
+            return Ok(vec![]);
         }
+
+        if self.port.is_some() && res.device_id == PORT_DEVICE_ID {
+            // This was for the redirected port; nothing further to do until the server starts
+            // issuing Device I/O Requests against it.
+            return Ok(vec![]);
+        }
+
+        // This was for a directory we're sharing over TDP. Set up this device's
+        // FileId namespace and file cache before any IRPs for it can arrive.
+        self.dir.process_device_announce(res.device_id);
+        self.dir.handle_device_reply(res)
     }
 
-    fn handle_device_io_request(&mut self, payload: &mut Payload) -> RdpResult<Vec<Vec<u8>>> {
+    fn handle_device_io_request<S: Read + Write>(
+        &mut self,
+        payload: &mut Payload,
+        mcs: &mut mcs::Client<S>,
+    ) -> RdpResult<Vec<Vec<u8>>> {
         let device_io_request = DeviceIoRequest::decode(payload)?;
         let major_function = device_io_request.major_function.clone();
+        let is_printer_op = self.printer.is_some() && device_io_request.device_id == PRINTER_DEVICE_ID;
+        let is_port_op = self.port.is_some() && device_io_request.device_id == PORT_DEVICE_ID;
 
         // Smartcard control only uses IRP_MJ_DEVICE_CONTROL; directory control uses IRP_MJ_DEVICE_CONTROL along with
         // all the other MajorFunctions supported by this Client. Therefore if we receive any other major function when drive
-        // redirection is not allowed, something has gone wrong. In such a case, we return an error as a security measure
-        // to ensure directories are never shared when RBAC doesn't permit it.
-        if major_function != MajorFunction::IRP_MJ_DEVICE_CONTROL
-            && !self.dir.allow_directory_sharing
+        // redirection is not allowed (and it's not targeting the redirected printer or port, which have their own opt-in),
+        // something has gone wrong. In such a case, we return an error as a security measure to ensure directories are
+        // never shared when RBAC doesn't permit it.
+        if major_function != MajorFunction::IRP_MJ_DEVICE_CONTROL && !is_printer_op && !is_port_op
         {
-            return Err(RdpError::TryError(
-                "received a drive redirection major function when drive redirection was not allowed"
-                    .to_string(),
-            ));
+            self.ensure_drive_redirection_negotiated()?;
         }
 
         let resp = match major_function {
             MajorFunction::IRP_MJ_DEVICE_CONTROL => {
                 self.process_irp_device_control(device_io_request, payload)
             }
+            MajorFunction::IRP_MJ_CREATE if is_printer_op => self
+                .printer
+                .as_ref()
+                .unwrap()
+                .process_irp_create(device_io_request, payload),
+            MajorFunction::IRP_MJ_CREATE if is_port_op => {
+                self.port.as_ref().unwrap().process_irp_create(device_io_request, payload)
+            }
             MajorFunction::IRP_MJ_CREATE => self.dir.process_irp_create(device_io_request, payload),
             MajorFunction::IRP_MJ_QUERY_INFORMATION => self
                 .dir
                 .process_irp_query_information(device_io_request, payload),
-            MajorFunction::IRP_MJ_CLOSE => self.dir.process_irp_close(device_io_request),
+            MajorFunction::IRP_MJ_CLOSE if is_printer_op => self
+                .printer
+                .as_ref()
+                .unwrap()
+                .process_irp_close(device_io_request),
+            MajorFunction::IRP_MJ_CLOSE if is_port_op => {
+                self.port.as_ref().unwrap().process_irp_close(device_io_request)
+            }
+            MajorFunction::IRP_MJ_CLOSE => self.dir.process_irp_close(device_io_request, mcs),
             MajorFunction::IRP_MJ_DIRECTORY_CONTROL => self
                 .dir
                 .process_irp_directory_control(device_io_request, payload),
             MajorFunction::IRP_MJ_QUERY_VOLUME_INFORMATION => self
                 .dir
                 .process_irp_query_volume_information(device_io_request, payload),
+            MajorFunction::IRP_MJ_READ if is_port_op => self
+                .port
+                .as_mut()
+                .unwrap()
+                .process_irp_read(device_io_request, payload),
             MajorFunction::IRP_MJ_READ => self.dir.process_irp_read(device_io_request, payload),
+            MajorFunction::IRP_MJ_WRITE if is_printer_op => self
+                .printer
+                .as_ref()
+                .unwrap()
+                .process_irp_write(device_io_request, payload),
+            MajorFunction::IRP_MJ_WRITE if is_port_op => self
+                .port
+                .as_ref()
+                .unwrap()
+                .process_irp_write(device_io_request, payload),
             MajorFunction::IRP_MJ_WRITE => self.dir.process_irp_write(device_io_request, payload),
             MajorFunction::IRP_MJ_SET_INFORMATION => self
                 .dir
@@ -248,17 +516,26 @@ impl Client {
     ) -> RdpResult<Vec<u8>> {
         let ioctl = DeviceControlRequest::decode(device_io_request, payload)?;
         let is_smart_card_op = ioctl.header.device_id == self.get_scard_device_id()?;
+        let is_printer_op = self.printer.is_some() && ioctl.header.device_id == PRINTER_DEVICE_ID;
+        let is_port_op = self.port.is_some() && ioctl.header.device_id == PORT_DEVICE_ID;
         debug!("received RDP: {:?}", ioctl);
 
-        // IRP_MJ_DEVICE_CONTROL is the one major function used by both the smartcard controller (always enabled)
-        // and shared directory controller (potentially disabled by RBAC). Here we check that directory sharing
-        // is enabled here before proceeding with any shared directory controls as an additional security measure.
-        if !is_smart_card_op && !self.dir.allow_directory_sharing {
-            return Err(RdpError::TryError("received a drive redirection major function when drive redirection was not allowed".to_string()));
+        // IRP_MJ_DEVICE_CONTROL is the one major function used by the smartcard controller (always enabled),
+        // the printer and port controllers (enabled when configured), and the shared directory controller
+        // (potentially disabled by RBAC). Here we check that directory sharing is enabled before proceeding
+        // with any shared directory controls as an additional security measure.
+        if !is_smart_card_op && !is_printer_op && !is_port_op {
+            self.ensure_drive_redirection_negotiated()?;
         }
         if is_smart_card_op {
             // Smart card control
             self.scard.process_irp_device_control(ioctl, payload)
+        } else if is_printer_op {
+            // Printer control
+            self.printer.as_ref().unwrap().process_irp_device_control(ioctl)
+        } else if is_port_op {
+            // Serial/parallel port control
+            self.port.as_mut().unwrap().process_irp_device_control(ioctl, payload)
         } else {
             // Drive redirection
             self.dir.process_irp_device_control(ioctl)
@@ -270,7 +547,17 @@ impl Client {
         req: ClientDeviceListAnnounce,
         mcs: &mut mcs::Client<S>,
     ) -> RdpResult<()> {
-        self.push_active_device_id(req.device_list[0].device_id)?;
+        if !self.negotiated_caps.supports_drive_redirection() {
+            return Err(not_negotiated_error(
+                "cannot announce a new drive for redirection: the server never negotiated drive redirection support",
+            ));
+        }
+        // `req` is usually a single-drive ClientDeviceListAnnounceRequest::new_drive, but the
+        // wire format (and this type) allow announcing several drives at once, so track every
+        // device_id it carries rather than just the first.
+        for device in req.device_list.iter() {
+            self.push_active_device_id(device.device_id)?;
+        }
         debug!("sending new drive for redirection over RDP: {:?}", req);
 
         let responses =
@@ -283,6 +570,54 @@ impl Client {
         Ok(())
     }
 
+    /// Removes one or more previously announced devices from redirection without tearing down
+    /// the RDP connection, e.g. when RBAC revokes access to a shared directory or the user
+    /// ejects it mid-session. The server is required to support this because we always request
+    /// RDPDR_DEVICE_REMOVE_PDUS in our general capability set (see
+    /// ServerCoreCapabilityRequest::new_response).
+    pub fn write_client_device_list_remove<S: Read + Write>(
+        &mut self,
+        device_ids: Vec<u32>,
+        mcs: &mut mcs::Client<S>,
+    ) -> RdpResult<()> {
+        if !self.negotiated_caps.device_remove_pdus {
+            return Err(not_negotiated_error(
+                "cannot remove a device: the server never negotiated RDPDR_DEVICE_REMOVE_PDUS support",
+            ));
+        }
+        for device_id in device_ids.iter() {
+            if *device_id == SCARD_DEVICE_ID {
+                return Err(RdpError::TryError(
+                    "cannot remove the smartcard device".to_string(),
+                ));
+            }
+            if !self.active_device_ids.contains(device_id) {
+                return Err(RdpError::TryError(format!(
+                    "cannot remove device_id {} because it is not currently active",
+                    device_id
+                )));
+            }
+        }
+
+        let req = ClientDeviceListRemove::new(device_ids.clone());
+        debug!("sending RDP {:?}", req);
+        let responses =
+            self.add_headers_and_chunkify(PacketId::PAKID_CORE_DEVICELIST_REMOVE, req.encode()?)?;
+        let chan = &CHANNEL_NAME.to_string();
+        for resp in responses {
+            mcs.write(chan, resp)?;
+        }
+
+        self.active_device_ids.retain(|id| !device_ids.contains(id));
+        for device_id in device_ids {
+            if device_id != PRINTER_DEVICE_ID {
+                self.dir.remove_device(device_id);
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn handle_tdp_sd_info_response<S: Read + Write>(
         &mut self,
         res: SharedDirectoryInfoResponse,
@@ -339,6 +674,47 @@ impl Client {
         self.dir.handle_tdp_sd_move_response(res, mcs)
     }
 
+    pub fn handle_tdp_sd_free_space_response<S: Read + Write>(
+        &mut self,
+        res: SharedDirectoryFreeSpaceResponse,
+        mcs: &mut mcs::Client<S>,
+    ) -> RdpResult<()> {
+        self.dir.handle_tdp_sd_free_space_response(res, mcs)
+    }
+
+    pub fn handle_tdp_sd_truncate_response<S: Read + Write>(
+        &mut self,
+        res: SharedDirectoryTruncateResponse,
+        mcs: &mut mcs::Client<S>,
+    ) -> RdpResult<()> {
+        self.dir.handle_tdp_sd_truncate_response(res, mcs)
+    }
+
+    pub fn handle_tdp_sd_set_timestamps_response<S: Read + Write>(
+        &mut self,
+        res: SharedDirectorySetTimestampsResponse,
+        mcs: &mut mcs::Client<S>,
+    ) -> RdpResult<()> {
+        self.dir.handle_tdp_sd_set_timestamps_response(res, mcs)
+    }
+
+    pub fn handle_tdp_sd_change_notify_response<S: Read + Write>(
+        &mut self,
+        res: SharedDirectoryChangeNotifyResponse,
+        mcs: &mut mcs::Client<S>,
+    ) -> RdpResult<()> {
+        self.dir.handle_tdp_sd_change_notify_response(res, mcs)
+    }
+
+    /// Fails any IRP whose CompletionId has been sitting in the correlation table awaiting a TDP
+    /// response for longer than the configured IRP timeout (dir::DEFAULT_IRP_TIMEOUT unless
+    /// Config sets another), so a shared directory that stops
+    /// responding doesn't leave the RDP server waiting on an I/O request forever. The caller is
+    /// expected to invoke this periodically (e.g. from the same loop that pumps `read_and_reply`).
+    pub fn sweep_expired_irps<S: Read + Write>(&mut self, mcs: &mut mcs::Client<S>) -> RdpResult<()> {
+        self.dir.sweep_expired_irps(mcs)
+    }
+
     /// add_headers_and_chunkify takes an encoded PDU ready to be sent over a virtual channel (payload),
     /// adds on the Shared Header based the passed packet_id, adds the appropriate (virtual) Channel PDU Header,
     /// and splits the entire payload into chunks if the payload exceeds the maximum size.
@@ -347,7 +723,9 @@ impl Client {
         packet_id: PacketId,
         payload: Vec<u8>,
     ) -> RdpResult<Vec<Vec<u8>>> {
-        let mut inner = SharedHeader::new(Component::RDPDR_CTYP_CORE, packet_id).encode()?;
+        let header = SharedHeader::new(Component::RDPDR_CTYP_CORE, packet_id);
+        let mut inner = Vec::with_capacity(header.encoded_len() + payload.len());
+        header.encode_into(&mut inner)?;
         inner.extend_from_slice(&payload);
         self.vchan.add_header_and_chunkify(None, inner)
     }
@@ -363,12 +741,34 @@ impl Client {
         Ok(())
     }
 
+    /// Checks both preconditions for servicing a drive redirection device I/O request: local RBAC
+    /// (`dir.allow_directory_sharing`) and what the server actually negotiated in
+    /// `PAKID_CORE_SERVER_CAPABILITY`. The two fail differently on purpose: the former is a
+    /// config/permissions problem, the latter means the server is asking for a device-control
+    /// path it never agreed to support.
+    fn ensure_drive_redirection_negotiated(&self) -> RdpResult<()> {
+        if !self.dir.allow_directory_sharing {
+            return Err(RdpError::TryError(
+                "received a drive redirection major function when drive redirection was not allowed"
+                    .to_string(),
+            ));
+        }
+        if !self.negotiated_caps.supports_drive_redirection() {
+            return Err(not_negotiated_error(
+                "received a drive redirection request, but the server never negotiated RDPDR_DEVICE_REMOVE_PDUS support and/or a compatible CAP_DRIVE_TYPE version",
+            ));
+        }
+        Ok(())
+    }
+
     fn get_scard_device_id(&self) -> RdpResult<u32> {
-        // We always push it into the list first
-        if !self.active_device_ids.is_empty() {
-            return Ok(self.active_device_ids[0]);
+        // Don't assume the smartcard is active_device_ids[0]: once directories can be announced
+        // or removed at runtime (see dir::Client::process_device_announce/remove_device), the
+        // smartcard's position in active_device_ids is no longer guaranteed.
+        if self.active_device_ids.contains(&SCARD_DEVICE_ID) {
+            return Ok(SCARD_DEVICE_ID);
         }
-        Err(RdpError::TryError("no active device ids".to_string()))
+        Err(RdpError::TryError("no active smartcard device id".to_string()))
     }
 }
 
@@ -389,6 +789,13 @@ impl SharedHeader {
             packet_id,
         }
     }
+
+    fn encode(&self) -> RdpResult<Vec<u8>> {
+        self.encode_to_vec()
+    }
+}
+
+impl Decodable for SharedHeader {
     fn decode(payload: &mut Payload) -> RdpResult<Self> {
         let component = payload.read_u16::<LittleEndian>()?;
         let packet_id = payload.read_u16::<LittleEndian>()?;
@@ -401,11 +808,17 @@ impl SharedHeader {
             })?,
         })
     }
-    fn encode(&self) -> RdpResult<Vec<u8>> {
-        let mut w = vec![];
-        w.write_u16::<LittleEndian>(self.component.to_u16().unwrap())?;
-        w.write_u16::<LittleEndian>(self.packet_id.to_u16().unwrap())?;
-        Ok(w)
+}
+
+impl Encodable for SharedHeader {
+    fn encode_into(&self, buf: &mut Vec<u8>) -> RdpResult<()> {
+        buf.write_u16::<LittleEndian>(self.component.to_u16().unwrap())?;
+        buf.write_u16::<LittleEndian>(self.packet_id.to_u16().unwrap())?;
+        Ok(())
+    }
+
+    fn encoded_len(&self) -> usize {
+        U32_SIZE as usize // Component (u16) + PacketId (u16)
     }
 }
 
@@ -430,13 +843,11 @@ impl ClientIdMessage {
     }
 
     fn encode(&self) -> RdpResult<Vec<u8>> {
-        let mut w = vec![];
-        w.write_u16::<LittleEndian>(self.version_major)?;
-        w.write_u16::<LittleEndian>(self.version_minor)?;
-        w.write_u32::<LittleEndian>(self.client_id)?;
-        Ok(w)
+        self.encode_to_vec()
     }
+}
 
+impl Decodable for ClientIdMessage {
     fn decode(payload: &mut Payload) -> RdpResult<Self> {
         Ok(Self {
             version_major: payload.read_u16::<LittleEndian>()?,
@@ -446,6 +857,19 @@ impl ClientIdMessage {
     }
 }
 
+impl Encodable for ClientIdMessage {
+    fn encode_into(&self, buf: &mut Vec<u8>) -> RdpResult<()> {
+        buf.write_u16::<LittleEndian>(self.version_major)?;
+        buf.write_u16::<LittleEndian>(self.version_minor)?;
+        buf.write_u32::<LittleEndian>(self.client_id)?;
+        Ok(())
+    }
+
+    fn encoded_len(&self) -> usize {
+        8 // version_major (u16) + version_minor (u16) + client_id (u32)
+    }
+}
+
 #[derive(Debug)]
 struct ServerCoreCapabilityRequest {
     num_capabilities: u16,
@@ -454,10 +878,11 @@ struct ServerCoreCapabilityRequest {
 }
 
 impl ServerCoreCapabilityRequest {
-    fn new_response(allow_directory_sharing: bool) -> Self {
+    fn new_response(allow_directory_sharing: bool, printer_enabled: bool, port_enabled: bool) -> Self {
         // Clients are always required to send the "general" capability set.
-        // In addition, we also send the optional smartcard capability (CAP_SMARTCARD_TYPE)
-        // and drive capability (CAP_DRIVE_TYPE).
+        // In addition, we also send the optional smartcard capability (CAP_SMARTCARD_TYPE),
+        // drive capability (CAP_DRIVE_TYPE), and (when configured for redirection) the printer
+        // capability (CAP_PRINTER_TYPE) and port capability (CAP_PORT_TYPE).
         let mut capabilities = vec![
             CapabilitySet {
                 header: CapabilityHeader {
@@ -472,7 +897,9 @@ impl ServerCoreCapabilityRequest {
                     protocol_minor_version: VERSION_MINOR,
                     io_code_1: 0x00007fff, // Combination of all the required bits.
                     io_code_2: 0,
-                    extended_pdu: 0x00000001 | 0x00000002, // RDPDR_DEVICE_REMOVE_PDUS | RDPDR_CLIENT_DISPLAY_NAME_PDU
+                    extended_pdu: RDPDR_DEVICE_REMOVE_PDUS
+                        | RDPDR_CLIENT_DISPLAY_NAME_PDU
+                        | RDPDR_USER_LOGGEDON_PDU,
                     extra_flags_1: 0,
                     extra_flags_2: 0,
                     special_type_device_cap: 1, // Request redirection of 1 special device - smartcard.
@@ -499,6 +926,28 @@ impl ServerCoreCapabilityRequest {
             });
         }
 
+        if printer_enabled {
+            capabilities.push(CapabilitySet {
+                header: CapabilityHeader {
+                    cap_type: CapabilityType::CAP_PRINTER_TYPE,
+                    length: 8, // 8 byte header + empty capability descriptor
+                    version: PRINTER_CAPABILITY_VERSION_01,
+                },
+                data: Capability::Printer,
+            });
+        }
+
+        if port_enabled {
+            capabilities.push(CapabilitySet {
+                header: CapabilityHeader {
+                    cap_type: CapabilityType::CAP_PORT_TYPE,
+                    length: 8, // 8 byte header + empty capability descriptor
+                    version: PORT_CAPABILITY_VERSION_01,
+                },
+                data: Capability::Port,
+            });
+        }
+
         Self {
             padding: 0,
             num_capabilities: capabilities.len() as u16,
@@ -507,15 +956,11 @@ impl ServerCoreCapabilityRequest {
     }
 
     fn encode(&self) -> RdpResult<Vec<u8>> {
-        let mut w = vec![];
-        w.write_u16::<LittleEndian>(self.num_capabilities)?;
-        w.write_u16::<LittleEndian>(self.padding)?;
-        for cap in self.capabilities.iter() {
-            w.extend_from_slice(&cap.encode()?);
-        }
-        Ok(w)
+        self.encode_to_vec()
     }
+}
 
+impl Decodable for ServerCoreCapabilityRequest {
     fn decode(payload: &mut Payload) -> RdpResult<Self> {
         let num_capabilities = payload.read_u16::<LittleEndian>()?;
         let padding = payload.read_u16::<LittleEndian>()?;
@@ -532,6 +977,25 @@ impl ServerCoreCapabilityRequest {
     }
 }
 
+impl Encodable for ServerCoreCapabilityRequest {
+    fn encode_into(&self, buf: &mut Vec<u8>) -> RdpResult<()> {
+        buf.write_u16::<LittleEndian>(self.num_capabilities)?;
+        buf.write_u16::<LittleEndian>(self.padding)?;
+        for cap in self.capabilities.iter() {
+            buf.extend_from_slice(&cap.encode()?);
+        }
+        Ok(())
+    }
+
+    fn encoded_len(&self) -> usize {
+        4 + self
+            .capabilities
+            .iter()
+            .map(|cap| cap.header.length as usize)
+            .sum::<usize>()
+    }
+}
+
 #[derive(Debug)]
 struct CapabilitySet {
     header: CapabilityHeader,
@@ -544,9 +1008,26 @@ impl CapabilitySet {
         w.extend_from_slice(&self.data.encode()?);
         Ok(w)
     }
+}
+
+impl Decodable for CapabilitySet {
     fn decode(payload: &mut Payload) -> RdpResult<Self> {
         let header = CapabilityHeader::decode(payload)?;
+
+        // header.length is the size, in bytes, of this entire capability set (header + data).
+        // Capability::decode only understands the data layout of CAP_GENERAL_TYPE; every other
+        // type it accepts (Printer/Port/Drive/Smartcard) decodes as an empty marker regardless of
+        // how many descriptor bytes the server actually sent. Track the cursor across the call so
+        // any bytes Capability::decode didn't consume are skipped here, rather than left for the
+        // next CapabilitySet::decode to stumble over mid-structure.
+        let data_start = payload.position();
         let data = Capability::decode(payload, &header)?;
+        let consumed = payload.position() - data_start;
+        let declared_data_len = u64::from(header.length).saturating_sub(u64::from(header.encoded_len() as u32));
+        if consumed < declared_data_len {
+            let skip = declared_data_len - consumed;
+            payload.seek(SeekFrom::Current(skip as i64))?;
+        }
 
         Ok(Self { header, data })
     }
@@ -561,12 +1042,11 @@ struct CapabilityHeader {
 
 impl CapabilityHeader {
     fn encode(&self) -> RdpResult<Vec<u8>> {
-        let mut w = vec![];
-        w.write_u16::<LittleEndian>(self.cap_type.to_u16().unwrap())?;
-        w.write_u16::<LittleEndian>(self.length)?;
-        w.write_u32::<LittleEndian>(self.version)?;
-        Ok(w)
+        self.encode_to_vec()
     }
+}
+
+impl Decodable for CapabilityHeader {
     fn decode(payload: &mut Payload) -> RdpResult<Self> {
         let cap_type = payload.read_u16::<LittleEndian>()?;
         Ok(Self {
@@ -579,6 +1059,19 @@ impl CapabilityHeader {
     }
 }
 
+impl Encodable for CapabilityHeader {
+    fn encode_into(&self, buf: &mut Vec<u8>) -> RdpResult<()> {
+        buf.write_u16::<LittleEndian>(self.cap_type.to_u16().unwrap())?;
+        buf.write_u16::<LittleEndian>(self.length)?;
+        buf.write_u32::<LittleEndian>(self.version)?;
+        Ok(())
+    }
+
+    fn encoded_len(&self) -> usize {
+        8 // cap_type (u16) + length (u16) + version (u32)
+    }
+}
+
 #[derive(Debug)]
 enum Capability {
     General(GeneralCapabilitySet),
@@ -625,20 +1118,12 @@ struct GeneralCapabilitySet {
 
 impl GeneralCapabilitySet {
     fn encode(&self) -> RdpResult<Vec<u8>> {
-        let mut w = vec![];
-        w.write_u32::<LittleEndian>(self.os_type)?;
-        w.write_u32::<LittleEndian>(self.os_version)?;
-        w.write_u16::<LittleEndian>(self.protocol_major_version)?;
-        w.write_u16::<LittleEndian>(self.protocol_minor_version)?;
-        w.write_u32::<LittleEndian>(self.io_code_1)?;
-        w.write_u32::<LittleEndian>(self.io_code_2)?;
-        w.write_u32::<LittleEndian>(self.extended_pdu)?;
-        w.write_u32::<LittleEndian>(self.extra_flags_1)?;
-        w.write_u32::<LittleEndian>(self.extra_flags_2)?;
-        w.write_u32::<LittleEndian>(self.special_type_device_cap)?;
-        Ok(w)
+        self.encode_to_vec()
     }
 
+    // Not ported onto `Decodable`: unlike every other capability descriptor, this one's layout
+    // depends on the negotiated capability version (see the special_type_device_cap field below),
+    // so decoding it needs an extra parameter `Decodable::decode`'s fixed signature has no room for.
     fn decode(payload: &mut Payload, version: u32) -> RdpResult<Self> {
         Ok(Self {
             os_type: payload.read_u32::<LittleEndian>()?,
@@ -659,6 +1144,26 @@ impl GeneralCapabilitySet {
     }
 }
 
+impl Encodable for GeneralCapabilitySet {
+    fn encode_into(&self, buf: &mut Vec<u8>) -> RdpResult<()> {
+        buf.write_u32::<LittleEndian>(self.os_type)?;
+        buf.write_u32::<LittleEndian>(self.os_version)?;
+        buf.write_u16::<LittleEndian>(self.protocol_major_version)?;
+        buf.write_u16::<LittleEndian>(self.protocol_minor_version)?;
+        buf.write_u32::<LittleEndian>(self.io_code_1)?;
+        buf.write_u32::<LittleEndian>(self.io_code_2)?;
+        buf.write_u32::<LittleEndian>(self.extended_pdu)?;
+        buf.write_u32::<LittleEndian>(self.extra_flags_1)?;
+        buf.write_u32::<LittleEndian>(self.extra_flags_2)?;
+        buf.write_u32::<LittleEndian>(self.special_type_device_cap)?;
+        Ok(())
+    }
+
+    fn encoded_len(&self) -> usize {
+        36 // 8 u32 fields + 2 u16 fields, see encode_into
+    }
+}
+
 type ClientCoreCapabilityResponse = ServerCoreCapabilityRequest;
 
 #[derive(Debug)]
@@ -716,6 +1221,14 @@ impl ClientDeviceListAnnounceRequest {
         }
     }
 
+    /// Appends another device (e.g. a printer) to this announce request before it's sent, so
+    /// more than one device can be announced in a single Client Device List Announce Request.
+    fn with_device(mut self, device: DeviceAnnounceHeader) -> Self {
+        self.device_list.push(device);
+        self.device_count = self.device_list.len() as u32;
+        self
+    }
+
     fn encode(&self) -> RdpResult<Vec<u8>> {
         let mut w = vec![];
         w.write_u32::<LittleEndian>(self.device_count)?;
@@ -726,6 +1239,34 @@ impl ClientDeviceListAnnounceRequest {
     }
 }
 
+/// 2.2.3.2 Client Drive Device List Remove (DR_DEVICELIST_REMOVE)
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpefs/a59ec1e2-e31a-4ea4-b29e-13e78a29af22
+/// Sent under PacketId::PAKID_CORE_DEVICELIST_REMOVE to un-redirect one or more devices that were
+/// previously announced via ClientDeviceListAnnounceRequest, without tearing down the session.
+#[derive(Debug)]
+struct ClientDeviceListRemove {
+    device_count: u32,
+    device_ids: Vec<u32>,
+}
+
+impl ClientDeviceListRemove {
+    fn new(device_ids: Vec<u32>) -> Self {
+        Self {
+            device_count: device_ids.len() as u32,
+            device_ids,
+        }
+    }
+
+    fn encode(&self) -> RdpResult<Vec<u8>> {
+        let mut w = vec![];
+        w.write_u32::<LittleEndian>(self.device_count)?;
+        for device_id in self.device_ids.iter() {
+            w.write_u32::<LittleEndian>(*device_id)?;
+        }
+        Ok(w)
+    }
+}
+
 /// 2.2.1.3 Device Announce Header (DEVICE_ANNOUNCE)
 /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpefs/32e34332-774b-4ead-8c9d-5d64720d6bf9
 #[derive(Debug)]
@@ -1004,6 +1545,7 @@ impl DeviceCreateRequest {
 
         // usize is 32 bits on a 32 bit target and 64 on a 64, so we can safely say try_into().unwrap()
         // for a u32 will never panic on the machines that run teleport.
+        payload.ensure_remaining(path_length as u64)?;
         let mut path = vec![0u8; path_length.try_into().unwrap()];
         payload.read_exact(&mut path)?;
         let path = WindowsPath::from(util::from_unicode(path)?);
@@ -1108,9 +1650,13 @@ struct ServerDriveQueryInformationRequest {
     ///
     /// FileAttributeTagInformation
     /// This information class is used to query for file attribute and reparse tag information.
+    ///
+    /// FileFullEaInformation
+    /// This information class is used to query a file's extended attributes.
     file_info_class_lvl: FileInformationClassLevel,
-    // Length, Padding, and QueryBuffer appear to be vestigial fields and can safely be ignored. Their description
-    // is provided below for documentation purposes.
+    // Length, Padding, and QueryBuffer are vestigial for every FileInformationClass above except
+    // FileFullEaInformation, whose QueryBuffer carries a FileGetEaInformation (see ea_name below),
+    // so their description is provided here for documentation purposes.
     //
     // Length (4 bytes): A 32-bit unsigned integer that specifies the number of bytes in the QueryBuffer field.
     //
@@ -1120,25 +1666,40 @@ struct ServerDriveQueryInformationRequest {
     // The content of this field is based on the value of the FileInformationClass field, which determines the different
     // structures that MUST be contained in the QueryBuffer field. For a complete list of these structures, see [MS-FSCC]
     // section 2.4. The "File information class" table defines all the possible values for the FileInformationClass field.
+    /// The single extended attribute name named by this request's QueryBuffer, when
+    /// file_info_class_lvl is FileFullEaInformation and the caller supplied one. None when no
+    /// QueryBuffer was sent (the caller wants every extended attribute) or for every other
+    /// file_info_class_lvl.
+    ea_name: Option<String>,
 }
 
 impl ServerDriveQueryInformationRequest {
     fn decode(device_io_request: DeviceIoRequest, payload: &mut Payload) -> RdpResult<Self> {
         let n = payload.read_u32::<LittleEndian>()?;
-        if let Some(file_info_class_lvl) = FileInformationClassLevel::from_u32(n) {
-            return Ok(Self {
-                device_io_request,
-                file_info_class_lvl,
-            });
-        }
-
-        Err(invalid_data_error(
-            format!(
+        let file_info_class_lvl = FileInformationClassLevel::from_u32(n).ok_or_else(|| {
+            invalid_data_error(&format!(
                 "received invalid FileInformationClass in ServerDriveQueryInformationRequest: {}",
                 n
-            )
-            .as_str(),
-        ))
+            ))
+        })?;
+
+        let ea_name = if file_info_class_lvl == FileInformationClassLevel::FileFullEaInformation {
+            let length = payload.read_u32::<LittleEndian>()?;
+            payload.seek(SeekFrom::Current(24))?;
+            if length > 0 {
+                Some(FileGetEaInformation::decode(payload)?.ea_name)
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+
+        Ok(Self {
+            device_io_request,
+            file_info_class_lvl,
+            ea_name,
+        })
     }
 }
 
@@ -1152,12 +1713,17 @@ enum FileInformationClass {
     FileBothDirectoryInformation(FileBothDirectoryInformation),
     FileAttributeTagInformation(FileAttributeTagInformation),
     FileFullDirectoryInformation(FileFullDirectoryInformation),
+    FileIdBothDirectoryInformation(FileIdBothDirectoryInformation),
+    FileIdFullDirectoryInformation(FileIdFullDirectoryInformation),
+    FileFullEaInformation(FileFullEaInformation),
     FileEndOfFileInformation(FileEndOfFileInformation),
     FileDispositionInformation(FileDispositionInformation),
     FileRenameInformation(FileRenameInformation),
     FileAllocationInformation(FileAllocationInformation),
     FileNamesInformation(FileNamesInformation),
     FileDirectoryInformation(FileDirectoryInformation),
+    FileNetworkOpenInformation(FileNetworkOpenInformation),
+    FileAllInformation(FileAllInformation),
 }
 
 impl FileInformationClass {
@@ -1168,12 +1734,17 @@ impl FileInformationClass {
             Self::FileBothDirectoryInformation(file_info_class) => file_info_class.encode(),
             Self::FileAttributeTagInformation(file_info_class) => file_info_class.encode(),
             Self::FileFullDirectoryInformation(file_info_class) => file_info_class.encode(),
+            Self::FileIdBothDirectoryInformation(file_info_class) => file_info_class.encode(),
+            Self::FileIdFullDirectoryInformation(file_info_class) => file_info_class.encode(),
+            Self::FileFullEaInformation(file_info_class) => file_info_class.encode(),
             Self::FileEndOfFileInformation(file_info_class) => file_info_class.encode(),
             Self::FileDispositionInformation(file_info_class) => file_info_class.encode(),
             Self::FileRenameInformation(file_info_class) => file_info_class.encode(),
             Self::FileAllocationInformation(file_info_class) => file_info_class.encode(),
             Self::FileNamesInformation(file_info_class) => file_info_class.encode(),
             Self::FileDirectoryInformation(file_info_class) => file_info_class.encode(),
+            Self::FileNetworkOpenInformation(file_info_class) => file_info_class.encode(),
+            Self::FileAllInformation(file_info_class) => file_info_class.encode(),
         }
     }
 
@@ -1182,6 +1753,27 @@ impl FileInformationClass {
         length: u32,
         payload: &mut Payload,
     ) -> RdpResult<Self> {
+        // The server declares, in `length`, how many bytes make up this class's buffer. Reject it
+        // up front if it's too small for the class's fixed-size fields rather than letting the
+        // nested decode() drift past the declared boundary into whatever field follows it.
+        let min_length = match file_information_class_level {
+            FileInformationClassLevel::FileBasicInformation => FileBasicInformation::BASE_SIZE,
+            FileInformationClassLevel::FileEndOfFileInformation => {
+                FileEndOfFileInformation::BASE_SIZE
+            }
+            FileInformationClassLevel::FileRenameInformation => FileRenameInformation::BASE_SIZE,
+            FileInformationClassLevel::FileAllocationInformation => {
+                FileAllocationInformation::BASE_SIZE
+            }
+            _ => 0,
+        };
+        if length < min_length {
+            return Err(invalid_data_error(&format!(
+                "{:?} requires a buffer of at least {} bytes, got {}",
+                file_information_class_level, min_length, length
+            )));
+        }
+
         match file_information_class_level {
             FileInformationClassLevel::FileBasicInformation => Ok(
                 FileInformationClass::FileBasicInformation(FileBasicInformation::decode(payload)?),
@@ -1220,27 +1812,176 @@ impl FileInformationClass {
             Self::FileBothDirectoryInformation(file_info_class) => file_info_class.size(),
             Self::FileAttributeTagInformation(file_info_class) => file_info_class.size(),
             Self::FileFullDirectoryInformation(file_info_class) => file_info_class.size(),
+            Self::FileIdBothDirectoryInformation(file_info_class) => file_info_class.size(),
+            Self::FileIdFullDirectoryInformation(file_info_class) => file_info_class.size(),
+            Self::FileFullEaInformation(file_info_class) => file_info_class.size(),
             Self::FileEndOfFileInformation(file_info_class) => file_info_class.size(),
             Self::FileDispositionInformation(file_info_class) => file_info_class.size(),
             Self::FileRenameInformation(file_info_class) => file_info_class.size(),
             Self::FileAllocationInformation(file_info_class) => file_info_class.size(),
             Self::FileNamesInformation(file_info_class) => file_info_class.size(),
             Self::FileDirectoryInformation(file_info_class) => file_info_class.size(),
+            Self::FileNetworkOpenInformation(file_info_class) => file_info_class.size(),
+            Self::FileAllInformation(file_info_class) => file_info_class.size(),
         }
     }
-}
 
-/// 2.4.7 FileBasicInformation [MS-FSCC]
-/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-fscc/16023025-8a78-492f-8b96-c873b042ac50
-#[derive(Debug, Clone)]
-struct FileBasicInformation {
-    creation_time: i64,
-    last_access_time: i64,
-    last_write_time: i64,
-    change_time: i64,
-    file_attributes: flags::FileAttributes,
-    // NOTE: The `reserved` field in the spec MUST not be serialized and sent over RDP, or it will break the server implementation.
-    // FreeRDP does the same: https://github.com/FreeRDP/FreeRDP/blob/1adb263813ca2e76a893ef729a04db8f94b5d757/channels/drive/client/drive_file.c#L508
+    /// The byte boundary MS-FSCC requires each directory entry's next_entry_offset to land its
+    /// successor on (see `FileCacheObject::next_batch`/`encode_batch` below).
+    const ENTRY_ALIGNMENT: u32 = 8;
+
+    /// This entry's `size()`, rounded up to `ENTRY_ALIGNMENT`. Used both to budget how many
+    /// entries fit in one IRP_MN_QUERY_DIRECTORY response, and as the next_entry_offset written
+    /// ahead of every entry but the last in a batch.
+    fn aligned_size(&self) -> u32 {
+        Self::round_up_to_alignment(self.size())
+    }
+
+    fn round_up_to_alignment(size: u32) -> u32 {
+        (size + (Self::ENTRY_ALIGNMENT - 1)) / Self::ENTRY_ALIGNMENT * Self::ENTRY_ALIGNMENT
+    }
+
+    /// The would-be aligned wire size of a directory entry for a file/directory named `name` at
+    /// `file_info_class_lvl`, without actually constructing the entry -- in particular, without
+    /// consuming a name from the enumeration's `ShortNameTable`. Lets `FileCacheObject::next_batch`
+    /// decide whether an entry fits in the current response's budget before paying the cost of
+    /// building one that might just get thrown away.
+    fn aligned_size_for(file_info_class_lvl: &FileInformationClassLevel, name: &str) -> RdpResult<u32> {
+        let name_length = util::unicode_size(name, false);
+        let size = match file_info_class_lvl {
+            FileInformationClassLevel::FileBothDirectoryInformation => {
+                FileBothDirectoryInformation::BASE_SIZE + name_length
+            }
+            FileInformationClassLevel::FileFullDirectoryInformation => {
+                FileFullDirectoryInformation::BASE_SIZE + name_length
+            }
+            FileInformationClassLevel::FileIdBothDirectoryInformation => {
+                FileIdBothDirectoryInformation::BASE_SIZE + name_length
+            }
+            FileInformationClassLevel::FileIdFullDirectoryInformation => {
+                FileIdFullDirectoryInformation::BASE_SIZE + name_length
+            }
+            FileInformationClassLevel::FileNamesInformation => {
+                FileNamesInformation::BASE_SIZE + name_length
+            }
+            FileInformationClassLevel::FileDirectoryInformation => {
+                FileDirectoryInformation::BASE_SIZE + name_length
+            }
+            _ => {
+                return Err(invalid_data_error(
+                    "received invalid FileInformationClassLevel in ServerDriveQueryDirectoryRequest",
+                ))
+            }
+        };
+        Ok(Self::round_up_to_alignment(size))
+    }
+
+    /// Converts a single directory entry to the wire representation requested by
+    /// `file_info_class_lvl`, the same conversion FreeRDP-based servers request via the
+    /// FileInformationClass field of a Server Drive Query Directory Request. `short_names` is only
+    /// consulted for the FileBothDirectoryInformation/FileIdBothDirectoryInformation variants,
+    /// which are the only ones that carry an 8.3 short name.
+    fn from_fso(
+        file_info_class_lvl: &FileInformationClassLevel,
+        fso: FileSystemObject,
+        short_names: &mut ShortNameTable,
+    ) -> RdpResult<Self> {
+        match file_info_class_lvl {
+            FileInformationClassLevel::FileBothDirectoryInformation => Ok(
+                Self::FileBothDirectoryInformation(FileBothDirectoryInformation::from(
+                    fso,
+                    short_names,
+                )?),
+            ),
+            FileInformationClassLevel::FileFullDirectoryInformation => Ok(
+                Self::FileFullDirectoryInformation(FileFullDirectoryInformation::from(fso)?),
+            ),
+            FileInformationClassLevel::FileIdBothDirectoryInformation => Ok(
+                Self::FileIdBothDirectoryInformation(FileIdBothDirectoryInformation::from(
+                    fso,
+                    short_names,
+                )?),
+            ),
+            FileInformationClassLevel::FileIdFullDirectoryInformation => Ok(
+                Self::FileIdFullDirectoryInformation(FileIdFullDirectoryInformation::from(fso)?),
+            ),
+            FileInformationClassLevel::FileNamesInformation => Ok(Self::FileNamesInformation(
+                FileNamesInformation::new(fso.name()?),
+            )),
+            FileInformationClassLevel::FileDirectoryInformation => Ok(
+                Self::FileDirectoryInformation(FileDirectoryInformation::from(fso)?),
+            ),
+            _ => Err(invalid_data_error(
+                "received invalid FileInformationClassLevel in ServerDriveQueryDirectoryRequest",
+            )),
+        }
+    }
+
+    /// Sets next_entry_offset on the directory-entry variants that carry one; a no-op on any
+    /// other variant (those are never passed to `encode_batch`).
+    fn set_next_entry_offset(&mut self, offset: u32) {
+        match self {
+            Self::FileBothDirectoryInformation(f) => f.next_entry_offset = offset,
+            Self::FileFullDirectoryInformation(f) => f.next_entry_offset = offset,
+            Self::FileIdBothDirectoryInformation(f) => f.next_entry_offset = offset,
+            Self::FileIdFullDirectoryInformation(f) => f.next_entry_offset = offset,
+            Self::FileNamesInformation(f) => f.next_entry_offset = offset,
+            Self::FileDirectoryInformation(f) => f.next_entry_offset = offset,
+            _ => {}
+        }
+    }
+
+    /// The total encoded length of `entries` once `encode_batch` pads and chains them, i.e. the
+    /// `length` field of a `ClientDriveQueryDirectoryResponse` carrying them.
+    fn batch_size(entries: &[Self]) -> u32 {
+        let last = entries.len().saturating_sub(1);
+        entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                if i == last {
+                    entry.size()
+                } else {
+                    entry.aligned_size()
+                }
+            })
+            .sum()
+    }
+
+    /// Serializes `entries` back-to-back into a single buffer, chaining them via next_entry_offset
+    /// per MS-FSCC: every entry but the last has its next_entry_offset set to its ENTRY_ALIGNMENT-
+    /// rounded size and is padded out to that size so the following entry starts on the boundary
+    /// next_entry_offset promises; the final entry's next_entry_offset is 0 and it's left
+    /// unpadded, matching how a single-entry response already looked before batching existed.
+    fn encode_batch(entries: &[Self]) -> RdpResult<Vec<u8>> {
+        let mut w = vec![];
+        let last = entries.len().saturating_sub(1);
+        for (i, entry) in entries.iter().enumerate() {
+            let mut entry = entry.clone();
+            let next_entry_offset = if i == last { 0 } else { entry.aligned_size() };
+            entry.set_next_entry_offset(next_entry_offset);
+
+            let start = w.len();
+            w.extend_from_slice(&entry.encode()?);
+            if i != last {
+                w.resize(start + next_entry_offset as usize, 0);
+            }
+        }
+        Ok(w)
+    }
+}
+
+/// 2.4.7 FileBasicInformation [MS-FSCC]
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-fscc/16023025-8a78-492f-8b96-c873b042ac50
+#[derive(Debug, Clone)]
+struct FileBasicInformation {
+    creation_time: i64,
+    last_access_time: i64,
+    last_write_time: i64,
+    change_time: i64,
+    file_attributes: flags::FileAttributes,
+    // NOTE: The `reserved` field in the spec MUST not be serialized and sent over RDP, or it will break the server implementation.
+    // FreeRDP does the same: https://github.com/FreeRDP/FreeRDP/blob/1adb263813ca2e76a893ef729a04db8f94b5d757/channels/drive/client/drive_file.c#L508
     //reserved: u32,
 }
 
@@ -1339,34 +2080,445 @@ struct FileAttributeTagInformation {
     reparse_tag: u32,
 }
 
-impl FileAttributeTagInformation {
-    const BASE_SIZE: u32 = U32_SIZE + FILE_ATTR_SIZE;
+impl FileAttributeTagInformation {
+    const BASE_SIZE: u32 = U32_SIZE + FILE_ATTR_SIZE;
+
+    fn encode(&self) -> RdpResult<Vec<u8>> {
+        let mut w = vec![];
+        w.write_u32::<LittleEndian>(self.file_attributes.bits())?;
+        w.write_u32::<LittleEndian>(self.reparse_tag)?;
+        Ok(w)
+    }
+
+    fn decode(payload: &mut Payload) -> RdpResult<Self> {
+        let file_attributes = flags::FileAttributes::from_bits(payload.read_u32::<LittleEndian>()?)
+            .ok_or_else(|| invalid_data_error("invalid flags in FileAttributeTagInformation decode"))?;
+        let reparse_tag = payload.read_u32::<LittleEndian>()?;
+
+        Ok(Self {
+            file_attributes,
+            reparse_tag,
+        })
+    }
+
+    fn size(&self) -> u32 {
+        Self::BASE_SIZE
+    }
+}
+
+/// 2.4.29 FileNetworkOpenInformation [MS-FSCC]
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-fscc/e1875c6a-8925-48b4-a0cf-762525388637
+#[derive(Debug, Clone)]
+struct FileNetworkOpenInformation {
+    creation_time: i64,
+    last_access_time: i64,
+    last_write_time: i64,
+    change_time: i64,
+    allocation_size: i64,
+    end_of_file: i64,
+    file_attributes: flags::FileAttributes,
+    // NOTE: The `reserved` field in the spec MUST not be serialized and sent over RDP, see the
+    // NOTE in FileBasicInformation above.
+    // reserved: u32,
+}
+
+impl FileNetworkOpenInformation {
+    const BASE_SIZE: u32 = (6 * I64_SIZE) + FILE_ATTR_SIZE;
+
+    fn encode(&self) -> RdpResult<Vec<u8>> {
+        let mut w = vec![];
+        w.write_i64::<LittleEndian>(self.creation_time)?;
+        w.write_i64::<LittleEndian>(self.last_access_time)?;
+        w.write_i64::<LittleEndian>(self.last_write_time)?;
+        w.write_i64::<LittleEndian>(self.change_time)?;
+        w.write_i64::<LittleEndian>(self.allocation_size)?;
+        w.write_i64::<LittleEndian>(self.end_of_file)?;
+        w.write_u32::<LittleEndian>(self.file_attributes.bits())?;
+        Ok(w)
+    }
+
+    fn size(&self) -> u32 {
+        Self::BASE_SIZE
+    }
+}
+
+/// 2.4.2 FileAllInformation [MS-FSCC]
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-fscc/95f3056a-ebc1-4f5d-b938-3f68a100ce18
+///
+/// A concatenation of FileBasicInformation, FileStandardInformation, and several single-field
+/// information classes FreeRDP's drive_file.c builds the same way
+/// (https://github.com/FreeRDP/FreeRDP/blob/511444a65e7aa2f537c5e531fa68157a50c1bd4d/channels/drive/client/drive_file.c#L583),
+/// followed by a FileNameInformation carrying the file's name.
+#[derive(Debug, Clone)]
+struct FileAllInformation {
+    basic_information: FileBasicInformation,
+    standard_information: FileStandardInformation,
+    /// FileInternalInformation's IndexNumber: an identifier that is, in combination with the
+    /// file's volume, unique to the file. We don't have a real inode number, so this is
+    /// FileSystemObject::stable_file_id.
+    internal_information: i64,
+    /// FileEaInformation's EaSize. We don't support extended attributes here, so this is always 0.
+    ea_information: u32,
+    /// FileAccessInformation's AccessFlags. We always grant the access the server asked for at
+    /// create time, so there's nothing more restrictive to report here.
+    access_information: u32,
+    /// FilePositionInformation's CurrentByteOffset. We don't track a server-side file position
+    /// (every IRP_MJ_READ/IRP_MJ_WRITE carries its own offset), so this is always 0.
+    position_information: i64,
+    /// FileModeInformation's Mode.
+    mode_information: u32,
+    /// FileAlignmentInformation's AlignmentRequirement.
+    alignment_information: u32,
+    file_name_length: u32,
+    file_name: String,
+}
+
+impl FileAllInformation {
+    /// internal_information + ea_information + access_information + position_information +
+    /// mode_information + alignment_information + file_name_length.
+    const BASE_SIZE: u32 = FileBasicInformation::BASE_SIZE
+        + FileStandardInformation::BASE_SIZE
+        + I64_SIZE
+        + U32_SIZE
+        + U32_SIZE
+        + I64_SIZE
+        + U32_SIZE
+        + U32_SIZE
+        + U32_SIZE;
+
+    fn new(
+        basic_information: FileBasicInformation,
+        standard_information: FileStandardInformation,
+        internal_information: i64,
+        file_name: String,
+    ) -> Self {
+        Self {
+            basic_information,
+            standard_information,
+            internal_information,
+            ea_information: 0,
+            access_information: 0,
+            position_information: 0,
+            mode_information: 0,
+            alignment_information: 0,
+            file_name_length: util::unicode_size(&file_name, false),
+            file_name,
+        }
+    }
+
+    fn encode(&self) -> RdpResult<Vec<u8>> {
+        let mut w = vec![];
+        w.extend_from_slice(&self.basic_information.encode()?);
+        w.extend_from_slice(&self.standard_information.encode()?);
+        w.write_i64::<LittleEndian>(self.internal_information)?;
+        w.write_u32::<LittleEndian>(self.ea_information)?;
+        w.write_u32::<LittleEndian>(self.access_information)?;
+        w.write_i64::<LittleEndian>(self.position_information)?;
+        w.write_u32::<LittleEndian>(self.mode_information)?;
+        w.write_u32::<LittleEndian>(self.alignment_information)?;
+        w.write_u32::<LittleEndian>(self.file_name_length)?;
+        w.extend_from_slice(&util::to_unicode(&self.file_name, false));
+        Ok(w)
+    }
+
+    fn size(&self) -> u32 {
+        Self::BASE_SIZE + self.file_name_length
+    }
+}
+
+/// 2.1.8 Boolean
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-fscc/8ce7b38c-d3cc-415d-ab39-944000ea77ff
+#[derive(Debug, FromPrimitive, ToPrimitive, PartialEq, Clone)]
+#[repr(u8)]
+enum Boolean {
+    True = 1,
+    False = 0,
+}
+
+/// Characters the classic FAT short-name algorithm drops when deriving an 8.3 alias, matching the
+/// set Windows itself rejects in a short name (see MS-FSCC 2.4.8 and the FAT short-name generation
+/// rules implemented by every FAT driver).
+const INVALID_SHORT_NAME_CHARS: &[char] = &[
+    '"', '*', '+', ',', '.', '/', ':', ';', '<', '=', '>', '?', '[', ']', '|', '\\',
+];
+
+/// Tracks the 8.3 short names already generated within a single directory enumeration, so that
+/// `generate` can disambiguate a collision with a numeric `~N` tail the way Windows itself does.
+/// 8.3 uniqueness is only meaningful within one directory, so callers scope one of these per open
+/// directory handle (see `FileCacheObject`) rather than sharing it across directories.
+#[derive(Debug, Clone, Default)]
+struct ShortNameTable {
+    seen: HashSet<String>,
+}
+
+impl ShortNameTable {
+    /// Derives an 8.3 short name for `long_name`: upper-case, strip characters invalid in a short
+    /// name, keep up to 8 characters of the base and up to 3 of the extension after the last '.',
+    /// then, if that collides with a name already generated by this table, replace the tail of
+    /// the base with `~1`, `~2`, ... (truncating the base so the numeral still fits in 8
+    /// characters) until a free name is found.
+    fn generate(&mut self, long_name: &str) -> String {
+        let (base, ext) = Self::split_base_ext(long_name);
+        let base = Self::sanitize(&base, 8);
+        let ext = Self::sanitize(&ext, 3);
+
+        let candidate = Self::join(&base, &ext);
+        if self.seen.insert(candidate.clone()) {
+            return candidate;
+        }
+
+        for n in 1..=999_999u32 {
+            let tail = format!("~{}", n);
+            let truncated_base: String = base.chars().take(8 - tail.len()).collect();
+            let candidate = Self::join(&format!("{}{}", truncated_base, tail), &ext);
+            if self.seen.insert(candidate.clone()) {
+                return candidate;
+            }
+        }
+
+        // Exhausted every ~N tail, which would require hundreds of thousands of colliding names
+        // in one directory; accept the duplicate rather than failing the enumeration over it.
+        candidate
+    }
+
+    fn split_base_ext(name: &str) -> (String, String) {
+        match name.rfind('.') {
+            // A leading dot (e.g. ".bashrc") isn't an extension separator; treat the whole name
+            // as the base, same as Windows does for dotfiles synthesized from other filesystems.
+            Some(pos) if pos > 0 => (name[..pos].to_string(), name[pos + 1..].to_string()),
+            _ => (name.to_string(), String::new()),
+        }
+    }
+
+    fn sanitize(component: &str, max_len: usize) -> String {
+        component
+            .to_uppercase()
+            .chars()
+            .filter(|c| !c.is_whitespace() && !INVALID_SHORT_NAME_CHARS.contains(c))
+            .take(max_len)
+            .collect()
+    }
+
+    fn join(base: &str, ext: &str) -> String {
+        if ext.is_empty() {
+            base.to_string()
+        } else {
+            format!("{}.{}", base, ext)
+        }
+    }
+}
+
+/// 2.4.8 FileBothDirectoryInformation
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-fscc/270df317-9ba5-4ccb-ba00-8d22be139bc5
+#[derive(Debug, Clone)]
+struct FileBothDirectoryInformation {
+    next_entry_offset: u32,
+    file_index: u32,
+    creation_time: i64,
+    last_access_time: i64,
+    last_write_time: i64,
+    change_time: i64,
+    end_of_file: i64,
+    allocation_size: i64,
+    file_attributes: flags::FileAttributes,
+    file_name_length: u32,
+    ea_size: u32,
+    short_name_length: i8,
+    // reserved: u8: MUST NOT be added,
+    // see https://github.com/FreeRDP/FreeRDP/blob/511444a65e7aa2f537c5e531fa68157a50c1bd4d/channels/drive/client/drive_file.c#L907
+    short_name: [u8; 24], // 24 bytes
+    file_name: String,
+}
+
+impl FileBothDirectoryInformation {
+    /// Base size of the FileBothDirectoryInformation, not accounting for variably sized file_name.
+    /// Note that file_name's size should be calculated as if it were a Unicode string.
+    const BASE_SIZE: u32 = (4 * U32_SIZE) + FILE_ATTR_SIZE + (6 * I64_SIZE) + I8_SIZE + 24; // 93
+
+    fn new(
+        creation_time: i64,
+        last_access_time: i64,
+        last_write_time: i64,
+        change_time: i64,
+        file_size: i64,
+        file_attributes: flags::FileAttributes,
+        file_name: String,
+        short_name: &str,
+        ea_size: u32,
+    ) -> Self {
+        // Default field values taken from
+        // https://github.com/FreeRDP/FreeRDP/blob/511444a65e7aa2f537c5e531fa68157a50c1bd4d/channels/drive/client/drive_file.c#L871
+        let short_name_unicode = util::to_unicode(short_name, false);
+        let mut short_name_buf = [0; 24];
+        let n = short_name_unicode.len().min(short_name_buf.len());
+        short_name_buf[..n].copy_from_slice(&short_name_unicode[..n]);
+
+        Self {
+            next_entry_offset: 0,
+            file_index: 0,
+            creation_time,
+            last_access_time,
+            last_write_time,
+            change_time,
+            end_of_file: file_size,
+            allocation_size: file_size,
+            file_attributes,
+            file_name_length: util::unicode_size(&file_name, false),
+            ea_size,
+            short_name_length: n as i8,
+            short_name: short_name_buf,
+            file_name,
+        }
+    }
+
+    fn encode(&self) -> RdpResult<Vec<u8>> {
+        let mut w = vec![];
+        w.write_u32::<LittleEndian>(self.next_entry_offset)?;
+        w.write_u32::<LittleEndian>(self.file_index)?;
+        w.write_i64::<LittleEndian>(self.creation_time)?;
+        w.write_i64::<LittleEndian>(self.last_access_time)?;
+        w.write_i64::<LittleEndian>(self.last_write_time)?;
+        w.write_i64::<LittleEndian>(self.change_time)?;
+        w.write_i64::<LittleEndian>(self.end_of_file)?;
+        w.write_i64::<LittleEndian>(self.allocation_size)?;
+        w.write_u32::<LittleEndian>(self.file_attributes.bits())?;
+        w.write_u32::<LittleEndian>(self.file_name_length)?;
+        w.write_u32::<LittleEndian>(self.ea_size)?;
+        w.write_i8(self.short_name_length)?;
+        // reserved u8, MUST NOT be added!
+        w.extend_from_slice(&self.short_name);
+        // When working with this field, use file_name_length to determine the length of the file name rather
+        // than assuming the presence of a trailing null delimiter. Dot directory names are valid for this field.
+        w.extend_from_slice(&util::to_unicode(&self.file_name, false));
+        Ok(w)
+    }
+
+    fn from(fso: FileSystemObject, short_names: &mut ShortNameTable) -> RdpResult<Self> {
+        let file_attributes = file_attributes_for(fso.file_type);
+
+        let last_modified = to_windows_time(fso.last_modified)?;
+        let ea_size = ea_size_for(&fso);
+        let file_name = fso.name()?;
+        let short_name = short_names.generate(&file_name);
+
+        Ok(FileBothDirectoryInformation::new(
+            last_modified,
+            last_modified,
+            last_modified,
+            last_modified,
+            i64::try_from(fso.size)?,
+            file_attributes,
+            file_name,
+            &short_name,
+            ea_size,
+        ))
+    }
+
+    fn size(&self) -> u32 {
+        Self::BASE_SIZE + self.file_name_length
+    }
+}
+
+/// 2.4.14 FileFullDirectoryInformation
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-fscc/e8d926d1-3a22-4654-be9c-58317a85540b
+#[derive(Debug, Clone)]
+struct FileFullDirectoryInformation {
+    next_entry_offset: u32,
+    file_index: u32,
+    creation_time: i64,
+    last_access_time: i64,
+    last_write_time: i64,
+    change_time: i64,
+    end_of_file: i64,
+    allocation_size: i64,
+    file_attributes: flags::FileAttributes,
+    file_name_length: u32,
+    ea_size: u32,
+    file_name: String,
+}
+
+impl FileFullDirectoryInformation {
+    /// Base size of the FileFullDirectoryInformation, not accounting for variably sized file_name.
+    /// Note that file_name's size should be calculated as if it were a Unicode string.
+    const BASE_SIZE: u32 = (4 * U32_SIZE) + FILE_ATTR_SIZE + (6 * I64_SIZE); // 68
+
+    fn new(
+        creation_time: i64,
+        last_access_time: i64,
+        last_write_time: i64,
+        change_time: i64,
+        file_size: i64,
+        file_attributes: flags::FileAttributes,
+        file_name: String,
+        ea_size: u32,
+    ) -> Self {
+        // Default field values taken from
+        // https://github.com/FreeRDP/FreeRDP/blob/511444a65e7aa2f537c5e531fa68157a50c1bd4d/channels/drive/client/drive_file.c#L871
+        Self {
+            next_entry_offset: 0,
+            file_index: 0,
+            creation_time,
+            last_access_time,
+            last_write_time,
+            change_time,
+            end_of_file: file_size,
+            allocation_size: file_size,
+            file_attributes,
+            file_name_length: util::unicode_size(&file_name, false),
+            ea_size,
+            file_name,
+        }
+    }
+
+    fn encode(&self) -> RdpResult<Vec<u8>> {
+        let mut w = vec![];
+        w.write_u32::<LittleEndian>(self.next_entry_offset)?;
+        w.write_u32::<LittleEndian>(self.file_index)?;
+        w.write_i64::<LittleEndian>(self.creation_time)?;
+        w.write_i64::<LittleEndian>(self.last_access_time)?;
+        w.write_i64::<LittleEndian>(self.last_write_time)?;
+        w.write_i64::<LittleEndian>(self.change_time)?;
+        w.write_i64::<LittleEndian>(self.end_of_file)?;
+        w.write_i64::<LittleEndian>(self.allocation_size)?;
+        w.write_u32::<LittleEndian>(self.file_attributes.bits())?;
+        w.write_u32::<LittleEndian>(self.file_name_length)?;
+        w.write_u32::<LittleEndian>(self.ea_size)?;
+        // When working with this field, use file_name_length to determine the length of the file name rather
+        // than assuming the presence of a trailing null delimiter. Dot directory names are valid for this field.
+        w.extend_from_slice(&util::to_unicode(&self.file_name, false));
+        Ok(w)
+    }
+
+    fn from(fso: FileSystemObject) -> RdpResult<Self> {
+        let file_attributes = file_attributes_for(fso.file_type);
 
-    fn encode(&self) -> RdpResult<Vec<u8>> {
-        let mut w = vec![];
-        w.write_u32::<LittleEndian>(self.file_attributes.bits())?;
-        w.write_u32::<LittleEndian>(self.reparse_tag)?;
-        Ok(w)
+        let last_modified = to_windows_time(fso.last_modified)?;
+        let ea_size = ea_size_for(&fso);
+
+        Ok(Self::new(
+            last_modified,
+            last_modified,
+            last_modified,
+            last_modified,
+            i64::try_from(fso.size)?,
+            file_attributes,
+            fso.name()?,
+            ea_size,
+        ))
     }
 
     fn size(&self) -> u32 {
-        Self::BASE_SIZE
+        Self::BASE_SIZE + self.file_name_length
     }
 }
 
-/// 2.1.8 Boolean
-/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-fscc/8ce7b38c-d3cc-415d-ab39-944000ea77ff
-#[derive(Debug, FromPrimitive, ToPrimitive, PartialEq, Clone)]
-#[repr(u8)]
-enum Boolean {
-    True = 1,
-    False = 0,
-}
-
-/// 2.4.8 FileBothDirectoryInformation
-/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-fscc/270df317-9ba5-4ccb-ba00-8d22be139bc5
+/// 2.4.17 FileIdBothDirectoryInformation
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-fscc/08947df3-5846-4d0b-8e65-02d7f51e0278
+///
+/// Identical to FileBothDirectoryInformation except that it also carries a FileId, which
+/// applications use for hard-link detection and de-duplication across directory enumerations.
 #[derive(Debug, Clone)]
-struct FileBothDirectoryInformation {
+struct FileIdBothDirectoryInformation {
     next_entry_offset: u32,
     file_index: u32,
     creation_time: i64,
@@ -1379,16 +2531,17 @@ struct FileBothDirectoryInformation {
     file_name_length: u32,
     ea_size: u32,
     short_name_length: i8,
-    // reserved: u8: MUST NOT be added,
-    // see https://github.com/FreeRDP/FreeRDP/blob/511444a65e7aa2f537c5e531fa68157a50c1bd4d/channels/drive/client/drive_file.c#L907
+    // reserved: u8: MUST NOT be added, see FileBothDirectoryInformation::encode above.
     short_name: [u8; 24], // 24 bytes
+    // Reserved2 (2 bytes): MUST NOT be added, for the same reason as the reserved byte above.
+    file_id: i64,
     file_name: String,
 }
 
-impl FileBothDirectoryInformation {
-    /// Base size of the FileBothDirectoryInformation, not accounting for variably sized file_name.
-    /// Note that file_name's size should be calculated as if it were a Unicode string.
-    const BASE_SIZE: u32 = (4 * U32_SIZE) + FILE_ATTR_SIZE + (6 * I64_SIZE) + I8_SIZE + 24; // 93
+impl FileIdBothDirectoryInformation {
+    /// Base size of the FileIdBothDirectoryInformation, not accounting for variably sized
+    /// file_name. Note that file_name's size should be calculated as if it were a Unicode string.
+    const BASE_SIZE: u32 = (4 * U32_SIZE) + FILE_ATTR_SIZE + (6 * I64_SIZE) + I8_SIZE + 24 + I64_SIZE; // 101
 
     fn new(
         creation_time: i64,
@@ -1398,9 +2551,15 @@ impl FileBothDirectoryInformation {
         file_size: i64,
         file_attributes: flags::FileAttributes,
         file_name: String,
+        short_name: &str,
+        file_id: i64,
+        ea_size: u32,
     ) -> Self {
-        // Default field values taken from
-        // https://github.com/FreeRDP/FreeRDP/blob/511444a65e7aa2f537c5e531fa68157a50c1bd4d/channels/drive/client/drive_file.c#L871
+        let short_name_unicode = util::to_unicode(short_name, false);
+        let mut short_name_buf = [0; 24];
+        let n = short_name_unicode.len().min(short_name_buf.len());
+        short_name_buf[..n].copy_from_slice(&short_name_unicode[..n]);
+
         Self {
             next_entry_offset: 0,
             file_index: 0,
@@ -1412,9 +2571,10 @@ impl FileBothDirectoryInformation {
             allocation_size: file_size,
             file_attributes,
             file_name_length: util::unicode_size(&file_name, false),
-            ea_size: 0,
-            short_name_length: 0,
-            short_name: [0; 24],
+            ea_size,
+            short_name_length: n as i8,
+            short_name: short_name_buf,
+            file_id,
             file_name,
         }
     }
@@ -1435,29 +2595,32 @@ impl FileBothDirectoryInformation {
         w.write_i8(self.short_name_length)?;
         // reserved u8, MUST NOT be added!
         w.extend_from_slice(&self.short_name);
-        // When working with this field, use file_name_length to determine the length of the file name rather
-        // than assuming the presence of a trailing null delimiter. Dot directory names are valid for this field.
+        // Reserved2, MUST NOT be added!
+        w.write_i64::<LittleEndian>(self.file_id)?;
         w.extend_from_slice(&util::to_unicode(&self.file_name, false));
         Ok(w)
     }
 
-    fn from(fso: FileSystemObject) -> RdpResult<Self> {
-        let file_attributes = if fso.file_type == FileType::Directory {
-            flags::FileAttributes::FILE_ATTRIBUTE_DIRECTORY
-        } else {
-            flags::FileAttributes::FILE_ATTRIBUTE_NORMAL
-        };
+    fn from(fso: FileSystemObject, short_names: &mut ShortNameTable) -> RdpResult<Self> {
+        let file_attributes = file_attributes_for(fso.file_type);
 
-        let last_modified = to_windows_time(fso.last_modified);
+        let last_modified = to_windows_time(fso.last_modified)?;
+        let ea_size = ea_size_for(&fso);
+        let file_name = fso.name()?;
+        let short_name = short_names.generate(&file_name);
+        let file_id = fso.stable_file_id() as i64;
 
-        Ok(FileBothDirectoryInformation::new(
+        Ok(Self::new(
             last_modified,
             last_modified,
             last_modified,
             last_modified,
             i64::try_from(fso.size)?,
             file_attributes,
-            fso.name()?,
+            file_name,
+            &short_name,
+            file_id,
+            ea_size,
         ))
     }
 
@@ -1466,10 +2629,13 @@ impl FileBothDirectoryInformation {
     }
 }
 
-/// 2.4.14 FileFullDirectoryInformation
-/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-fscc/e8d926d1-3a22-4654-be9c-58317a85540b
+/// 2.4.18 FileIdFullDirectoryInformation
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-fscc/01b20058-91c8-43a4-8057-f859cd3836ac
+///
+/// Identical to FileFullDirectoryInformation except that it also carries a FileId, which
+/// applications use for hard-link detection and de-duplication across directory enumerations.
 #[derive(Debug, Clone)]
-struct FileFullDirectoryInformation {
+struct FileIdFullDirectoryInformation {
     next_entry_offset: u32,
     file_index: u32,
     creation_time: i64,
@@ -1481,13 +2647,14 @@ struct FileFullDirectoryInformation {
     file_attributes: flags::FileAttributes,
     file_name_length: u32,
     ea_size: u32,
+    file_id: i64,
     file_name: String,
 }
 
-impl FileFullDirectoryInformation {
-    /// Base size of the FileFullDirectoryInformation, not accounting for variably sized file_name.
-    /// Note that file_name's size should be calculated as if it were a Unicode string.
-    const BASE_SIZE: u32 = (4 * U32_SIZE) + FILE_ATTR_SIZE + (6 * I64_SIZE); // 68
+impl FileIdFullDirectoryInformation {
+    /// Base size of the FileIdFullDirectoryInformation, not accounting for variably sized
+    /// file_name. Note that file_name's size should be calculated as if it were a Unicode string.
+    const BASE_SIZE: u32 = (4 * U32_SIZE) + FILE_ATTR_SIZE + (6 * I64_SIZE) + I64_SIZE; // 76
 
     fn new(
         creation_time: i64,
@@ -1497,9 +2664,9 @@ impl FileFullDirectoryInformation {
         file_size: i64,
         file_attributes: flags::FileAttributes,
         file_name: String,
+        file_id: i64,
+        ea_size: u32,
     ) -> Self {
-        // Default field values taken from
-        // https://github.com/FreeRDP/FreeRDP/blob/511444a65e7aa2f537c5e531fa68157a50c1bd4d/channels/drive/client/drive_file.c#L871
         Self {
             next_entry_offset: 0,
             file_index: 0,
@@ -1511,7 +2678,8 @@ impl FileFullDirectoryInformation {
             allocation_size: file_size,
             file_attributes,
             file_name_length: util::unicode_size(&file_name, false),
-            ea_size: 0,
+            ea_size,
+            file_id,
             file_name,
         }
     }
@@ -1529,20 +2697,17 @@ impl FileFullDirectoryInformation {
         w.write_u32::<LittleEndian>(self.file_attributes.bits())?;
         w.write_u32::<LittleEndian>(self.file_name_length)?;
         w.write_u32::<LittleEndian>(self.ea_size)?;
-        // When working with this field, use file_name_length to determine the length of the file name rather
-        // than assuming the presence of a trailing null delimiter. Dot directory names are valid for this field.
+        w.write_i64::<LittleEndian>(self.file_id)?;
         w.extend_from_slice(&util::to_unicode(&self.file_name, false));
         Ok(w)
     }
 
     fn from(fso: FileSystemObject) -> RdpResult<Self> {
-        let file_attributes = if fso.file_type == FileType::Directory {
-            flags::FileAttributes::FILE_ATTRIBUTE_DIRECTORY
-        } else {
-            flags::FileAttributes::FILE_ATTRIBUTE_NORMAL
-        };
+        let file_attributes = file_attributes_for(fso.file_type);
 
-        let last_modified = to_windows_time(fso.last_modified);
+        let last_modified = to_windows_time(fso.last_modified)?;
+        let file_id = fso.stable_file_id() as i64;
+        let ea_size = ea_size_for(&fso);
 
         Ok(Self::new(
             last_modified,
@@ -1552,6 +2717,8 @@ impl FileFullDirectoryInformation {
             i64::try_from(fso.size)?,
             file_attributes,
             fso.name()?,
+            file_id,
+            ea_size,
         ))
     }
 
@@ -1560,6 +2727,134 @@ impl FileFullDirectoryInformation {
     }
 }
 
+/// A single extended attribute, as returned by NtQueryEaFile and carried by the entries of a
+/// FileFullEaInformation. Packs to the wire as NextEntryOffset(4) + Flags(1) + EaNameLength(1) +
+/// EaValueLength(2) + EaName (NUL-terminated ASCII) + EaValue (raw bytes).
+#[derive(Debug, Clone)]
+struct EaEntry {
+    flags: u8,
+    name: String,
+    value: Vec<u8>,
+}
+
+impl EaEntry {
+    /// NextEntryOffset(4) + Flags(1) + EaNameLength(1) + EaValueLength(2), not accounting for the
+    /// variably sized EaName (plus its terminating NUL) and EaValue.
+    const BASE_SIZE: u32 = U32_SIZE + U8_SIZE + U8_SIZE + 2; // 8
+
+    /// NT requires an EA entry's NextEntryOffset to keep its successor DWORD-aligned, unlike the
+    /// directory information classes above, which require 8-byte (QWORD) alignment -- hence this
+    /// doesn't reuse FileInformationClass::ENTRY_ALIGNMENT.
+    const ALIGNMENT: u32 = 4;
+
+    fn size(&self) -> u32 {
+        Self::BASE_SIZE + (self.name.len() as u32) + 1 /* NUL */ + (self.value.len() as u32)
+    }
+
+    fn aligned_size(&self) -> u32 {
+        let size = self.size();
+        (size + (Self::ALIGNMENT - 1)) / Self::ALIGNMENT * Self::ALIGNMENT
+    }
+
+    fn encode(&self, next_entry_offset: u32) -> RdpResult<Vec<u8>> {
+        let mut w = vec![];
+        w.write_u32::<LittleEndian>(next_entry_offset)?;
+        w.write_u8(self.flags)?;
+        w.write_u8(self.name.len() as u8)?;
+        w.write_u16::<LittleEndian>(self.value.len() as u16)?;
+        w.extend_from_slice(self.name.as_bytes());
+        w.write_u8(0)?; // NUL terminator
+        w.extend_from_slice(&self.value);
+        Ok(w)
+    }
+}
+
+/// 2.4.15 FileFullEaInformation
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-fscc/49355a50-4fa7-4d8c-b523-e973f33846c1
+///
+/// Reports every extended attribute FileSystemObject::extended_attributes carries for a file.
+/// Unlike the directory information classes above, a single FileFullEaInformation is the complete
+/// answer to one IRP_MJ_QUERY_INFORMATION request, so the NextEntryOffset chaining between its
+/// entries happens inside encode() rather than via FileInformationClass::encode_batch.
+#[derive(Debug, Clone)]
+struct FileFullEaInformation {
+    entries: Vec<EaEntry>,
+}
+
+impl FileFullEaInformation {
+    fn from(fso: &FileSystemObject) -> Self {
+        Self {
+            entries: fso
+                .extended_attributes
+                .iter()
+                .map(|(name, value)| EaEntry {
+                    flags: 0,
+                    name: name.clone(),
+                    value: value.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    fn size(&self) -> u32 {
+        let last = self.entries.len().saturating_sub(1);
+        self.entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                if i == last {
+                    entry.size()
+                } else {
+                    entry.aligned_size()
+                }
+            })
+            .sum()
+    }
+
+    fn encode(&self) -> RdpResult<Vec<u8>> {
+        let mut w = vec![];
+        let last = self.entries.len().saturating_sub(1);
+        for (i, entry) in self.entries.iter().enumerate() {
+            let next_entry_offset = if i == last { 0 } else { entry.aligned_size() };
+            let start = w.len();
+            w.extend_from_slice(&entry.encode(next_entry_offset)?);
+            if i != last {
+                w.resize(start + next_entry_offset as usize, 0);
+            }
+        }
+        Ok(w)
+    }
+}
+
+/// 2.4.16 FileGetEaInformation
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-fscc/db8fcd7e-9b80-4007-9d5d-1d3dbb010e5e
+///
+/// The QueryBuffer of a Server Drive Query Information Request whose FileInformationClass is
+/// FileFullEaInformation: names a single extended attribute the caller wants returned instead of
+/// every attribute on the file (see ServerDriveQueryInformationRequest::ea_name). We don't filter
+/// the FileFullEaInformation response by name, but the structure still needs a decode path so the
+/// payload cursor lands in the right place.
+#[derive(Debug, Clone)]
+struct FileGetEaInformation {
+    ea_name: String,
+}
+
+impl FileGetEaInformation {
+    fn decode(payload: &mut Payload) -> RdpResult<Self> {
+        // NextEntryOffset is only meaningful when chaining multiple FileGetEaInformation entries
+        // in one QueryBuffer to ask for several named attributes at once; we only support a
+        // single entry, so it's read and discarded.
+        payload.read_u32::<LittleEndian>()?;
+        let ea_name_length = payload.read_u8()? as usize;
+        payload.ensure_remaining(ea_name_length as u64)?;
+        let mut ea_name = vec![0; ea_name_length];
+        payload.read_exact(&mut ea_name)?;
+        Ok(Self {
+            ea_name: String::from_utf8_lossy(&ea_name).into_owned(),
+        })
+    }
+}
+
 // 2.4.13 FileEndOfFileInformation
 // https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-fscc/75241cca-3167-472f-8058-a52d77c6bb17
 #[derive(Debug, Clone)]
@@ -1646,6 +2941,7 @@ impl FileRenameInformation {
         payload.read_u8()?;
 
         let file_name_length = payload.read_u32::<LittleEndian>()?;
+        payload.ensure_remaining(file_name_length as u64)?;
         let mut file_name = vec![0u8; file_name_length as usize];
         payload.read_exact(&mut file_name)?;
         let file_name = WindowsPath::from(util::from_unicode(file_name)?);
@@ -1794,13 +3090,9 @@ impl FileDirectoryInformation {
     }
 
     fn from(fso: FileSystemObject) -> RdpResult<Self> {
-        let file_attributes = if fso.file_type == FileType::Directory {
-            flags::FileAttributes::FILE_ATTRIBUTE_DIRECTORY
-        } else {
-            flags::FileAttributes::FILE_ATTRIBUTE_NORMAL
-        };
+        let file_attributes = file_attributes_for(fso.file_type);
 
-        let last_modified = to_windows_time(fso.last_modified);
+        let last_modified = to_windows_time(fso.last_modified)?;
 
         Ok(Self::new(
             last_modified,
@@ -1861,10 +3153,11 @@ impl FileFsVolumeInformation {
     /// 1 i64, 2 u32, 1 Boolean
     const BASE_SIZE: u32 = I64_SIZE + (2 * U32_SIZE) + BOOL_SIZE; // 17
 
-    fn new(volume_creation_time: i64) -> Self {
-        // volume_label can just be something we make up
+    /// Builds a FileFsVolumeInformation for volume_creation_time, using volume_label if the
+    /// browser reported one for the backing volume, or else falling back to a made-up label.
+    fn new(volume_creation_time: i64, volume_label: Option<String>) -> Self {
         // https://github.com/FreeRDP/FreeRDP/blob/511444a65e7aa2f537c5e531fa68157a50c1bd4d/channels/drive/client/drive_main.c#L446
-        let volume_label = "TELEPORT".to_string();
+        let volume_label = volume_label.unwrap_or_else(|| "TELEPORT".to_string());
 
         Self {
             volume_creation_time,
@@ -1907,7 +3200,6 @@ struct FileFsSizeInformation {
     bytes_per_sector: u32,
 }
 
-#[allow(dead_code)]
 impl FileFsSizeInformation {
     const BASE_SIZE: u32 = (2 * I64_SIZE) + (2 * U32_SIZE);
 
@@ -1924,6 +3216,36 @@ impl FileFsSizeInformation {
         }
     }
 
+    /// Builds a FileFsSizeInformation from the real byte counts and volume geometry reported by a
+    /// SharedDirectoryFreeSpaceResponse. The alloc unit counts are the byte counts divided by the
+    /// reported allocation unit size (bytes_per_sector * sectors_per_alloc_unit), rounded down,
+    /// rather than derived from some invented disk geometry. A reported bytes_per_sector or
+    /// sectors_per_alloc_unit of 0 is treated as 1.
+    fn from_bytes(
+        bytes_total: u64,
+        bytes_free: u64,
+        bytes_per_sector: u32,
+        sectors_per_alloc_unit: u32,
+    ) -> Self {
+        let bytes_per_sector = if bytes_per_sector == 0 {
+            1
+        } else {
+            bytes_per_sector
+        };
+        let sectors_per_alloc_unit = if sectors_per_alloc_unit == 0 {
+            1
+        } else {
+            sectors_per_alloc_unit
+        };
+        let bytes_per_alloc_unit = bytes_per_sector as u64 * sectors_per_alloc_unit as u64;
+        Self {
+            total_alloc_units: (bytes_total / bytes_per_alloc_unit) as i64,
+            available_alloc_units: (bytes_free / bytes_per_alloc_unit) as i64,
+            sectors_per_alloc_unit,
+            bytes_per_sector,
+        }
+    }
+
     fn encode(&self) -> RdpResult<Vec<u8>> {
         let mut w = vec![];
         w.write_i64::<LittleEndian>(self.total_alloc_units)?;
@@ -1996,7 +3318,6 @@ struct FileFsFullSizeInformation {
     bytes_per_sector: u32,
 }
 
-#[allow(dead_code)]
 impl FileFsFullSizeInformation {
     const BASE_SIZE: u32 = (3 * I64_SIZE) + (2 * U32_SIZE);
 
@@ -2014,6 +3335,39 @@ impl FileFsFullSizeInformation {
         }
     }
 
+    /// Builds a FileFsFullSizeInformation from the real byte counts and volume geometry reported
+    /// by a SharedDirectoryFreeSpaceResponse. The browser doesn't model a separate per-caller
+    /// quota for a shared directory, so caller_available_alloc_units and
+    /// actual_available_alloc_units both come from the same bytes_free figure. The alloc unit
+    /// counts are the byte counts divided by the reported allocation unit size
+    /// (bytes_per_sector * sectors_per_alloc_unit), rounded down; a reported 0 for either
+    /// geometry value is treated as 1.
+    fn from_bytes(
+        bytes_total: u64,
+        bytes_free: u64,
+        bytes_per_sector: u32,
+        sectors_per_alloc_unit: u32,
+    ) -> Self {
+        let bytes_per_sector = if bytes_per_sector == 0 {
+            1
+        } else {
+            bytes_per_sector
+        };
+        let sectors_per_alloc_unit = if sectors_per_alloc_unit == 0 {
+            1
+        } else {
+            sectors_per_alloc_unit
+        };
+        let bytes_per_alloc_unit = bytes_per_sector as u64 * sectors_per_alloc_unit as u64;
+        Self {
+            total_alloc_units: (bytes_total / bytes_per_alloc_unit) as i64,
+            caller_available_alloc_units: (bytes_free / bytes_per_alloc_unit) as i64,
+            actual_available_alloc_units: (bytes_free / bytes_per_alloc_unit) as i64,
+            sectors_per_alloc_unit,
+            bytes_per_sector,
+        }
+    }
+
     fn encode(&self) -> RdpResult<Vec<u8>> {
         let mut w = vec![];
         w.write_i64::<LittleEndian>(self.total_alloc_units)?;
@@ -2100,15 +3454,11 @@ impl ClientDriveQueryInformationResponse {
                     Some(FileBasicInformation::BASE_SIZE),
                     Some(FileInformationClass::FileBasicInformation(
                         FileBasicInformation {
-                            creation_time: to_windows_time(file.fso.last_modified),
-                            last_access_time: to_windows_time(file.fso.last_modified),
-                            last_write_time: to_windows_time(file.fso.last_modified),
-                            change_time: to_windows_time(file.fso.last_modified),
-                            file_attributes: if file.fso.file_type == FileType::File {
-                                flags::FileAttributes::FILE_ATTRIBUTE_NORMAL
-                            } else {
-                                flags::FileAttributes::FILE_ATTRIBUTE_DIRECTORY
-                            },
+                            creation_time: to_windows_time(file.fso.last_modified)?,
+                            last_access_time: to_windows_time(file.fso.last_modified)?,
+                            last_write_time: to_windows_time(file.fso.last_modified)?,
+                            change_time: to_windows_time(file.fso.last_modified)?,
+                            file_attributes: file_attributes_for(file.fso.file_type),
                         },
                     )),
                 ),
@@ -2124,10 +3474,10 @@ impl ClientDriveQueryInformationResponse {
                             } else {
                                 Boolean::False
                             },
-                            directory: if file.fso.file_type == FileType::File {
-                                Boolean::False
-                            } else {
+                            directory: if file.fso.file_type == FileType::Directory {
                                 Boolean::True
+                            } else {
+                                Boolean::False
                             },
                         },
                     )),
@@ -2136,15 +3486,74 @@ impl ClientDriveQueryInformationResponse {
                     Some(FileAttributeTagInformation::BASE_SIZE),
                     Some(FileInformationClass::FileAttributeTagInformation(
                         FileAttributeTagInformation {
-                            file_attributes: if file.fso.file_type == FileType::File {
-                                flags::FileAttributes::FILE_ATTRIBUTE_NORMAL
+                            file_attributes: file_attributes_for(file.fso.file_type),
+                            reparse_tag: if file.fso.file_type == FileType::Symlink {
+                                IO_REPARSE_TAG_SYMLINK
                             } else {
-                                flags::FileAttributes::FILE_ATTRIBUTE_DIRECTORY
+                                0
                             },
-                            reparse_tag: 0,
                         },
                     )),
                 ),
+                FileInformationClassLevel::FileFullEaInformation => {
+                    // req.ea_name, when present, names the single extended attribute the caller
+                    // asked for; we don't support filtering by name (see
+                    // FileGetEaInformation::decode), so every attribute is always returned.
+                    let ea_info = FileFullEaInformation::from(&file.fso);
+                    (
+                        Some(ea_info.size()),
+                        Some(FileInformationClass::FileFullEaInformation(ea_info)),
+                    )
+                }
+                FileInformationClassLevel::FileNetworkOpenInformation => {
+                    let info = FileNetworkOpenInformation {
+                        creation_time: to_windows_time(file.fso.last_modified)?,
+                        last_access_time: to_windows_time(file.fso.last_modified)?,
+                        last_write_time: to_windows_time(file.fso.last_modified)?,
+                        change_time: to_windows_time(file.fso.last_modified)?,
+                        allocation_size: file.fso.size as i64,
+                        end_of_file: file.fso.size as i64,
+                        file_attributes: file_attributes_for(file.fso.file_type),
+                    };
+                    (
+                        Some(info.size()),
+                        Some(FileInformationClass::FileNetworkOpenInformation(info)),
+                    )
+                }
+                FileInformationClassLevel::FileAllInformation => {
+                    let basic_information = FileBasicInformation {
+                        creation_time: to_windows_time(file.fso.last_modified)?,
+                        last_access_time: to_windows_time(file.fso.last_modified)?,
+                        last_write_time: to_windows_time(file.fso.last_modified)?,
+                        change_time: to_windows_time(file.fso.last_modified)?,
+                        file_attributes: file_attributes_for(file.fso.file_type),
+                    };
+                    let standard_information = FileStandardInformation {
+                        allocation_size: file.fso.size as i64,
+                        end_of_file: file.fso.size as i64,
+                        number_of_links: 0,
+                        delete_pending: if file.delete_pending {
+                            Boolean::True
+                        } else {
+                            Boolean::False
+                        },
+                        directory: if file.fso.file_type == FileType::Directory {
+                            Boolean::True
+                        } else {
+                            Boolean::False
+                        },
+                    };
+                    let info = FileAllInformation::new(
+                        basic_information,
+                        standard_information,
+                        file.fso.stable_file_id() as i64,
+                        file.fso.name()?,
+                    );
+                    (
+                        Some(info.size()),
+                        Some(FileInformationClass::FileAllInformation(info)),
+                    )
+                }
                 _ => {
                     return Err(not_implemented_error(&format!(
                         "received unsupported FileInformationClass: {:?}",
@@ -2226,7 +3635,6 @@ impl DeviceCloseResponse {
 /// 2.2.3.3.11 Server Drive NotifyChange Directory Request (DR_DRIVE_NOTIFY_CHANGE_DIRECTORY_REQ)
 /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpefs/ed05e73d-e53e-4261-a1e1-365a70ba6512
 #[derive(Debug)]
-#[allow(dead_code)]
 struct ServerDriveNotifyChangeDirectoryRequest {
     /// The MajorFunction field in the DR_DEVICE_IOREQUEST header MUST be set to IRP_MJ_DIRECTORY_CONTROL,
     /// and the MinorFunction field MUST be set to IRP_MN_NOTIFY_CHANGE_DIRECTORY.
@@ -2237,7 +3645,6 @@ struct ServerDriveNotifyChangeDirectoryRequest {
     // Padding (27 bytes):  An array of 27 bytes. This field is unused and MUST be ignored.
 }
 
-#[allow(dead_code)]
 impl ServerDriveNotifyChangeDirectoryRequest {
     fn decode(device_io_request: DeviceIoRequest, payload: &mut Payload) -> RdpResult<Self> {
         let invalid_flags =
@@ -2281,6 +3688,12 @@ impl DeviceReadRequest {
 
 /// 2.2.1.5.3 Device Read Response (DR_READ_RSP)
 /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpefs/d35d3f91-fc5b-492b-80be-47f483ad1dc9
+///
+/// `read_data` can be up to `max_tdp_chunk_size` bytes (see dir.rs), which is often larger than
+/// the virtual channel's negotiated chunk size. This struct's encoding is the full, unfragmented
+/// DR_READ_RSP; splitting it into CHANNEL_PDU_HEADER-framed fragments with
+/// CHANNEL_FLAG_FIRST/CHANNEL_FLAG_LAST is handled generically for every outgoing RDPDR PDU by
+/// `Client::add_headers_and_chunkify`, so there's nothing read-specific to do here.
 struct DeviceReadResponse {
     /// The CompletionId field of this header MUST match a Device I/O Request (section 2.2.1.4) message that had the MajorFunction field set to IRP_MJ_READ.
     device_io_reply: DeviceIoResponse,
@@ -2353,6 +3766,10 @@ impl std::fmt::Debug for DeviceWriteRequest {
 }
 
 impl DeviceWriteRequest {
+    /// By the time `payload` reaches this decoder, `self.vchan.read` (see `read_and_reply`) has
+    /// already reassembled every CHANNEL_PDU fragment of the underlying DR_WRITE_REQ into one
+    /// contiguous buffer, so a write_data spanning several virtual channel chunks still shows up
+    /// here as a single, complete slice and `read_exact` can't short-read on a fragment boundary.
     fn decode(device_io_request: DeviceIoRequest, payload: &mut Payload) -> RdpResult<Self> {
         let length = payload.read_u32::<LittleEndian>()?;
         let offset = payload.read_u64::<LittleEndian>()?;
@@ -2361,6 +3778,7 @@ impl DeviceWriteRequest {
         // must ignore it
         payload.seek(SeekFrom::Current(20))?;
 
+        payload.ensure_remaining(length as u64)?;
         let mut write_data = vec![0; length as usize];
         payload.read_exact(&mut write_data)?;
 
@@ -2398,8 +3816,7 @@ impl DeviceWriteResponse {
         let mut w = vec![];
         w.extend_from_slice(&self.device_io_reply.encode()?);
         w.write_u32::<LittleEndian>(self.length)?;
-        // 1 byte padding
-        w.write_u32::<LittleEndian>(0)?;
+        w.write_u8(0)?; // 1 byte padding
         Ok(w)
     }
 }
@@ -2428,8 +3845,7 @@ impl ClientDriveSetInformationResponse {
         let mut w = vec![];
         w.extend_from_slice(&self.device_io_reply.encode()?);
         w.write_u32::<LittleEndian>(self.length)?;
-        // 1 byte padding
-        w.write_u32::<LittleEndian>(0)?;
+        w.write_u8(0)?; // 1 byte padding
         Ok(w)
     }
 }
@@ -2468,6 +3884,7 @@ impl ServerDriveSetInformationRequest {
 
         // There is a padding of 24 bytes between offset and write data so we
         // must ignore it
+        payload.ensure_remaining(24)?;
         payload.seek(SeekFrom::Current(24))?;
 
         let set_buffer =
@@ -2539,6 +3956,7 @@ impl ServerDriveQueryDirectoryRequest {
             payload.read_exact(&mut padding)?;
 
             // TODO(isaiah): make a from_unicode_exact
+            payload.ensure_remaining(path_length as u64)?;
             let mut path_as_vec = vec![0u8; path_length.try_into().unwrap()];
             payload.read_exact(&mut path_as_vec)?;
             path = WindowsPath::from(util::from_unicode(path_as_vec)?);
@@ -2565,8 +3983,9 @@ struct ClientDriveQueryDirectoryResponse {
     /// Specifies the number of bytes in the Buffer field.
     length: u32,
     /// The content of this field is based on the value of the FileInformationClass field in the Server Drive Query Directory Request
-    /// message, which determines the different structures that MUST be contained in the Buffer field.
-    buffer: Option<FileInformationClass>,
+    /// message, which determines the different structures that MUST be contained in the Buffer field. Holds every directory entry
+    /// batched into this single response (see `FileCacheObject::next_batch`), chained via next_entry_offset.
+    buffer: Vec<FileInformationClass>,
     // Padding (1 byte): This field is unused and MUST be ignored.
 }
 
@@ -2574,26 +3993,26 @@ impl ClientDriveQueryDirectoryResponse {
     fn new(
         device_io_request: &DeviceIoRequest,
         io_status: NTSTATUS,
-        buffer: Option<FileInformationClass>,
+        buffer: Vec<FileInformationClass>,
     ) -> RdpResult<Self> {
         // This match block ensures that the passed parameters are in a configuration that's
         // explicitly supported by the length calculation (below) and the self.encode() method.
         match io_status {
             NTSTATUS::STATUS_SUCCESS => {
-                if buffer.is_none() {
+                if buffer.is_empty() {
                     return Err(invalid_data_error(
                         "a ClientDriveQueryDirectoryResponse with NTSTATUS::STATUS_SUCCESS \
-                        should have Some(FileInformationClass) buffer, got None",
+                        should have a non-empty buffer, got none",
                     ));
                 }
             }
             NTSTATUS::STATUS_NOT_SUPPORTED
             | NTSTATUS::STATUS_NO_MORE_FILES
             | NTSTATUS::STATUS_UNSUCCESSFUL => {
-                if buffer.is_some() {
+                if !buffer.is_empty() {
                     return Err(invalid_data_error(&format!(
                         "a ClientDriveQueryDirectoryResponse with NTSTATUS = {:?} \
-                        should have a None buffer, got {:?}",
+                        should have an empty buffer, got {:?}",
                         io_status, buffer,
                     )));
                 }
@@ -2606,24 +4025,19 @@ impl ClientDriveQueryDirectoryResponse {
             }
         }
 
-        let length = match buffer {
-            Some(ref fs_information_class) => match fs_information_class {
-                FileInformationClass::FileBothDirectoryInformation(fs_info_class) => {
-                    fs_info_class.size()
-                }
-                FileInformationClass::FileFullDirectoryInformation(fs_info_class) => {
-                    fs_info_class.size()
-                }
-                FileInformationClass::FileNamesInformation(fs_info_class) => fs_info_class.size(),
-                FileInformationClass::FileDirectoryInformation(fs_info_class) => {
-                    fs_info_class.size()
-                }
-                _ => {
-                    return Err(not_implemented_error(&format!("ClientDriveQueryDirectoryResponse not implemented for fs_information_class {:?}", fs_information_class)));
-                }
-            },
-            None => 0,
-        };
+        for fs_information_class in &buffer {
+            if !matches!(
+                fs_information_class,
+                FileInformationClass::FileBothDirectoryInformation(_)
+                    | FileInformationClass::FileFullDirectoryInformation(_)
+                    | FileInformationClass::FileNamesInformation(_)
+                    | FileInformationClass::FileDirectoryInformation(_)
+            ) {
+                return Err(not_implemented_error(&format!("ClientDriveQueryDirectoryResponse not implemented for fs_information_class {:?}", fs_information_class)));
+            }
+        }
+
+        let length = FileInformationClass::batch_size(&buffer);
 
         Ok(Self {
             device_io_reply: DeviceIoResponse::new(
@@ -2639,9 +4053,7 @@ impl ClientDriveQueryDirectoryResponse {
         let mut w = vec![];
         w.extend_from_slice(&self.device_io_reply.encode()?);
         w.write_u32::<LittleEndian>(self.length)?;
-        if let Some(buffer) = &self.buffer {
-            w.extend_from_slice(&buffer.encode()?);
-        }
+        w.extend_from_slice(&FileInformationClass::encode_batch(&self.buffer)?);
         if self.device_io_reply.io_status
             == NTSTATUS::to_u32(&NTSTATUS::STATUS_NO_MORE_FILES).unwrap()
         {
@@ -2653,6 +4065,90 @@ impl ClientDriveQueryDirectoryResponse {
     }
 }
 
+/// 2.6.1 FILE_NOTIFY_INFORMATION
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-fscc/634043d7-7b39-47e9-9e26-bda64685e4c9
+#[derive(Debug)]
+struct FileNotifyInformation {
+    next_entry_offset: u32,
+    action: u32,
+    file_name_length: u32,
+    file_name: String,
+}
+
+impl FileNotifyInformation {
+    /// Base size of the FileNotifyInformation, not accounting for the variably sized file_name.
+    const BASE_SIZE: u32 = 3 * U32_SIZE;
+
+    fn new(action: u32, file_name: String) -> Self {
+        Self {
+            next_entry_offset: 0,
+            action,
+            file_name_length: util::unicode_size(&file_name, false),
+            file_name,
+        }
+    }
+
+    fn size(&self) -> u32 {
+        Self::BASE_SIZE + self.file_name_length
+    }
+
+    fn encode(&self) -> RdpResult<Vec<u8>> {
+        let mut w = vec![];
+        w.write_u32::<LittleEndian>(self.next_entry_offset)?;
+        w.write_u32::<LittleEndian>(self.action)?;
+        w.write_u32::<LittleEndian>(self.file_name_length)?;
+        w.extend_from_slice(&util::to_unicode(&self.file_name, false));
+        Ok(w)
+    }
+}
+
+/// 2.2.3.4.11 Client Drive NotifyChange Directory Response (DR_DRIVE_NOTIFY_CHANGE_DIRECTORY_RSP)
+/// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpefs/3e82d2e9-62a5-4d4b-b635-ba198ea0a27d
+#[derive(Debug)]
+struct ClientDriveNotifyChangeDirectoryResponse {
+    /// The CompletionId field of the DR_DEVICE_IOCOMPLETION header MUST match a Device I/O Request
+    /// that has the MajorFunction field set to IRP_MJ_DIRECTORY_CONTROL and the MinorFunction field
+    /// set to IRP_MN_NOTIFY_CHANGE_DIRECTORY.
+    device_io_reply: DeviceIoResponse,
+    /// Specifies the number of bytes in the Buffer field.
+    length: u32,
+    /// A single FILE_NOTIFY_INFORMATION entry describing the change that completed this IRP, or
+    /// None if io_status isn't STATUS_SUCCESS.
+    buffer: Option<FileNotifyInformation>,
+}
+
+impl ClientDriveNotifyChangeDirectoryResponse {
+    fn new(
+        device_io_request: &DeviceIoRequest,
+        io_status: NTSTATUS,
+        buffer: Option<FileNotifyInformation>,
+    ) -> Self {
+        let length = match &buffer {
+            Some(info) => info.size(),
+            None => 0,
+        };
+
+        Self {
+            device_io_reply: DeviceIoResponse::new(
+                device_io_request,
+                NTSTATUS::to_u32(&io_status).unwrap(),
+            ),
+            length,
+            buffer,
+        }
+    }
+
+    fn encode(&self) -> RdpResult<Vec<u8>> {
+        let mut w = vec![];
+        w.extend_from_slice(&self.device_io_reply.encode()?);
+        w.write_u32::<LittleEndian>(self.length)?;
+        if let Some(buffer) = &self.buffer {
+            w.extend_from_slice(&buffer.encode()?);
+        }
+        Ok(w)
+    }
+}
+
 /// 2.2.3.3.6 Server Drive Query Volume Information Request
 /// https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpefs/484e622d-0e2b-423c-8461-7de38878effb
 ///
@@ -2777,11 +4273,48 @@ impl ClientDriveQueryVolumeInformationResponse {
 /// whereas Windows prefers 64-bit signed integers representing the number of 100-nanosecond intervals
 /// that have elapsed since January 1, 1601, Coordinated Universal Time (UTC)
 /// (https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-fscc/a69cc039-d288-4673-9598-772b6083f8bf).
-fn to_windows_time(tdp_time_ms: u64) -> i64 {
+fn to_windows_time(tdp_time_ms: u64) -> RdpResult<i64> {
     // https://stackoverflow.com/a/5471380/6277051
     // https://docs.microsoft.com/en-us/windows/win32/sysinfo/converting-a-time-t-value-to-a-file-time
-    let tdp_time_sec = tdp_time_ms / 1000;
-    ((tdp_time_sec * 10000000) + 116444736000000000) as i64
+    //
+    // Unlike the stackoverflow answer, we keep millisecond resolution all the way through
+    // instead of truncating to whole seconds before converting, and we use checked arithmetic so
+    // a tdp_time_ms large enough to overflow an i64 FILETIME is reported as an error rather than
+    // silently wrapping.
+    i64::try_from(tdp_time_ms)
+        .ok()
+        .and_then(|tdp_time_ms| tdp_time_ms.checked_mul(10_000))
+        .and_then(|filetime| filetime.checked_add(116_444_736_000_000_000))
+        .ok_or_else(|| invalid_data_error(&format!("tdp_time_ms {} overflows FILETIME", tdp_time_ms)))
+}
+
+/// The inverse of `to_windows_time`: converts a Windows FILETIME (100-ns intervals since
+/// January 1, 1601 UTC) back to milliseconds since the UNIX epoch, as TDP expects. Saturates to 0
+/// for any filetime preceding the UNIX epoch, rather than wrapping a negative value into a huge
+/// u64.
+fn from_windows_time(filetime: i64) -> u64 {
+    (filetime.saturating_sub(116444736000000000) / 10000).max(0) as u64
+}
+
+/// Maps a redirected file's FileType to the FileAttributes Windows would report for it. A
+/// symlink is surfaced as a reparse point rather than flattened to a regular file or directory,
+/// so that FileAttributeTagInformation (and the directory-listing FileInformationClass variants)
+/// let a Windows client recognize it as a link instead of silently following it.
+fn file_attributes_for(file_type: FileType) -> flags::FileAttributes {
+    match file_type {
+        FileType::File => flags::FileAttributes::FILE_ATTRIBUTE_NORMAL,
+        FileType::Directory => flags::FileAttributes::FILE_ATTRIBUTE_DIRECTORY,
+        FileType::Symlink => flags::FileAttributes::FILE_ATTRIBUTE_REPARSE_POINT,
+    }
+}
+
+/// The real ea_size FileBothDirectoryInformation/FileFullDirectoryInformation/
+/// FileIdBothDirectoryInformation/FileIdFullDirectoryInformation report for fso: the packed byte
+/// length FileFullEaInformation::encode would produce for its extended attributes, which is
+/// exactly what NtQueryEaFile reports as the same file's EaSize. 0 when fso carries no extended
+/// attributes, same as before extended attributes existed.
+fn ea_size_for(fso: &FileSystemObject) -> u32 {
+    FileFullEaInformation::from(fso).size()
 }
 
 #[cfg(test)]
@@ -2792,7 +4325,42 @@ mod tests {
     fn test_to_windows_time() {
         // Cross checked against
         // https://www.silisoftware.com/tools/date.php?inputdate=1655246166&inputformat=unix
-        assert_eq!(to_windows_time(1655246166 * 1000), 132997197660000000);
-        assert_eq!(to_windows_time(1000), 116444736010000000);
+        assert_eq!(
+            to_windows_time(1655246166 * 1000).unwrap(),
+            132997197660000000
+        );
+        assert_eq!(to_windows_time(1000).unwrap(), 116444736010000000);
+
+        // Sub-second input should retain millisecond resolution instead of being truncated to
+        // the nearest second first.
+        assert_eq!(to_windows_time(1655246166123).unwrap(), 132997197661230000);
+
+        // A tdp_time_ms large enough to overflow an i64 FILETIME is reported as an error rather
+        // than silently wrapping.
+        assert!(to_windows_time(u64::MAX).is_err());
+        assert!(to_windows_time(i64::MAX as u64 - 1).is_err());
+    }
+
+    #[test]
+    fn test_from_windows_time() {
+        // Same vectors as test_to_windows_time, read in the opposite direction.
+        assert_eq!(from_windows_time(132997197660000000), 1655246166 * 1000);
+        assert_eq!(from_windows_time(116444736010000000), 1000);
+    }
+
+    #[test]
+    fn test_windows_time_round_trip() {
+        for tdp_time_ms in [0, 1000, 1655246166123] {
+            assert_eq!(
+                from_windows_time(to_windows_time(tdp_time_ms).unwrap()),
+                tdp_time_ms
+            );
+        }
+    }
+
+    #[test]
+    fn test_from_windows_time_before_unix_epoch_saturates() {
+        assert_eq!(from_windows_time(0), 0);
+        assert_eq!(from_windows_time(i64::MIN), 0);
     }
 }
@@ -17,33 +17,82 @@ use super::consts::{
 };
 use super::path::UnixPath;
 use super::{
-    Boolean, ClientDriveQueryDirectoryResponse, ClientDriveQueryInformationResponse,
+    from_windows_time, Boolean, ClientDriveNotifyChangeDirectoryResponse,
+    ClientDriveQueryDirectoryResponse, ClientDriveQueryInformationResponse,
     ClientDriveQueryVolumeInformationResponse, ClientDriveSetInformationResponse,
     DeviceCloseRequest, DeviceCloseResponse, DeviceControlRequest, DeviceControlResponse,
-    DeviceCreateRequest, DeviceCreateResponse, DeviceIoRequest, DeviceReadRequest,
-    DeviceReadResponse, DeviceWriteRequest, DeviceWriteResponse, FileBothDirectoryInformation,
-    FileDirectoryInformation, FileFsAttributeInformation, FileFsDeviceInformation,
-    FileFsFullSizeInformation, FileFsSizeInformation, FileFsVolumeInformation,
-    FileFullDirectoryInformation, FileInformationClass, FileNamesInformation,
+    DeviceCreateRequest, DeviceCreateResponse, DeviceIoRequest, DeviceIoResponse,
+    DeviceReadRequest, DeviceReadResponse, DeviceWriteRequest, DeviceWriteResponse,
+    FileFsAttributeInformation, FileFsDeviceInformation, FileFsFullSizeInformation,
+    FileFsSizeInformation, FileFsVolumeInformation, FileInformationClass, FileNotifyInformation,
     FileRenameInformation, FileSystemInformationClass, ServerCreateDriveRequest,
-    ServerDeviceAnnounceResponse, ServerDriveQueryDirectoryRequest,
-    ServerDriveQueryInformationRequest, ServerDriveQueryVolumeInformationRequest,
-    ServerDriveSetInformationRequest,
+    ServerDeviceAnnounceResponse, ServerDriveNotifyChangeDirectoryRequest,
+    ServerDriveQueryDirectoryRequest, ServerDriveQueryInformationRequest,
+    ServerDriveQueryVolumeInformationRequest, ServerDriveSetInformationRequest, ShortNameTable,
 };
 use crate::errors::{invalid_data_error, not_implemented_error, try_error, NTSTATUS_OK};
 use crate::rdpdr::{flags, CHANNEL_NAME};
 use crate::{
-    FileSystemObject, FileType, Payload, SharedDirectoryAcknowledge, SharedDirectoryCreateRequest,
+    FileSystemEventAction, FileSystemObject, FileType, Payload, SharedDirectoryAcknowledge,
+    SharedDirectoryChangeNotifyResponse, SharedDirectoryCreateRequest,
     SharedDirectoryCreateResponse, SharedDirectoryDeleteRequest, SharedDirectoryDeleteResponse,
-    SharedDirectoryInfoRequest, SharedDirectoryInfoResponse, SharedDirectoryListRequest,
-    SharedDirectoryListResponse, SharedDirectoryMoveRequest, SharedDirectoryMoveResponse,
-    SharedDirectoryReadRequest, SharedDirectoryReadResponse, SharedDirectoryWriteRequest,
+    SharedDirectoryFreeSpaceRequest, SharedDirectoryFreeSpaceResponse, SharedDirectoryInfoRequest,
+    SharedDirectoryInfoResponse, SharedDirectoryListRequest, SharedDirectoryListResponse,
+    SharedDirectoryMoveRequest, SharedDirectoryMoveResponse, SharedDirectoryReadRequest,
+    SharedDirectoryReadResponse, SharedDirectorySetTimestampsRequest,
+    SharedDirectorySetTimestampsResponse, SharedDirectoryTruncateRequest,
+    SharedDirectoryTruncateResponse, SharedDirectoryWatchRequest, SharedDirectoryWriteRequest,
     SharedDirectoryWriteResponse, TdpErrCode,
 };
+use crc32fast::Hasher as Crc32Hasher;
+use num_traits::ToPrimitive;
 use rdp::core::mcs;
 use rdp::model::error::RdpResult;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+/// How long a CompletionId may sit in `pending_irps` without a matching TDP response before
+/// `sweep_expired_irps` gives up on it and synthesizes a failure IRP completion, used whenever a
+/// Config doesn't set irp_timeout (or sets it to the zero duration).
+const DEFAULT_IRP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The largest single read/write tdp_sd_read/tdp_sd_write will ask the browser to service in one
+/// TDP round trip, used whenever a Config doesn't set max_tdp_chunk_size (or sets it to 0). An
+/// IRP_MJ_READ/IRP_MJ_WRITE larger than this is split into chunks of at most this many bytes, so
+/// no single TDP message balloons to the size of the whole file being transferred.
+const DEFAULT_MAX_TDP_CHUNK_SIZE: u32 = 1024 * 1024;
+
+/// The largest number of chunks belonging to the same split IRP_MJ_READ/IRP_MJ_WRITE that may be
+/// outstanding at once, used whenever a Config doesn't set max_chunks_in_flight (or sets it to
+/// 0). Keeping several chunks in flight lets the browser service them concurrently instead of
+/// stalling the whole transfer on one chunk's round trip at a time.
+const DEFAULT_MAX_CHUNKS_IN_FLIGHT: usize = 4;
+
+/// The number of times a single chunk of a chunked read may be re-requested after failing its
+/// checksum before the whole transfer is given up on as corrupted. A lone bad chunk is usually a
+/// transient transport glitch, so it's worth resuming just that chunk a couple of times rather
+/// than forcing the caller to restart the entire (potentially very large) read from scratch.
+const MAX_CHUNK_CHECKSUM_RETRIES: u32 = 2;
+
+/// The largest number of change events queue_notify_event will hold onto for a single
+/// directory_id while no IRP_MN_NOTIFY_CHANGE_DIRECTORY is outstanding for it. Bounds the memory
+/// a share that changes rapidly (e.g. a build directory) can consume; once full, the oldest
+/// queued event is dropped to make room for the newest.
+const MAX_QUEUED_NOTIFY_EVENTS: usize = 16;
+
+/// The buffer budget handed to FileCacheObject::next_batch for a single IRP_MN_QUERY_DIRECTORY
+/// response. MS-RDPEFS doesn't give the client an output buffer length to target for this
+/// message (unlike IRP_MJ_READ), so we pick a size in line with what a real Windows redirector
+/// buffers per round trip, large enough to make large directories listable in a handful of
+/// queries instead of one per entry.
+const DIR_QUERY_BATCH_BUFFER_SIZE: u32 = 64 * 1024;
+
+/// How recently two SharedDirectoryChangeNotifyResponses for the same directory_id, action and
+/// path must have been queued for the second to be treated as a duplicate of the first and
+/// dropped rather than queued again. Editors and installers commonly fire several near-identical
+/// notifications (e.g. write-then-touch) for what is really one logical change.
+const NOTIFY_DEBOUNCE_WINDOW: Duration = Duration::from_millis(250);
 
 /// Client is a client for handling the directory sharing
 /// aspects of an RDPDR client as defined in
@@ -53,11 +102,34 @@ use std::io::{Read, Write};
 /// https://github.com/gravitational/teleport/blob/master/rfd/0067-desktop-access-file-system-sharing.md.
 pub struct Client {
     pub allow_directory_sharing: bool,
-    /// FileId-indexed cache of FileCacheObjects.
-    /// See the documentation of FileCacheObject
-    /// for more detail on how this is used.
-    file_cache: FileCache,
-    next_file_id: u32, // used to generate file ids
+    /// The permission mask applied to every shared directory. See SharePermissions.
+    permissions: SharePermissions,
+    /// DeviceId-indexed file caches, one per announced shared directory.
+    /// Keying the cache (and the FileId namespace) by device_id allows
+    /// several directories to be redirected simultaneously without their
+    /// FileIds or directory listings colliding.
+    /// See the documentation of FileCacheObject for more detail on how
+    /// each per-device cache is used.
+    file_caches: HashMap<u32, FileCache>,
+    /// DeviceId-indexed FileId generators, one per shared directory.
+    next_file_ids: HashMap<u32, u32>,
+    /// The largest read/write chunk tdp_sd_read/tdp_sd_write will ask the browser to service in
+    /// one TDP round trip; see DEFAULT_MAX_TDP_CHUNK_SIZE.
+    max_tdp_chunk_size: u32,
+    /// The largest number of chunks belonging to the same split transfer that may be outstanding
+    /// at once; see DEFAULT_MAX_CHUNKS_IN_FLIGHT.
+    max_chunks_in_flight: usize,
+    /// Generator for the synthetic CompletionIds used to correlate the sub-requests of a split
+    /// IRP_MJ_READ/IRP_MJ_WRITE; see generate_chunk_completion_id.
+    next_chunk_completion_id: u32,
+    /// When true, every outgoing SharedDirectoryWriteRequest carries a CRC32 of its write_data,
+    /// and every incoming SharedDirectoryReadResponse's checksum (when present) is verified
+    /// against its read_data before the data is handed back to the RDP server. Off by default so
+    /// peers that don't send a checksum field stay compatible.
+    verify_integrity: bool,
+    /// How long a CompletionId may sit in `pending_irps` without a matching TDP response before
+    /// sweep_expired_irps fails it; see DEFAULT_IRP_TIMEOUT.
+    irp_timeout: Duration,
 
     // Functions for sending tdp messages to the browser client.
     tdp_sd_acknowledge: SharedDirectoryAcknowledgeSender,
@@ -68,6 +140,10 @@ pub struct Client {
     tdp_sd_read_request: SharedDirectoryReadRequestSender,
     tdp_sd_write_request: SharedDirectoryWriteRequestSender,
     tdp_sd_move_request: SharedDirectoryMoveRequestSender,
+    tdp_sd_free_space_request: SharedDirectoryFreeSpaceRequestSender,
+    tdp_sd_truncate_request: SharedDirectoryTruncateRequestSender,
+    tdp_sd_set_timestamps_request: SharedDirectorySetTimestampsRequestSender,
+    tdp_sd_watch_request: SharedDirectoryWatchRequestSender,
 
     // CompletionId-indexed maps of handlers for tdp messages coming from the browser client.
     pending_sd_info_resp_handlers: HashMap<u32, SharedDirectoryInfoResponseHandler>,
@@ -77,10 +153,163 @@ pub struct Client {
     pending_sd_read_resp_handlers: HashMap<u32, SharedDirectoryReadResponseHandler>,
     pending_sd_write_resp_handlers: HashMap<u32, SharedDirectoryWriteResponseHandler>,
     pending_sd_move_resp_handlers: HashMap<u32, SharedDirectoryMoveResponseHandler>,
+    pending_sd_free_space_resp_handlers: HashMap<u32, SharedDirectoryFreeSpaceResponseHandler>,
+    pending_sd_truncate_resp_handlers: HashMap<u32, SharedDirectoryTruncateResponseHandler>,
+    pending_sd_set_timestamps_resp_handlers:
+        HashMap<u32, SharedDirectorySetTimestampsResponseHandler>,
+    /// The original IRP's CompletionId-indexed table of in-progress chunked reads; see
+    /// PendingChunkedRead.
+    pending_chunked_reads: HashMap<u32, PendingChunkedRead>,
+    /// The original IRP's CompletionId-indexed table of in-progress chunked writes; see
+    /// PendingChunkedWrite.
+    pending_chunked_writes: HashMap<u32, PendingChunkedWrite>,
+    /// CompletionId-indexed table of outstanding IRP_MN_NOTIFY_CHANGE_DIRECTORYs. Unlike the
+    /// pending_sd_*_resp_handlers maps above, an entry here isn't removed the first time its TDP
+    /// response arrives: a SharedDirectoryChangeNotifyResponse may arrive many times (or not
+    /// match what RDP asked to be notified about) before one finally completes the IRP, so the
+    /// entry lives until a qualifying change notification completes it or the watched FileId is
+    /// cancelled/closed.
+    pending_sd_notify_resp_handlers: HashMap<u32, PendingWatch>,
+    /// DeviceId-indexed queues of changes observed (via queue_notify_event) while no
+    /// IRP_MN_NOTIFY_CHANGE_DIRECTORY was outstanding for that directory, most likely because the
+    /// previous one already completed and Windows hasn't reissued a new watch yet. Drained by
+    /// try_complete_from_queue the next time a watch is registered for the same directory, so a
+    /// change isn't lost just because it landed in that gap. Bounded by MAX_QUEUED_NOTIFY_EVENTS
+    /// and cleared in remove_device when the directory is un-shared.
+    pending_notify_events: HashMap<u32, VecDeque<QueuedNotifyEvent>>,
+
+    /// CompletionId-indexed table of every IRP that's currently awaiting a TDP response,
+    /// recording when it was sent so sweep_expired_irps can detect one the browser never
+    /// answered, and so process_irp_cancel can fail the right in-flight request when RDP
+    /// sends an IRP_MN_CANCEL for it.
+    pending_irps: HashMap<u32, PendingIrp>,
+}
+
+/// Bookkeeping for a single in-flight IRP, recorded alongside its entry in one of the
+/// pending_sd_*_resp_handlers maps so that map doesn't have to be scanned to find it.
+#[derive(Debug, Clone)]
+struct PendingIrp {
+    device_io_request: DeviceIoRequest,
+    started_at: Instant,
+}
+
+/// Bookkeeping for a single outstanding IRP_MN_NOTIFY_CHANGE_DIRECTORY, recording enough of the
+/// original request to evaluate incoming SharedDirectoryChangeNotifyResponses against it and,
+/// eventually, complete it.
+#[derive(Debug, Clone)]
+struct PendingWatch {
+    device_io_request: DeviceIoRequest,
+    /// The path of the watched directory itself, used to compute each reported change's path
+    /// relative to it and to decide whether a deeply nested change is in scope.
+    path: UnixPath,
+    /// If nonzero, changes anywhere in the watched directory's tree complete this IRP; otherwise
+    /// only changes to direct children of the watched directory do.
+    watch_tree: u8,
+    completion_filter: flags::CompletionFilter,
+}
+
+/// A single filesystem change queued by queue_notify_event against the directory_id it was
+/// reported for, awaiting a future watch registration that can make use of it.
+#[derive(Debug, Clone)]
+struct QueuedNotifyEvent {
+    action: FileSystemEventAction,
+    path: UnixPath,
+    queued_at: Instant,
+}
+
+/// Bookkeeping for an IRP_MJ_READ whose length exceeds max_tdp_chunk_size, shared by every chunk
+/// request tdp_sd_read issued for it, so the chunk that completes the transfer can stitch every
+/// chunk's data back together (by offset, since chunk responses can arrive out of order) and
+/// answer the original IRP exactly once.
+#[derive(Debug)]
+struct PendingChunkedRead {
+    rdp_req: DeviceReadRequest,
+    path: UnixPath,
+    /// (offset, length) pairs not yet sent to the browser, in ascending offset order.
+    queued: VecDeque<(u64, u32)>,
+    /// Chunks sent to the browser but not yet answered.
+    in_flight: usize,
+    /// Data received so far, keyed by its offset within the read.
+    completed: BTreeMap<u64, Vec<u8>>,
+    /// Set on the first chunk to fail; once set, no further chunks are sent and the transfer is
+    /// completed with this error as soon as every already in-flight chunk has answered.
+    failed: Option<TdpErrCode>,
+    /// Set once any chunk's checksum fails to verify after exhausting MAX_CHUNK_CHECKSUM_RETRIES
+    /// resumes of that chunk; takes priority over `failed` when the transfer completes, since
+    /// corrupted data shouldn't be delivered even if every chunk's own err_code was Nil.
+    checksum_failed: bool,
+    /// Number of times each offset's chunk has been resumed after a checksum failure, so a chunk
+    /// that keeps failing doesn't retry forever.
+    checksum_retries: HashMap<u64, u32>,
+}
+
+/// Bookkeeping for an IRP_MJ_WRITE whose length exceeds max_tdp_chunk_size, shared by every chunk
+/// request tdp_sd_write issued for it, so the chunk that completes the transfer can total up the
+/// bytes written and answer the original IRP exactly once.
+#[derive(Debug)]
+struct PendingChunkedWrite {
+    device_io_request: DeviceIoRequest,
+    directory_id: u32,
+    path: UnixPath,
+    /// (offset, data) pairs not yet sent to the browser, in ascending offset order.
+    queued: VecDeque<(u64, Vec<u8>)>,
+    in_flight: usize,
+    bytes_written: u32,
+    failed: Option<TdpErrCode>,
+}
+
+/// A fine-grained permission mask applied to every shared directory, checked by dir::Client
+/// before honoring any RDP operation that would mutate the share. This enforcement happens
+/// here, in the Rust client, rather than trusting the browser side to refuse the write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SharePermissions {
+    pub write: bool,
+    pub create: bool,
+    pub delete: bool,
+    pub rename: bool,
+}
+
+impl SharePermissions {
+    pub fn read_write() -> Self {
+        Self {
+            write: true,
+            create: true,
+            delete: true,
+            rename: true,
+        }
+    }
+
+    pub fn read_only() -> Self {
+        Self {
+            write: false,
+            create: false,
+            delete: false,
+            rename: false,
+        }
+    }
+}
+
+impl Default for SharePermissions {
+    fn default() -> Self {
+        Self::read_write()
+    }
 }
 
 pub struct Config {
     pub allow_directory_sharing: bool,
+    pub permissions: SharePermissions,
+    /// The largest read/write chunk tdp_sd_read/tdp_sd_write will ask the browser to service in
+    /// one TDP round trip. 0 means use DEFAULT_MAX_TDP_CHUNK_SIZE.
+    pub max_tdp_chunk_size: u32,
+    /// The largest number of chunks belonging to the same split transfer that may be outstanding
+    /// at once. 0 means use DEFAULT_MAX_CHUNKS_IN_FLIGHT.
+    pub max_chunks_in_flight: usize,
+    /// When true, enables end-to-end integrity checksums on shared-directory read/write
+    /// transfers; see Client::verify_integrity.
+    pub verify_integrity: bool,
+    /// How long a CompletionId may sit waiting for a TDP response before sweep_expired_irps
+    /// fails it. The zero duration means use DEFAULT_IRP_TIMEOUT.
+    pub irp_timeout: Duration,
     pub tdp_sd_acknowledge: SharedDirectoryAcknowledgeSender,
     pub tdp_sd_info_request: SharedDirectoryInfoRequestSender,
     pub tdp_sd_create_request: SharedDirectoryCreateRequestSender,
@@ -89,6 +318,10 @@ pub struct Config {
     pub tdp_sd_read_request: SharedDirectoryReadRequestSender,
     pub tdp_sd_write_request: SharedDirectoryWriteRequestSender,
     pub tdp_sd_move_request: SharedDirectoryMoveRequestSender,
+    pub tdp_sd_free_space_request: SharedDirectoryFreeSpaceRequestSender,
+    pub tdp_sd_truncate_request: SharedDirectoryTruncateRequestSender,
+    pub tdp_sd_set_timestamps_request: SharedDirectorySetTimestampsRequestSender,
+    pub tdp_sd_watch_request: SharedDirectoryWatchRequestSender,
 }
 
 impl Client {
@@ -101,8 +334,26 @@ impl Client {
 
         Client {
             allow_directory_sharing: cfg.allow_directory_sharing,
-            file_cache: FileCache::new(),
-            next_file_id: 0,
+            permissions: cfg.permissions,
+            file_caches: HashMap::new(),
+            next_file_ids: HashMap::new(),
+            max_tdp_chunk_size: if cfg.max_tdp_chunk_size == 0 {
+                DEFAULT_MAX_TDP_CHUNK_SIZE
+            } else {
+                cfg.max_tdp_chunk_size
+            },
+            max_chunks_in_flight: if cfg.max_chunks_in_flight == 0 {
+                DEFAULT_MAX_CHUNKS_IN_FLIGHT
+            } else {
+                cfg.max_chunks_in_flight
+            },
+            next_chunk_completion_id: 0,
+            verify_integrity: cfg.verify_integrity,
+            irp_timeout: if cfg.irp_timeout == Duration::ZERO {
+                DEFAULT_IRP_TIMEOUT
+            } else {
+                cfg.irp_timeout
+            },
 
             tdp_sd_acknowledge: cfg.tdp_sd_acknowledge,
             tdp_sd_info_request: cfg.tdp_sd_info_request,
@@ -112,6 +363,10 @@ impl Client {
             tdp_sd_read_request: cfg.tdp_sd_read_request,
             tdp_sd_write_request: cfg.tdp_sd_write_request,
             tdp_sd_move_request: cfg.tdp_sd_move_request,
+            tdp_sd_free_space_request: cfg.tdp_sd_free_space_request,
+            tdp_sd_truncate_request: cfg.tdp_sd_truncate_request,
+            tdp_sd_set_timestamps_request: cfg.tdp_sd_set_timestamps_request,
+            tdp_sd_watch_request: cfg.tdp_sd_watch_request,
 
             pending_sd_info_resp_handlers: HashMap::new(),
             pending_sd_create_resp_handlers: HashMap::new(),
@@ -120,7 +375,128 @@ impl Client {
             pending_sd_read_resp_handlers: HashMap::new(),
             pending_sd_write_resp_handlers: HashMap::new(),
             pending_sd_move_resp_handlers: HashMap::new(),
+            pending_sd_free_space_resp_handlers: HashMap::new(),
+            pending_sd_truncate_resp_handlers: HashMap::new(),
+            pending_sd_set_timestamps_resp_handlers: HashMap::new(),
+            pending_chunked_reads: HashMap::new(),
+            pending_chunked_writes: HashMap::new(),
+            pending_sd_notify_resp_handlers: HashMap::new(),
+            pending_notify_events: HashMap::new(),
+
+            pending_irps: HashMap::new(),
+        }
+    }
+
+    /// Records that an IRP with the given device_io_request's CompletionId is now awaiting a
+    /// TDP response, so sweep_expired_irps can detect and fail it if the browser never answers.
+    /// Called alongside every insert into a pending_sd_*_resp_handlers map.
+    fn track_irp(&mut self, device_io_request: &DeviceIoRequest) {
+        self.pending_irps.insert(
+            device_io_request.completion_id,
+            PendingIrp {
+                device_io_request: device_io_request.clone(),
+                started_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Stops tracking the IRP for completion_id, whether because it completed normally or
+    /// because it's being cancelled/timed out.
+    fn untrack_irp(&mut self, completion_id: u32) {
+        self.pending_irps.remove(&completion_id);
+    }
+
+    /// Scans every outstanding IRP and, for any that have been waiting longer than
+    /// irp_timeout (DEFAULT_IRP_TIMEOUT unless Config sets another) for a TDP response, evicts its handler from whichever
+    /// pending_sd_*_resp_handlers map it's in and writes back a synthesized
+    /// STATUS_IO_TIMEOUT device I/O completion so the RDP server is never left hanging on a
+    /// share that went away. Intended to be called periodically (e.g. from the event loop).
+    pub fn sweep_expired_irps<S: Read + Write>(
+        &mut self,
+        mcs: &mut mcs::Client<S>,
+    ) -> RdpResult<()> {
+        let now = Instant::now();
+        let expired: Vec<DeviceIoRequest> = self
+            .pending_irps
+            .iter()
+            // An outstanding directory watch is meant to sit pending indefinitely, so it's
+            // exempted here rather than timed out after irp_timeout like a normal
+            // one-shot TDP round trip.
+            .filter(|(completion_id, irp)| {
+                now.duration_since(irp.started_at) >= self.irp_timeout
+                    && !self
+                        .pending_sd_notify_resp_handlers
+                        .contains_key(completion_id)
+            })
+            .map(|(_, irp)| irp.device_io_request.clone())
+            .collect();
+
+        for device_io_request in expired {
+            let completion_id = device_io_request.completion_id;
+            self.pending_irps.remove(&completion_id);
+            self.pending_sd_info_resp_handlers.remove(&completion_id);
+            self.pending_sd_create_resp_handlers.remove(&completion_id);
+            self.pending_sd_delete_resp_handlers.remove(&completion_id);
+            self.pending_sd_list_resp_handlers.remove(&completion_id);
+            self.pending_sd_read_resp_handlers.remove(&completion_id);
+            self.pending_sd_write_resp_handlers.remove(&completion_id);
+            self.pending_sd_move_resp_handlers.remove(&completion_id);
+            self.pending_sd_free_space_resp_handlers.remove(&completion_id);
+            self.pending_sd_truncate_resp_handlers
+                .remove(&completion_id);
+            self.pending_sd_set_timestamps_resp_handlers
+                .remove(&completion_id);
+            self.pending_chunked_reads.remove(&completion_id);
+            self.pending_chunked_writes.remove(&completion_id);
+
+            debug!(
+                "IRP with completion_id {} timed out waiting on a TDP response, failing it",
+                completion_id
+            );
+            let resp = DeviceIoResponse::new(
+                &device_io_request,
+                NTSTATUS::to_u32(&NTSTATUS::STATUS_IO_TIMEOUT).unwrap(),
+            );
+            mcs.write(&CHANNEL_NAME.to_string(), resp.encode()?)?;
         }
+
+        Ok(())
+    }
+
+    /// Handles an IRP_MN_CANCEL: looks up the in-flight IRP for completion_id, removes its
+    /// handler so the eventual (now-irrelevant) TDP response is silently dropped, and replies
+    /// with STATUS_CANCELLED so the RDP server can retire the original IRP.
+    pub fn process_irp_cancel(&mut self, completion_id: u32) -> RdpResult<Vec<u8>> {
+        let device_io_request = match self.pending_irps.remove(&completion_id) {
+            Some(irp) => irp.device_io_request,
+            None => {
+                return Err(invalid_data_error(&format!(
+                    "received IRP_MN_CANCEL for unknown completion_id {}",
+                    completion_id
+                )));
+            }
+        };
+        self.pending_sd_info_resp_handlers.remove(&completion_id);
+        self.pending_sd_create_resp_handlers.remove(&completion_id);
+        self.pending_sd_delete_resp_handlers.remove(&completion_id);
+        self.pending_sd_list_resp_handlers.remove(&completion_id);
+        self.pending_sd_read_resp_handlers.remove(&completion_id);
+        self.pending_sd_write_resp_handlers.remove(&completion_id);
+        self.pending_sd_move_resp_handlers.remove(&completion_id);
+        self.pending_sd_free_space_resp_handlers.remove(&completion_id);
+        self.pending_sd_truncate_resp_handlers
+            .remove(&completion_id);
+        self.pending_sd_set_timestamps_resp_handlers
+            .remove(&completion_id);
+        self.pending_chunked_reads.remove(&completion_id);
+        self.pending_chunked_writes.remove(&completion_id);
+        self.pending_sd_notify_resp_handlers.remove(&completion_id);
+
+        let resp = DeviceIoResponse::new(
+            &device_io_request,
+            NTSTATUS::to_u32(&NTSTATUS::STATUS_CANCELLED).unwrap(),
+        );
+        resp.encode()
     }
 
     pub fn handle_device_reply(
@@ -142,6 +518,25 @@ impl Client {
         Ok(vec![])
     }
 
+    /// process_device_announce registers bookkeeping for a newly announced shared directory,
+    /// so its FileId namespace and directory listings are kept separate from any other
+    /// simultaneously redirected drives. This should be called whenever a
+    /// ServerDeviceAnnounceResponse for a directory device_id is received, including when a
+    /// drive is announced after the RDPDR connection is already established.
+    pub fn process_device_announce(&mut self, device_id: u32) {
+        self.file_caches.entry(device_id).or_insert_with(FileCache::new);
+        self.next_file_ids.entry(device_id).or_insert(0);
+    }
+
+    /// remove_device tears down the cached file handles for a shared directory that's being
+    /// unshared, so a subsequent re-announce of the same device_id (or reuse of its FileIds by
+    /// another drive) doesn't see stale entries.
+    pub fn remove_device(&mut self, device_id: u32) {
+        self.file_caches.remove(&device_id);
+        self.next_file_ids.remove(&device_id);
+        self.pending_notify_events.remove(&device_id);
+    }
+
     pub fn process_irp_device_control(
         &mut self,
         ioctl: DeviceControlRequest,
@@ -162,6 +557,35 @@ impl Client {
         let rdp_req = ServerCreateDriveRequest::decode(device_io_request, payload)?;
         debug!("received RDP: {:?}", rdp_req);
 
+        // Reject any create that would write to a read-only share before we ever talk to the
+        // browser about it. FILE_OVERWRITE_IF is included alongside the dispositions named in
+        // the access-control RFD because it can create or overwrite just like FILE_SUPERSEDE.
+        if !self.permissions.write
+            && (rdp_req.create_disposition.intersects(
+                flags::CreateDisposition::FILE_SUPERSEDE
+                    | flags::CreateDisposition::FILE_CREATE
+                    | flags::CreateDisposition::FILE_OVERWRITE
+                    | flags::CreateDisposition::FILE_OVERWRITE_IF,
+            ) || rdp_req.desired_access.intersects(
+                flags::DesiredAccess::GENERIC_WRITE
+                    | flags::DesiredAccess::FILE_WRITE_DATA
+                    | flags::DesiredAccess::FILE_APPEND_DATA
+                    | flags::DesiredAccess::DELETE,
+            ))
+        {
+            return self.prep_device_create_response(&rdp_req, NTSTATUS::STATUS_ACCESS_DENIED, 0);
+        }
+
+        // Reject any path that would lexically resolve outside the shared directory's root
+        // before we ever talk to the browser about it.
+        if !is_confined_to_share(&UnixPath::from(&rdp_req.path)) {
+            return self.prep_device_create_response(
+                &rdp_req,
+                NTSTATUS::STATUS_OBJECT_PATH_INVALID,
+                0,
+            );
+        }
+
         // Send a TDP Shared Directory Info Request
         // https://github.com/FreeRDP/FreeRDP/blob/511444a65e7aa2f537c5e531fa68157a50c1bd4d/channels/drive/client/drive_file.c#L210
         let tdp_req = SharedDirectoryInfoRequest::from(rdp_req.clone());
@@ -170,6 +594,7 @@ impl Client {
         // Add a TDP Shared Directory Info Response handler to the handler cache.
         // When we receive a TDP Shared Directory Info Response with this completion_id,
         // this handler will be called.
+        self.track_irp(&rdp_req.device_io_request);
         self.pending_sd_info_resp_handlers.insert(
             rdp_req.device_io_request.completion_id,
             Box::new(
@@ -254,7 +679,7 @@ impl Client {
                         flags::CreateDisposition::FILE_SUPERSEDE => {
                             // If the file already exists, replace it with the given file. If it does not, create the given file.
                             if res.err_code == TdpErrCode::Nil {
-                                return cli.tdp_sd_overwrite(rdp_req);
+                                return cli.tdp_sd_overwrite(rdp_req, res.fso);
                             } else if res.err_code == TdpErrCode::DoesNotExist {
                                 return cli.tdp_sd_create(rdp_req, FileType::File);
                             }
@@ -262,8 +687,8 @@ impl Client {
                         flags::CreateDisposition::FILE_OPEN => {
                             // If the file already exists, open it instead of creating a new file. If it does not, fail the request and do not create a new file.
                             if res.err_code == TdpErrCode::Nil {
-                                let file_id = cli.generate_file_id();
-                                cli.file_cache.insert(
+                                let file_id = cli.generate_file_id(rdp_req.device_io_request.device_id);
+                                cli.file_cache_for(rdp_req.device_io_request.device_id).insert(
                                     file_id,
                                     FileCacheObject::new(UnixPath::from(&rdp_req.path), res.fso),
                                 );
@@ -295,8 +720,8 @@ impl Client {
                         flags::CreateDisposition::FILE_OPEN_IF => {
                             // If the file already exists, open it. If it does not, create the given file.
                             if res.err_code == TdpErrCode::Nil {
-                                let file_id = cli.generate_file_id();
-                                cli.file_cache.insert(
+                                let file_id = cli.generate_file_id(rdp_req.device_io_request.device_id);
+                                cli.file_cache_for(rdp_req.device_io_request.device_id).insert(
                                     file_id,
                                     FileCacheObject::new(UnixPath::from(&rdp_req.path), res.fso),
                                 );
@@ -312,7 +737,7 @@ impl Client {
                         flags::CreateDisposition::FILE_OVERWRITE => {
                             // If the file already exists, open it and overwrite it. If it does not, fail the request.
                             if res.err_code == TdpErrCode::Nil {
-                                return cli.tdp_sd_overwrite(rdp_req);
+                                return cli.tdp_sd_overwrite(rdp_req, res.fso);
                             } else if res.err_code == TdpErrCode::DoesNotExist {
                                 return cli.prep_device_create_response(
                                     &rdp_req,
@@ -324,7 +749,7 @@ impl Client {
                         flags::CreateDisposition::FILE_OVERWRITE_IF => {
                             // If the file already exists, open it and overwrite it. If it does not, create the given file.
                             if res.err_code == TdpErrCode::Nil {
-                                return cli.tdp_sd_overwrite(rdp_req);
+                                return cli.tdp_sd_overwrite(rdp_req, res.fso);
                             } else if res.err_code == TdpErrCode::DoesNotExist {
                                 return cli.tdp_sd_create(rdp_req, FileType::File);
                             }
@@ -345,12 +770,26 @@ impl Client {
         Ok(vec![])
     }
 
-    pub fn process_irp_close(&mut self, device_io_request: DeviceIoRequest) -> RdpResult<Vec<u8>> {
+    pub fn process_irp_close<S: Read + Write>(
+        &mut self,
+        device_io_request: DeviceIoRequest,
+        mcs: &mut mcs::Client<S>,
+    ) -> RdpResult<Vec<u8>> {
         // https://github.com/FreeRDP/FreeRDP/blob/511444a65e7aa2f537c5e531fa68157a50c1bd4d/channels/drive/client/drive_main.c#L236
         let rdp_req = DeviceCloseRequest::decode(device_io_request);
         debug!("received RDP: {:?}", rdp_req);
+        // If this FileId had an IRP_MN_NOTIFY_CHANGE_DIRECTORY outstanding against it, complete
+        // it with STATUS_CANCELLED now that the handle it was watching is gone.
+        self.release_watch(
+            rdp_req.device_io_request.device_id,
+            rdp_req.device_io_request.file_id,
+            mcs,
+        )?;
         // Remove the file from our cache
-        if let Some(file) = self.file_cache.remove(rdp_req.device_io_request.file_id) {
+        if let Some(file) = self
+            .file_cache_for(rdp_req.device_io_request.device_id)
+            .remove(rdp_req.device_io_request.file_id)
+        {
             if file.delete_pending {
                 return self.tdp_sd_delete(rdp_req, file);
             }
@@ -368,13 +807,17 @@ impl Client {
         // https://github.com/FreeRDP/FreeRDP/blob/511444a65e7aa2f537c5e531fa68157a50c1bd4d/channels/drive/client/drive_main.c#L373
         let rdp_req = ServerDriveQueryInformationRequest::decode(device_io_request, payload)?;
         debug!("received RDP: {:?}", rdp_req);
-        let f = self.file_cache.get(rdp_req.device_io_request.file_id);
+        // Clone out of the per-device cache so the borrow doesn't outlive this call.
+        let f = self
+            .file_cache_for(rdp_req.device_io_request.device_id)
+            .get(rdp_req.device_io_request.file_id)
+            .cloned();
         let code = if f.is_some() {
             NTSTATUS::STATUS_SUCCESS
         } else {
             NTSTATUS::STATUS_UNSUCCESSFUL
         };
-        self.prep_query_info_response(&rdp_req, f, code)
+        self.prep_query_info_response(&rdp_req, f.as_ref(), code)
     }
 
     /// The IRP_MJ_DIRECTORY_CONTROL function we support is when it's sent with minor function IRP_MN_QUERY_DIRECTORY,
@@ -400,8 +843,9 @@ impl Client {
                 let rdp_req = ServerDriveQueryDirectoryRequest::decode(device_io_request, payload)?;
                 debug!("received RDP: {:?}", rdp_req);
                 let file_id = rdp_req.device_io_request.file_id;
+                let device_id = rdp_req.device_io_request.device_id;
                 // https://github.com/FreeRDP/FreeRDP/blob/511444a65e7aa2f537c5e531fa68157a50c1bd4d/channels/drive/client/drive_main.c#L610
-                if let Some(dir) = self.file_cache.get(file_id) {
+                if let Some(dir) = self.file_cache_for(device_id).get(file_id) {
                     if dir.fso.file_type != FileType::Directory {
                         return Err(invalid_data_error("received an IRP_MN_QUERY_DIRECTORY request for a file rather than a directory"));
                     }
@@ -420,6 +864,11 @@ impl Client {
                     // path == "\\". I'm not quite sure what to do with this yet, so just leaving this as a note to self.
                     let path = dir.path.clone();
 
+                    // The last component of rdp_req.path is the search expression RDP wants us to
+                    // filter this directory's contents by (e.g. "*" or "*.txt"), not a real path
+                    // component, which is why it doesn't always match dir.path (see note above).
+                    let search_expression = UnixPath::from(&rdp_req.path).last().map(String::from);
+
                     // Ask the client for the list of files in this directory.
                     (self.tdp_sd_list_request)(SharedDirectoryListRequest {
                         completion_id: rdp_req.device_io_request.completion_id,
@@ -428,6 +877,7 @@ impl Client {
                     })?;
 
                     // When we get the response for that list of files...
+                    self.track_irp(&rdp_req.device_io_request);
                     self.pending_sd_list_resp_handlers.insert(
                         rdp_req.device_io_request.completion_id,
                         Box::new(
@@ -435,20 +885,31 @@ impl Client {
                                   res: SharedDirectoryListResponse|
                                   -> RdpResult<Vec<u8>> {
                                 if res.err_code != TdpErrCode::Nil {
-                                    // TODO(isaiah): For now any error will kill the session.
-                                    // In the future, we might want to make this send back
-                                    // an NTSTATUS::STATUS_UNSUCCESSFUL instead.
-                                    return Err(try_error(&format!(
-                                        "SharedDirectoryListRequest failed with err_code = {:?}",
-                                        res.err_code
-                                    )));
+                                    return cli.prep_drive_query_dir_response(
+                                        &rdp_req.device_io_request,
+                                        tdp_err_to_ntstatus(res.err_code),
+                                        vec![],
+                                    );
                                 }
 
                                 // If SharedDirectoryListRequest succeeded, move the
                                 // list of FileSystemObjects that correspond to this directory's
-                                // contents to its entry in the file cache.
-                                if let Some(dir) = cli.file_cache.get_mut(file_id) {
-                                    dir.contents = res.fso_list;
+                                // contents to its entry in the file cache, filtered down to the
+                                // entries that match RDP's search expression so the server never
+                                // sees a full unfiltered listing for a narrow wildcard query.
+                                if let Some(dir) = cli.file_cache_for(device_id).get_mut(file_id) {
+                                    dir.contents = res
+                                        .fso_list
+                                        .into_iter()
+                                        .filter(|fso| match (&search_expression, fso.name()) {
+                                            (Some(pattern), Ok(name)) => {
+                                                is_name_in_expression(&name, pattern)
+                                            }
+                                            // If we can't extract a name or there's no search
+                                            // expression to filter by, don't exclude the entry.
+                                            _ => true,
+                                        })
+                                        .collect();
                                     // And send back the "." directory over RDP
                                     return cli.prep_next_drive_query_dir_response(&rdp_req);
                                 }
@@ -467,21 +928,77 @@ impl Client {
                 self.prep_file_cache_fail_drive_query_dir_response(&rdp_req)
             }
             MinorFunction::IRP_MN_NOTIFY_CHANGE_DIRECTORY => {
-                debug!("received RDP: {:?}", device_io_request);
-                debug!(
-                    "ignoring IRP_MN_NOTIFY_CHANGE_DIRECTORY: {:?}",
-                    device_io_request
+                let rdp_req =
+                    ServerDriveNotifyChangeDirectoryRequest::decode(device_io_request, payload)?;
+                debug!("received RDP: {:?}", rdp_req);
+                let file_id = rdp_req.device_io_request.file_id;
+                let device_id = rdp_req.device_io_request.device_id;
+
+                let path = match self.file_cache_for(device_id).get(file_id) {
+                    Some(dir) => dir.path.clone(),
+                    None => {
+                        return self.prep_notify_change_response(
+                            &rdp_req.device_io_request,
+                            NTSTATUS::STATUS_UNSUCCESSFUL,
+                            None,
+                        );
+                    }
+                };
+
+                // A change may already have been observed and queued for this directory while no
+                // watch was registered for it - most likely in the gap between the previous
+                // IRP_MN_NOTIFY_CHANGE_DIRECTORY completing and this one arriving. If so, satisfy
+                // this IRP immediately instead of waiting on a new live change that may not come
+                // for a while.
+                if let Some(resp) = self.try_complete_from_queue(
+                    device_id,
+                    &path,
+                    rdp_req.watch_tree,
+                    rdp_req.completion_filter.clone(),
+                    &rdp_req.device_io_request,
+                )? {
+                    return Ok(resp);
+                }
+
+                // Ask the client to start watching this directory for changes.
+                (self.tdp_sd_watch_request)(SharedDirectoryWatchRequest {
+                    completion_id: rdp_req.device_io_request.completion_id,
+                    directory_id: device_id,
+                    path: path.clone(),
+                })?;
+
+                // This IRP stays outstanding until a qualifying SharedDirectoryChangeNotifyResponse
+                // arrives, or it's cancelled or its FileId is closed - see
+                // handle_tdp_sd_change_notify_response and release_watch.
+                self.track_irp(&rdp_req.device_io_request);
+                self.pending_sd_notify_resp_handlers.insert(
+                    rdp_req.device_io_request.completion_id,
+                    PendingWatch {
+                        device_io_request: rdp_req.device_io_request,
+                        path,
+                        watch_tree: rdp_req.watch_tree,
+                        completion_filter: rdp_req.completion_filter,
+                    },
                 );
-                // https://github.com/FreeRDP/FreeRDP/blob/511444a65e7aa2f537c5e531fa68157a50c1bd4d/channels/drive/client/drive_main.c#L661
+
+                // Return nothing yet; an RDP message will be sent back whenever a qualifying
+                // change notification completes this IRP.
                 Ok(vec![])
             }
+            MinorFunction::IRP_MN_CANCEL => {
+                debug!("received RDP: {:?}", device_io_request);
+                // The server is telling us it no longer cares about the response to an IRP it
+                // previously sent us (e.g. a long-running IRP_MN_QUERY_DIRECTORY). Fail the
+                // corresponding pending TDP request so its handler cache entry doesn't leak.
+                self.process_irp_cancel(device_io_request.completion_id)
+            }
             _ => {
                 debug!("received RDP: {:?}", device_io_request);
                 // https://github.com/FreeRDP/FreeRDP/blob/511444a65e7aa2f537c5e531fa68157a50c1bd4d/channels/drive/client/drive_main.c#L663
                 self.prep_drive_query_dir_response(
                     &device_io_request,
                     NTSTATUS::STATUS_NOT_SUPPORTED,
-                    None,
+                    vec![],
                 )
             }
         }
@@ -495,31 +1012,110 @@ impl Client {
     ) -> RdpResult<Vec<u8>> {
         let rdp_req = ServerDriveQueryVolumeInformationRequest::decode(device_io_request, payload)?;
         debug!("received RDP: {:?}", rdp_req);
-        if let Some(dir) = self.file_cache.get(rdp_req.device_io_request.file_id) {
+        let device_id = rdp_req.device_io_request.device_id;
+        if let Some(dir) = self
+            .file_cache_for(device_id)
+            .get(rdp_req.device_io_request.file_id)
+        {
             let buffer = match rdp_req.fs_info_class_lvl {
-                FileSystemInformationClassLevel::FileFsVolumeInformation => {
-                    Some(FileSystemInformationClass::FileFsVolumeInformation(
-                        FileFsVolumeInformation::new(dir.fso.last_modified as i64),
-                    ))
-                }
                 FileSystemInformationClassLevel::FileFsAttributeInformation => {
                     Some(FileSystemInformationClass::FileFsAttributeInformation(
                         FileFsAttributeInformation::new(),
                     ))
                 }
-                FileSystemInformationClassLevel::FileFsFullSizeInformation => {
-                    Some(FileSystemInformationClass::FileFsFullSizeInformation(
-                        FileFsFullSizeInformation::new(),
-                    ))
-                }
                 FileSystemInformationClassLevel::FileFsDeviceInformation => {
                     Some(FileSystemInformationClass::FileFsDeviceInformation(
                         FileFsDeviceInformation::new(),
                     ))
                 }
-                FileSystemInformationClassLevel::FileFsSizeInformation => Some(
-                    FileSystemInformationClass::FileFsSizeInformation(FileFsSizeInformation::new()),
-                ),
+                // FileFsVolumeInformation, FileFsFullSizeInformation and FileFsSizeInformation
+                // all report real numbers that only the browser knows (capacity, geometry, the
+                // volume label), so these three are handled below via a
+                // SharedDirectoryFreeSpaceRequest round trip instead of answered synchronously.
+                FileSystemInformationClassLevel::FileFsVolumeInformation
+                | FileSystemInformationClassLevel::FileFsFullSizeInformation
+                | FileSystemInformationClassLevel::FileFsSizeInformation => {
+                    let volume_creation_time = dir.fso.last_modified as i64;
+
+                    (self.tdp_sd_free_space_request)(SharedDirectoryFreeSpaceRequest {
+                        completion_id: rdp_req.device_io_request.completion_id,
+                        directory_id: device_id,
+                    })?;
+
+                    self.track_irp(&rdp_req.device_io_request);
+                    self.pending_sd_free_space_resp_handlers.insert(
+                        rdp_req.device_io_request.completion_id,
+                        Box::new(
+                            move |cli: &mut Self,
+                                  res: SharedDirectoryFreeSpaceResponse|
+                                  -> RdpResult<Vec<u8>> {
+                                if res.err_code != TdpErrCode::Nil {
+                                    return cli.prep_query_vol_info_response(
+                                        &rdp_req.device_io_request,
+                                        tdp_err_to_ntstatus(res.err_code),
+                                        None,
+                                    );
+                                }
+
+                                let buffer = match rdp_req.fs_info_class_lvl {
+                                    FileSystemInformationClassLevel::FileFsVolumeInformation => {
+                                        let volume_label = if res.volume_label.is_empty() {
+                                            None
+                                        } else {
+                                            Some(res.volume_label.clone())
+                                        };
+                                        FileSystemInformationClass::FileFsVolumeInformation(
+                                            FileFsVolumeInformation::new(
+                                                volume_creation_time,
+                                                volume_label,
+                                            ),
+                                        )
+                                    }
+                                    // bytes_total == 0 means the browser couldn't determine the
+                                    // backing volume's capacity (e.g. the host OS call failed),
+                                    // so fall back to the same defaults FreeRDP uses rather than
+                                    // reporting a volume with zero capacity.
+                                    FileSystemInformationClassLevel::FileFsFullSizeInformation => {
+                                        FileSystemInformationClass::FileFsFullSizeInformation(
+                                            if res.bytes_total == 0 {
+                                                FileFsFullSizeInformation::new()
+                                            } else {
+                                                FileFsFullSizeInformation::from_bytes(
+                                                    res.bytes_total,
+                                                    res.bytes_free,
+                                                    res.bytes_per_sector,
+                                                    res.sectors_per_cluster,
+                                                )
+                                            },
+                                        )
+                                    }
+                                    _ => FileSystemInformationClass::FileFsSizeInformation(
+                                        if res.bytes_total == 0 {
+                                            FileFsSizeInformation::new()
+                                        } else {
+                                            FileFsSizeInformation::from_bytes(
+                                                res.bytes_total,
+                                                res.bytes_free,
+                                                res.bytes_per_sector,
+                                                res.sectors_per_cluster,
+                                            )
+                                        },
+                                    ),
+                                };
+
+                                cli.prep_query_vol_info_response(
+                                    &rdp_req.device_io_request,
+                                    NTSTATUS::STATUS_SUCCESS,
+                                    Some(buffer),
+                                )
+                            },
+                        ),
+                    );
+
+                    // Return nothing yet, an RDP message will be returned when the
+                    // pending_sd_free_space_resp_handlers closure gets called.
+                    return Ok(vec![]);
+                }
                 _ => None,
             };
 
@@ -575,7 +1171,10 @@ impl Client {
         // Determine whether to send back a STATUS_DIRECTORY_NOT_EMPTY
         // or STATUS_SUCCESS in the case of a succesful operation
         // https://github.com/FreeRDP/FreeRDP/blob/dfa231c0a55b005af775b833f92f6bcd30363d77/channels/drive/client/drive_main.c#L430-L431
-        let io_status = match self.file_cache.get(rdp_req.device_io_request.file_id) {
+        let io_status = match self
+            .file_cache_for(rdp_req.device_io_request.device_id)
+            .get(rdp_req.device_io_request.file_id)
+        {
             Some(file) => {
                 if file.fso.file_type == FileType::Directory && file.fso.is_empty == TDP_FALSE {
                     NTSTATUS::STATUS_DIRECTORY_NOT_EMPTY
@@ -600,7 +1199,14 @@ impl Client {
             },
             FileInformationClassLevel::FileDispositionInformation => match rdp_req.set_buffer {
                 FileInformationClass::FileDispositionInformation(ref info) => {
-                    if let Some(file) = self.file_cache.get_mut(rdp_req.device_io_request.file_id) {
+                    if !self.permissions.delete && info.delete_pending == 1 {
+                        return self.prep_set_info_response(&rdp_req, NTSTATUS::STATUS_ACCESS_DENIED);
+                    }
+
+                    if let Some(file) = self
+                        .file_cache_for(rdp_req.device_io_request.device_id)
+                        .get_mut(rdp_req.device_io_request.file_id)
+                    {
                         if !(file.fso.file_type == FileType::Directory && file.fso.is_empty == TDP_FALSE) {
                             // https://github.com/FreeRDP/FreeRDP/blob/dfa231c0a55b005af775b833f92f6bcd30363d77/channels/drive/client/drive_file.c#L681
                             file.delete_pending = info.delete_pending == 1;
@@ -617,14 +1223,33 @@ impl Client {
                 )),
 
             },
-            FileInformationClassLevel::FileBasicInformation
-            | FileInformationClassLevel::FileEndOfFileInformation
+            FileInformationClassLevel::FileEndOfFileInformation
             | FileInformationClassLevel::FileAllocationInformation => {
-                // Each of these ask us to change something we don't have control over at the browser
-                // level, so we just do nothing and send back a success.
-                // https://github.com/FreeRDP/FreeRDP/blob/dfa231c0a55b005af775b833f92f6bcd30363d77/channels/drive/client/drive_file.c#L579
-                self.prep_set_info_response(&rdp_req, io_status)
+                let end_of_file = match rdp_req.set_buffer {
+                    FileInformationClass::FileEndOfFileInformation(ref info) => info.end_of_file,
+                    FileInformationClass::FileAllocationInformation(ref info) => {
+                        info.allocation_size
+                    }
+                    _ => {
+                        return Err(invalid_data_error(
+                            "FileInformationClass does not match FileInformationClassLevel",
+                        ))
+                    }
+                };
+
+                self.tdp_sd_truncate(rdp_req.clone(), end_of_file, io_status)
             }
+            FileInformationClassLevel::FileBasicInformation => match rdp_req.set_buffer {
+                FileInformationClass::FileBasicInformation(ref info) => self.tdp_sd_set_timestamps(
+                    rdp_req.clone(),
+                    info.creation_time,
+                    info.last_write_time,
+                    io_status,
+                ),
+                _ => Err(invalid_data_error(
+                    "FileInformationClass does not match FileInformationClassLevel",
+                )),
+            },
 
             _ => {
                 Err(not_implemented_error(&format!(
@@ -668,55 +1293,29 @@ impl Client {
     }
 
     /// prep_next_drive_query_dir_response is a helper function that takes advantage of the
-    /// Iterator implementation for FileCacheObject in order to respond appropriately to
-    /// Server Drive Query Directory Requests as they come in.
+    /// batching enumerator on FileCacheObject (see FileCacheObject::next_batch) in order to
+    /// respond appropriately to Server Drive Query Directory Requests as they come in.
     ///
     /// req gives us a FileId, which we use to get the FileCacheObject for the directory that
-    /// this request is targeted at. We use that FileCacheObject as an iterator, grabbing the
-    /// next() FileSystemObject (starting with ".", then "..", then iterating through the contents
-    /// of the target directory), which we then convert to an RDP FileInformationClass for sending back
-    /// to the RDP server.
+    /// this request is targeted at. We pull as many entries as fit in one response out of that
+    /// FileCacheObject (starting with ".", then "..", then the contents of the target directory),
+    /// each converted to an RDP FileInformationClass, chained together with next_entry_offset so
+    /// the server doesn't need to send one IRP_MN_QUERY_DIRECTORY per entry.
     fn prep_next_drive_query_dir_response(
         &mut self,
         req: &ServerDriveQueryDirectoryRequest,
     ) -> RdpResult<Vec<u8>> {
-        if let Some(dir) = self.file_cache.get_mut(req.device_io_request.file_id) {
-            // Get the next FileSystemObject from the FileCacheObject for translation
-            // into an RDP data structure. Because of how next() is implemented for FileCacheObject,
-            // the first time this is called we will get an object for the "." directory, the second
-            // time will give us "..", and then we will iterate through any files/directories stored
-            // within dir.
-            if let Some(fso) = dir.next() {
-                let buffer = match req.file_info_class_lvl {
-                    FileInformationClassLevel::FileBothDirectoryInformation => {
-                        Some(FileInformationClass::FileBothDirectoryInformation(
-                            FileBothDirectoryInformation::from(fso)?,
-                        ))
-                    }
-                    FileInformationClassLevel::FileFullDirectoryInformation => {
-                        Some(FileInformationClass::FileFullDirectoryInformation(
-                            FileFullDirectoryInformation::from(fso)?,
-                        ))
-                    }
-                    FileInformationClassLevel::FileNamesInformation => {
-                        Some(FileInformationClass::FileNamesInformation(
-                            FileNamesInformation::new(fso.name()?),
-                        ))
-                    }
-                    FileInformationClassLevel::FileDirectoryInformation => {
-                        Some(FileInformationClass::FileDirectoryInformation(
-                            FileDirectoryInformation::from(fso)?,
-                        ))
-                    }
-                    _ => {
-                        return Err(invalid_data_error("received invalid FileInformationClassLevel in ServerDriveQueryDirectoryRequest"));
-                    }
-                };
+        if let Some(dir) = self
+            .file_cache_for(req.device_io_request.device_id)
+            .get_mut(req.device_io_request.file_id)
+        {
+            let entries = dir.next_batch(&req.file_info_class_lvl, DIR_QUERY_BATCH_BUFFER_SIZE)?;
 
+            if !entries.is_empty() {
                 return self.prep_drive_query_dir_response(
                     &req.device_io_request,
                     NTSTATUS::STATUS_SUCCESS,
-                    buffer,
+                    entries,
                 );
             }
 
@@ -728,7 +1327,7 @@ impl Client {
             return self.prep_drive_query_dir_response(
                 &req.device_io_request,
                 NTSTATUS::STATUS_NO_MORE_FILES,
-                None,
+                vec![],
             );
         }
 
@@ -740,7 +1339,7 @@ impl Client {
         &self,
         device_io_request: &DeviceIoRequest,
         io_status: NTSTATUS,
-        buffer: Option<FileInformationClass>,
+        buffer: Vec<FileInformationClass>,
     ) -> RdpResult<Vec<u8>> {
         let resp = ClientDriveQueryDirectoryResponse::new(device_io_request, io_status, buffer)?;
         debug!("sending RDP: {:?}", resp);
@@ -759,7 +1358,7 @@ impl Client {
         self.prep_drive_query_dir_response(
             &req.device_io_request,
             NTSTATUS::STATUS_UNSUCCESSFUL,
-            None,
+            vec![],
         )
     }
 
@@ -775,6 +1374,18 @@ impl Client {
         resp.encode()
     }
 
+    fn prep_notify_change_response(
+        &self,
+        device_io_request: &DeviceIoRequest,
+        io_status: NTSTATUS,
+        buffer: Option<FileNotifyInformation>,
+    ) -> RdpResult<Vec<u8>> {
+        let resp =
+            ClientDriveNotifyChangeDirectoryResponse::new(device_io_request, io_status, buffer);
+        debug!("sending RDP: {:?}", resp);
+        resp.encode()
+    }
+
     fn prep_read_response(
         &self,
         req: DeviceReadRequest,
@@ -814,14 +1425,23 @@ impl Client {
         rdp_req: DeviceCreateRequest,
         file_type: FileType,
     ) -> RdpResult<Vec<u8>> {
+        if !self.permissions.create {
+            return self.prep_device_create_response(&rdp_req, NTSTATUS::STATUS_ACCESS_DENIED, 0);
+        }
+
         let tdp_req = SharedDirectoryCreateRequest {
             completion_id: rdp_req.device_io_request.completion_id,
             directory_id: rdp_req.device_io_request.device_id,
             file_type,
             path: UnixPath::from(&rdp_req.path),
+            // IRP_MJ_CREATE has no concept of creating a reparse point; link_target is only
+            // meaningful when file_type is FileType::Symlink, which tdp_sd_create is never
+            // called with today.
+            link_target: UnixPath::from(String::new()),
         };
         (self.tdp_sd_create_request)(tdp_req)?;
 
+        self.track_irp(&rdp_req.device_io_request);
         self.pending_sd_create_resp_handlers.insert(
             rdp_req.device_io_request.completion_id,
             Box::new(
@@ -829,13 +1449,13 @@ impl Client {
                     if res.err_code != TdpErrCode::Nil {
                         return cli.prep_device_create_response(
                             &rdp_req,
-                            NTSTATUS::STATUS_UNSUCCESSFUL,
+                            tdp_err_to_ntstatus(res.err_code),
                             0,
                         );
                     }
 
-                    let file_id = cli.generate_file_id();
-                    cli.file_cache.insert(
+                    let file_id = cli.generate_file_id(rdp_req.device_io_request.device_id);
+                    cli.file_cache_for(rdp_req.device_io_request.device_id).insert(
                         file_id,
                         FileCacheObject::new(UnixPath::from(&rdp_req.path), res.fso),
                     );
@@ -846,28 +1466,57 @@ impl Client {
         Ok(vec![])
     }
 
-    /// Helper function for combining a TDP SharedDirectoryDeleteRequest
-    /// with a TDP SharedDirectoryCreateRequest to overwrite a file, based
-    /// on an RDP DeviceCreateRequest.
-    fn tdp_sd_overwrite(&mut self, rdp_req: DeviceCreateRequest) -> RdpResult<Vec<u8>> {
-        let tdp_req = SharedDirectoryDeleteRequest {
+    /// Helper function for overwriting an existing file's content, based on an RDP
+    /// DeviceCreateRequest whose CreateDisposition is FILE_SUPERSEDE, FILE_OVERWRITE, or
+    /// FILE_OVERWRITE_IF. Reuses the same TDP SharedDirectoryTruncateRequest machinery that
+    /// backs FileEndOfFileInformation/FileAllocationInformation (see tdp_sd_truncate), resetting
+    /// the file to zero length rather than deleting and recreating it. existing_fso is the
+    /// SharedDirectoryInfoResponse's FileSystemObject for this path, used to seed the cached
+    /// entry's metadata since SharedDirectoryTruncateResponse carries none of its own.
+    fn tdp_sd_overwrite(
+        &mut self,
+        rdp_req: DeviceCreateRequest,
+        existing_fso: FileSystemObject,
+    ) -> RdpResult<Vec<u8>> {
+        if !self.permissions.write {
+            return self.prep_device_create_response(&rdp_req, NTSTATUS::STATUS_ACCESS_DENIED, 0);
+        }
+
+        let tdp_req = SharedDirectoryTruncateRequest {
             completion_id: rdp_req.device_io_request.completion_id,
             directory_id: rdp_req.device_io_request.device_id,
             path: UnixPath::from(&rdp_req.path),
+            end_of_file: 0,
         };
-        (self.tdp_sd_delete_request)(tdp_req)?;
-        self.pending_sd_delete_resp_handlers.insert(
+        (self.tdp_sd_truncate_request)(tdp_req)?;
+        self.track_irp(&rdp_req.device_io_request);
+        self.pending_sd_truncate_resp_handlers.insert(
             rdp_req.device_io_request.completion_id,
             Box::new(
-                |cli: &mut Self, res: SharedDirectoryDeleteResponse| -> RdpResult<Vec<u8>> {
-                    match res.err_code {
-                        TdpErrCode::Nil => cli.tdp_sd_create(rdp_req, FileType::File),
-                        _ => cli.prep_device_create_response(
+                move |cli: &mut Self, res: SharedDirectoryTruncateResponse| -> RdpResult<Vec<u8>> {
+                    if res.err_code != TdpErrCode::Nil {
+                        return cli.prep_device_create_response(
                             &rdp_req,
-                            NTSTATUS::STATUS_UNSUCCESSFUL,
+                            tdp_err_to_ntstatus(res.err_code),
                             0,
-                        ),
+                        );
                     }
+
+                    let file_id = cli.generate_file_id(rdp_req.device_io_request.device_id);
+                    let path = UnixPath::from(&rdp_req.path);
+                    let fso = FileSystemObject {
+                        last_modified: existing_fso.last_modified,
+                        size: 0,
+                        file_type: FileType::File,
+                        is_empty: TDP_FALSE,
+                        path: path.clone(),
+                        symlink_target: UnixPath::from(String::new()),
+                        file_id: existing_fso.file_id,
+                        extended_attributes: existing_fso.extended_attributes,
+                    };
+                    cli.file_cache_for(rdp_req.device_io_request.device_id)
+                        .insert(file_id, FileCacheObject::new(path, fso));
+                    cli.prep_device_create_response(&rdp_req, NTSTATUS::STATUS_SUCCESS, file_id)
                 },
             ),
         );
@@ -879,22 +1528,22 @@ impl Client {
         rdp_req: DeviceCloseRequest,
         file: FileCacheObject,
     ) -> RdpResult<Vec<u8>> {
+        if !self.permissions.delete {
+            return self.prep_device_close_response(rdp_req, NTSTATUS::STATUS_ACCESS_DENIED);
+        }
+
         let tdp_req = SharedDirectoryDeleteRequest {
             completion_id: rdp_req.device_io_request.completion_id,
             directory_id: rdp_req.device_io_request.device_id,
             path: file.path,
         };
         (self.tdp_sd_delete_request)(tdp_req)?;
+        self.track_irp(&rdp_req.device_io_request);
         self.pending_sd_delete_resp_handlers.insert(
             rdp_req.device_io_request.completion_id,
             Box::new(
                 |cli: &mut Self, res: SharedDirectoryDeleteResponse| -> RdpResult<Vec<u8>> {
-                    let code = if res.err_code == TdpErrCode::Nil {
-                        NTSTATUS::STATUS_SUCCESS
-                    } else {
-                        NTSTATUS::STATUS_UNSUCCESSFUL
-                    };
-                    cli.prep_device_close_response(rdp_req, code)
+                    cli.prep_device_close_response(rdp_req, tdp_err_to_ntstatus(res.err_code))
                 },
             ),
         );
@@ -902,21 +1551,34 @@ impl Client {
     }
 
     fn tdp_sd_read(&mut self, rdp_req: DeviceReadRequest) -> RdpResult<Vec<u8>> {
-        if let Some(file) = self.file_cache.get(rdp_req.device_io_request.file_id) {
+        if let Some(file) = self
+            .file_cache_for(rdp_req.device_io_request.device_id)
+            .get(rdp_req.device_io_request.file_id)
+        {
+            let path = file.path.clone();
+
+            if rdp_req.length > self.max_tdp_chunk_size {
+                return self.start_chunked_read(rdp_req, path);
+            }
+
             let tdp_req = SharedDirectoryReadRequest {
                 completion_id: rdp_req.device_io_request.completion_id,
                 directory_id: rdp_req.device_io_request.device_id,
-                path: file.path.clone(),
+                path,
                 length: rdp_req.length,
                 offset: rdp_req.offset,
             };
             (self.tdp_sd_read_request)(tdp_req)?;
 
+            let offset = rdp_req.offset;
+            self.track_irp(&rdp_req.device_io_request);
             self.pending_sd_read_resp_handlers.insert(
                 rdp_req.device_io_request.completion_id,
                 Box::new(
                     move |cli: &mut Self, res: SharedDirectoryReadResponse| -> RdpResult<Vec<u8>> {
                         match res.err_code {
+                            TdpErrCode::Nil if cli.read_checksum_failed(offset, &res) => cli
+                                .prep_read_response(rdp_req, NTSTATUS::STATUS_DATA_ERROR, vec![]),
                             TdpErrCode::Nil => cli.prep_read_response(
                                 rdp_req,
                                 NTSTATUS::STATUS_SUCCESS,
@@ -924,7 +1586,7 @@ impl Client {
                             ),
                             _ => cli.prep_read_response(
                                 rdp_req,
-                                NTSTATUS::STATUS_UNSUCCESSFUL,
+                                tdp_err_to_ntstatus(res.err_code),
                                 vec![],
                             ),
                         }
@@ -939,18 +1601,187 @@ impl Client {
         self.prep_read_response(rdp_req, NTSTATUS::STATUS_UNSUCCESSFUL, vec![])
     }
 
+    /// Splits an IRP_MJ_READ whose length exceeds max_tdp_chunk_size into max_tdp_chunk_size-sized
+    /// SharedDirectoryReadRequests, then hands them to send_next_read_chunks to keep up to
+    /// max_chunks_in_flight of them outstanding at once. The original IRP isn't answered until
+    /// handle_read_chunk_response has stitched every chunk's data back together by offset.
+    fn start_chunked_read(
+        &mut self,
+        rdp_req: DeviceReadRequest,
+        path: UnixPath,
+    ) -> RdpResult<Vec<u8>> {
+        let completion_id = rdp_req.device_io_request.completion_id;
+
+        let mut queued = VecDeque::new();
+        let mut offset = rdp_req.offset;
+        let mut remaining = rdp_req.length;
+        while remaining > 0 {
+            let len = remaining.min(self.max_tdp_chunk_size);
+            queued.push_back((offset, len));
+            offset += u64::from(len);
+            remaining -= len;
+        }
+
+        self.track_irp(&rdp_req.device_io_request);
+        self.pending_chunked_reads.insert(
+            completion_id,
+            PendingChunkedRead {
+                rdp_req,
+                path,
+                queued,
+                in_flight: 0,
+                completed: BTreeMap::new(),
+                failed: None,
+                checksum_failed: false,
+                checksum_retries: HashMap::new(),
+            },
+        );
+
+        self.send_next_read_chunks(completion_id)?;
+        Ok(vec![])
+    }
+
+    /// Sends as many of a chunked read's still-queued chunks as needed to bring its number of
+    /// in-flight chunks up to max_chunks_in_flight.
+    fn send_next_read_chunks(&mut self, completion_id: u32) -> RdpResult<()> {
+        loop {
+            let max_chunks_in_flight = self.max_chunks_in_flight;
+            let chunk = match self.pending_chunked_reads.get_mut(&completion_id) {
+                Some(read) if read.failed.is_none() && read.in_flight < max_chunks_in_flight => {
+                    read.queued.pop_front()
+                }
+                _ => None,
+            };
+            let (offset, length) = match chunk {
+                Some(chunk) => chunk,
+                None => return Ok(()),
+            };
+
+            let read = self
+                .pending_chunked_reads
+                .get_mut(&completion_id)
+                .expect("just looked up above");
+            read.in_flight += 1;
+            let path = read.path.clone();
+            let directory_id = read.rdp_req.device_io_request.device_id;
+
+            let chunk_completion_id = self.generate_chunk_completion_id();
+            (self.tdp_sd_read_request)(SharedDirectoryReadRequest {
+                completion_id: chunk_completion_id,
+                directory_id,
+                path,
+                length,
+                offset,
+            })?;
+            self.pending_sd_read_resp_handlers.insert(
+                chunk_completion_id,
+                Box::new(
+                    move |cli: &mut Self, res: SharedDirectoryReadResponse| -> RdpResult<Vec<u8>> {
+                        cli.handle_read_chunk_response(completion_id, offset, length, res)
+                    },
+                ),
+            );
+        }
+    }
+
+    /// Handles the TDP response to a single chunk of a chunked read: records its data (or its
+    /// error, if any), tops the in-flight count back up from the queue, and once every chunk has
+    /// either completed or the transfer has failed, answers the original IRP exactly once. A
+    /// chunk that fails its checksum is resumed (re-requested at the same offset/length) up to
+    /// MAX_CHUNK_CHECKSUM_RETRIES times before the transfer is given up on.
+    fn handle_read_chunk_response(
+        &mut self,
+        completion_id: u32,
+        offset: u64,
+        length: u32,
+        res: SharedDirectoryReadResponse,
+    ) -> RdpResult<Vec<u8>> {
+        let read = match self.pending_chunked_reads.get_mut(&completion_id) {
+            Some(read) => read,
+            // The transfer already finished (e.g. an earlier chunk failed it) or was cancelled;
+            // this is a late response for a chunk we've stopped caring about.
+            None => return Ok(vec![]),
+        };
+
+        read.in_flight -= 1;
+        let checksum_failed = match res.err_code {
+            TdpErrCode::Nil => self.read_checksum_failed(offset, &res),
+            _ => false,
+        };
+        let read = self
+            .pending_chunked_reads
+            .get_mut(&completion_id)
+            .expect("looked up at the top of this function");
+        if checksum_failed {
+            let retries = read.checksum_retries.entry(offset).or_insert(0);
+            if *retries < MAX_CHUNK_CHECKSUM_RETRIES {
+                *retries += 1;
+                read.queued.push_front((offset, length));
+            } else {
+                read.checksum_failed = true;
+            }
+        } else {
+            match res.err_code {
+                TdpErrCode::Nil => {
+                    read.completed.insert(offset, res.read_data);
+                }
+                _ if read.failed.is_none() => read.failed = Some(res.err_code),
+                _ => {}
+            }
+        }
+
+        if read.in_flight == 0
+            && (read.queued.is_empty() || read.failed.is_some() || read.checksum_failed)
+        {
+            let read = self
+                .pending_chunked_reads
+                .remove(&completion_id)
+                .expect("just looked up above");
+            self.untrack_irp(completion_id);
+            return match read.failed {
+                _ if read.checksum_failed => {
+                    self.prep_read_response(read.rdp_req, NTSTATUS::STATUS_DATA_ERROR, vec![])
+                }
+                Some(err_code) => {
+                    self.prep_read_response(read.rdp_req, tdp_err_to_ntstatus(err_code), vec![])
+                }
+                None => {
+                    let data = read.completed.into_values().flatten().collect();
+                    self.prep_read_response(read.rdp_req, NTSTATUS::STATUS_SUCCESS, data)
+                }
+            };
+        }
+
+        self.send_next_read_chunks(completion_id)?;
+        Ok(vec![])
+    }
+
     fn tdp_sd_write(&mut self, rdp_req: DeviceWriteRequest) -> RdpResult<Vec<u8>> {
-        if let Some(file) = self.file_cache.get(rdp_req.device_io_request.file_id) {
+        if let Some(file) = self
+            .file_cache_for(rdp_req.device_io_request.device_id)
+            .get(rdp_req.device_io_request.file_id)
+        {
+            let path = file.path.clone();
+
+            if rdp_req.length > self.max_tdp_chunk_size {
+                return self.start_chunked_write(rdp_req, path);
+            }
+
+            let checksum = self
+                .verify_integrity
+                .then(|| Self::checksum(&rdp_req.write_data));
             let tdp_req = SharedDirectoryWriteRequest {
                 completion_id: rdp_req.device_io_request.completion_id,
                 directory_id: rdp_req.device_io_request.device_id,
-                path: file.path.clone(),
+                path,
                 offset: rdp_req.offset,
                 write_data: rdp_req.write_data,
+                checksum,
             };
             (self.tdp_sd_write_request)(tdp_req)?;
 
             let device_io_request = rdp_req.device_io_request;
+            self.track_irp(&device_io_request);
             self.pending_sd_write_resp_handlers.insert(
                 device_io_request.completion_id,
                 Box::new(
@@ -965,7 +1796,7 @@ impl Client {
                             ),
                             _ => cli.prep_write_response(
                                 device_io_request,
-                                NTSTATUS::STATUS_UNSUCCESSFUL,
+                                tdp_err_to_ntstatus(res.err_code),
                                 0,
                             ),
                         }
@@ -980,30 +1811,168 @@ impl Client {
         self.prep_write_response(rdp_req.device_io_request, NTSTATUS::STATUS_UNSUCCESSFUL, 0)
     }
 
-    fn tdp_sd_move(
+    /// Splits an IRP_MJ_WRITE whose length exceeds max_tdp_chunk_size into max_tdp_chunk_size-sized
+    /// SharedDirectoryWriteRequests, then hands them to send_next_write_chunks to keep up to
+    /// max_chunks_in_flight of them outstanding at once. The original IRP isn't answered until
+    /// handle_write_chunk_response has seen every chunk's response.
+    fn start_chunked_write(
         &mut self,
-        rdp_req: ServerDriveSetInformationRequest,
-        rename_info: &FileRenameInformation,
-        io_status: NTSTATUS,
+        rdp_req: DeviceWriteRequest,
+        path: UnixPath,
     ) -> RdpResult<Vec<u8>> {
-        if let Some(file) = self.file_cache.get(rdp_req.device_io_request.file_id) {
-            (self.tdp_sd_move_request)(SharedDirectoryMoveRequest {
-                completion_id: rdp_req.device_io_request.completion_id,
-                directory_id: rdp_req.device_io_request.device_id,
-                original_path: file.path.clone(),
-                new_path: UnixPath::from(&rename_info.file_name),
-            })?;
+        let device_io_request = rdp_req.device_io_request;
+        let completion_id = device_io_request.completion_id;
+        let directory_id = device_io_request.device_id;
+
+        let mut queued = VecDeque::new();
+        let mut offset = rdp_req.offset;
+        for chunk in rdp_req.write_data.chunks(self.max_tdp_chunk_size as usize) {
+            queued.push_back((offset, chunk.to_vec()));
+            offset += chunk.len() as u64;
+        }
 
-            self.pending_sd_move_resp_handlers.insert(
-                rdp_req.device_io_request.completion_id,
-                Box::new(
-                    move |cli: &mut Self, res: SharedDirectoryMoveResponse| -> RdpResult<Vec<u8>> {
-                        if res.err_code != TdpErrCode::Nil {
-                            return cli
-                                .prep_set_info_response(&rdp_req, NTSTATUS::STATUS_UNSUCCESSFUL);
-                        }
+        self.track_irp(&device_io_request);
+        self.pending_chunked_writes.insert(
+            completion_id,
+            PendingChunkedWrite {
+                device_io_request,
+                directory_id,
+                path,
+                queued,
+                in_flight: 0,
+                bytes_written: 0,
+                failed: None,
+            },
+        );
 
-                        cli.prep_set_info_response(&rdp_req, io_status)
+        self.send_next_write_chunks(completion_id)?;
+        Ok(vec![])
+    }
+
+    /// Sends as many of a chunked write's still-queued chunks as needed to bring its number of
+    /// in-flight chunks up to max_chunks_in_flight.
+    fn send_next_write_chunks(&mut self, completion_id: u32) -> RdpResult<()> {
+        loop {
+            let max_chunks_in_flight = self.max_chunks_in_flight;
+            let chunk = match self.pending_chunked_writes.get_mut(&completion_id) {
+                Some(write) if write.failed.is_none() && write.in_flight < max_chunks_in_flight => {
+                    write.queued.pop_front()
+                }
+                _ => None,
+            };
+            let (offset, data) = match chunk {
+                Some(chunk) => chunk,
+                None => return Ok(()),
+            };
+
+            let write = self
+                .pending_chunked_writes
+                .get_mut(&completion_id)
+                .expect("just looked up above");
+            write.in_flight += 1;
+            let path = write.path.clone();
+            let directory_id = write.directory_id;
+
+            let chunk_completion_id = self.generate_chunk_completion_id();
+            let checksum = self.verify_integrity.then(|| Self::checksum(&data));
+            (self.tdp_sd_write_request)(SharedDirectoryWriteRequest {
+                completion_id: chunk_completion_id,
+                directory_id,
+                path,
+                offset,
+                write_data: data,
+                checksum,
+            })?;
+            self.pending_sd_write_resp_handlers.insert(
+                chunk_completion_id,
+                Box::new(
+                    move |cli: &mut Self,
+                          res: SharedDirectoryWriteResponse|
+                          -> RdpResult<Vec<u8>> {
+                        cli.handle_write_chunk_response(completion_id, res)
+                    },
+                ),
+            );
+        }
+    }
+
+    /// Handles the TDP response to a single chunk of a chunked write: totals up its bytes
+    /// written (or records its error, if any), tops the in-flight count back up from the queue,
+    /// and once every chunk has either completed or the transfer has failed, answers the
+    /// original IRP exactly once.
+    fn handle_write_chunk_response(
+        &mut self,
+        completion_id: u32,
+        res: SharedDirectoryWriteResponse,
+    ) -> RdpResult<Vec<u8>> {
+        let write = match self.pending_chunked_writes.get_mut(&completion_id) {
+            Some(write) => write,
+            // The transfer already finished (e.g. an earlier chunk failed it) or was cancelled;
+            // this is a late response for a chunk we've stopped caring about.
+            None => return Ok(vec![]),
+        };
+
+        write.in_flight -= 1;
+        match res.err_code {
+            TdpErrCode::Nil => write.bytes_written += res.bytes_written,
+            _ if write.failed.is_none() => write.failed = Some(res.err_code),
+            _ => {}
+        }
+
+        if write.in_flight == 0 && (write.queued.is_empty() || write.failed.is_some()) {
+            let write = self
+                .pending_chunked_writes
+                .remove(&completion_id)
+                .expect("just looked up above");
+            self.untrack_irp(completion_id);
+            return match write.failed {
+                Some(err_code) => self.prep_write_response(
+                    write.device_io_request,
+                    tdp_err_to_ntstatus(err_code),
+                    0,
+                ),
+                None => self.prep_write_response(
+                    write.device_io_request,
+                    NTSTATUS::STATUS_SUCCESS,
+                    write.bytes_written,
+                ),
+            };
+        }
+
+        self.send_next_write_chunks(completion_id)?;
+        Ok(vec![])
+    }
+
+    fn tdp_sd_move(
+        &mut self,
+        rdp_req: ServerDriveSetInformationRequest,
+        rename_info: &FileRenameInformation,
+        io_status: NTSTATUS,
+    ) -> RdpResult<Vec<u8>> {
+        if let Some(file) = self
+            .file_cache_for(rdp_req.device_io_request.device_id)
+            .get(rdp_req.device_io_request.file_id)
+        {
+            (self.tdp_sd_move_request)(SharedDirectoryMoveRequest {
+                completion_id: rdp_req.device_io_request.completion_id,
+                directory_id: rdp_req.device_io_request.device_id,
+                original_path: file.path.clone(),
+                new_path: UnixPath::from(&rename_info.file_name),
+            })?;
+
+            self.track_irp(&rdp_req.device_io_request);
+            self.pending_sd_move_resp_handlers.insert(
+                rdp_req.device_io_request.completion_id,
+                Box::new(
+                    move |cli: &mut Self, res: SharedDirectoryMoveResponse| -> RdpResult<Vec<u8>> {
+                        if res.err_code != TdpErrCode::Nil {
+                            return cli.prep_set_info_response(
+                                &rdp_req,
+                                tdp_err_to_ntstatus(res.err_code),
+                            );
+                        }
+
+                        cli.prep_set_info_response(&rdp_req, io_status)
                     },
                 ),
             );
@@ -1015,12 +1984,115 @@ impl Client {
         self.prep_set_info_response(&rdp_req, NTSTATUS::STATUS_UNSUCCESSFUL)
     }
 
+    fn tdp_sd_truncate(
+        &mut self,
+        rdp_req: ServerDriveSetInformationRequest,
+        end_of_file: i64,
+        io_status: NTSTATUS,
+    ) -> RdpResult<Vec<u8>> {
+        if !self.permissions.write {
+            return self.prep_set_info_response(&rdp_req, NTSTATUS::STATUS_ACCESS_DENIED);
+        }
+
+        // FileEndOfFileInformation/FileAllocationInformation's size field is a signed
+        // LARGE_INTEGER, but a file size can never actually be negative; reject it here rather
+        // than forwarding a nonsensical value over TDP for the browser to reject instead.
+        if end_of_file < 0 {
+            return self.prep_set_info_response(&rdp_req, NTSTATUS::STATUS_UNSUCCESSFUL);
+        }
+
+        let path = match self
+            .file_cache_for(rdp_req.device_io_request.device_id)
+            .get(rdp_req.device_io_request.file_id)
+        {
+            Some(file) => file.path.clone(),
+            None => return self.prep_set_info_response(&rdp_req, NTSTATUS::STATUS_UNSUCCESSFUL),
+        };
+
+        (self.tdp_sd_truncate_request)(SharedDirectoryTruncateRequest {
+            completion_id: rdp_req.device_io_request.completion_id,
+            directory_id: rdp_req.device_io_request.device_id,
+            path,
+            end_of_file,
+        })?;
+
+        self.track_irp(&rdp_req.device_io_request);
+        self.pending_sd_truncate_resp_handlers.insert(
+            rdp_req.device_io_request.completion_id,
+            Box::new(
+                move |cli: &mut Self, res: SharedDirectoryTruncateResponse| -> RdpResult<Vec<u8>> {
+                    if res.err_code != TdpErrCode::Nil {
+                        return cli
+                            .prep_set_info_response(&rdp_req, tdp_err_to_ntstatus(res.err_code));
+                    }
+
+                    cli.prep_set_info_response(&rdp_req, io_status)
+                },
+            ),
+        );
+
+        Ok(vec![])
+    }
+
+    fn tdp_sd_set_timestamps(
+        &mut self,
+        rdp_req: ServerDriveSetInformationRequest,
+        creation_time: i64,
+        last_write_time: i64,
+        io_status: NTSTATUS,
+    ) -> RdpResult<Vec<u8>> {
+        let path = match self
+            .file_cache_for(rdp_req.device_io_request.device_id)
+            .get(rdp_req.device_io_request.file_id)
+        {
+            Some(file) => file.path.clone(),
+            None => return self.prep_set_info_response(&rdp_req, NTSTATUS::STATUS_UNSUCCESSFUL),
+        };
+
+        (self.tdp_sd_set_timestamps_request)(SharedDirectorySetTimestampsRequest {
+            completion_id: rdp_req.device_io_request.completion_id,
+            directory_id: rdp_req.device_io_request.device_id,
+            path,
+            creation_time: from_windows_time(creation_time),
+            last_write_time: from_windows_time(last_write_time),
+        })?;
+
+        self.track_irp(&rdp_req.device_io_request);
+        self.pending_sd_set_timestamps_resp_handlers.insert(
+            rdp_req.device_io_request.completion_id,
+            Box::new(
+                move |cli: &mut Self,
+                      res: SharedDirectorySetTimestampsResponse|
+                      -> RdpResult<Vec<u8>> {
+                    if res.err_code != TdpErrCode::Nil {
+                        return cli
+                            .prep_set_info_response(&rdp_req, tdp_err_to_ntstatus(res.err_code));
+                    }
+
+                    cli.prep_set_info_response(&rdp_req, io_status)
+                },
+            ),
+        );
+
+        Ok(vec![])
+    }
+
     fn rename(
         &mut self,
         rdp_req: ServerDriveSetInformationRequest,
         rename_info: &FileRenameInformation,
         io_status: NTSTATUS,
     ) -> RdpResult<Vec<u8>> {
+        if !self.permissions.rename {
+            return self.prep_set_info_response(&rdp_req, NTSTATUS::STATUS_ACCESS_DENIED);
+        }
+
+        // Reject a rename/move whose destination would lexically resolve outside the shared
+        // directory's root before we ever talk to the browser about it.
+        if !is_confined_to_share(&UnixPath::from(&rename_info.file_name)) {
+            return self.prep_set_info_response(&rdp_req, NTSTATUS::STATUS_OBJECT_PATH_INVALID);
+        }
+
         // https://github.com/FreeRDP/FreeRDP/blob/dfa231c0a55b005af775b833f92f6bcd30363d77/channels/drive/client/drive_file.c#L709
         match rename_info.replace_if_exists {
             Boolean::True => self.rename_replace_if_exists(rdp_req, rename_info, io_status),
@@ -1054,6 +2126,7 @@ impl Client {
         })?;
 
         let rename_info = (*rename_info).clone();
+        self.track_irp(&rdp_req.device_io_request);
         self.pending_sd_info_resp_handlers.insert(
             rdp_req.device_io_request.completion_id,
             Box::new(
@@ -1077,6 +2150,7 @@ impl Client {
         mcs: &mut mcs::Client<S>,
     ) -> RdpResult<()> {
         debug!("received TDP SharedDirectoryInfoResponse: {:?}", res);
+        self.untrack_irp(res.completion_id);
         if let Some(tdp_resp_handler) = self
             .pending_sd_info_resp_handlers
             .remove(&res.completion_id)
@@ -1101,6 +2175,7 @@ impl Client {
         mcs: &mut mcs::Client<S>,
     ) -> RdpResult<()> {
         debug!("received TDP SharedDirectoryCreateResponse: {:?}", res);
+        self.untrack_irp(res.completion_id);
         if let Some(tdp_resp_handler) = self
             .pending_sd_create_resp_handlers
             .remove(&res.completion_id)
@@ -1125,6 +2200,7 @@ impl Client {
         mcs: &mut mcs::Client<S>,
     ) -> RdpResult<()> {
         debug!("received TDP SharedDirectoryDeleteResponse: {:?}", res);
+        self.untrack_irp(res.completion_id);
         if let Some(tdp_resp_handler) = self
             .pending_sd_delete_resp_handlers
             .remove(&res.completion_id)
@@ -1149,6 +2225,7 @@ impl Client {
         mcs: &mut mcs::Client<S>,
     ) -> RdpResult<()> {
         debug!("received TDP SharedDirectoryListResponse: {:?}", res);
+        self.untrack_irp(res.completion_id);
         if let Some(tdp_resp_handler) = self
             .pending_sd_list_resp_handlers
             .remove(&res.completion_id)
@@ -1173,6 +2250,7 @@ impl Client {
         mcs: &mut mcs::Client<S>,
     ) -> RdpResult<()> {
         debug!("received TDP: {:?}", res);
+        self.untrack_irp(res.completion_id);
         if let Some(tdp_resp_handler) = self
             .pending_sd_read_resp_handlers
             .remove(&res.completion_id)
@@ -1197,6 +2275,7 @@ impl Client {
         mcs: &mut mcs::Client<S>,
     ) -> RdpResult<()> {
         debug!("received TDP: {:?}", res);
+        self.untrack_irp(res.completion_id);
         if let Some(tdp_resp_handler) = self
             .pending_sd_write_resp_handlers
             .remove(&res.completion_id)
@@ -1221,6 +2300,7 @@ impl Client {
         mcs: &mut mcs::Client<S>,
     ) -> RdpResult<()> {
         debug!("received TDP SharedDirectoryMoveResponse: {:?}", res);
+        self.untrack_irp(res.completion_id);
         if let Some(tdp_resp_handler) = self
             .pending_sd_move_resp_handlers
             .remove(&res.completion_id)
@@ -1239,9 +2319,330 @@ impl Client {
         )))
     }
 
-    fn generate_file_id(&mut self) -> u32 {
-        self.next_file_id = self.next_file_id.wrapping_add(1);
-        self.next_file_id
+    pub fn handle_tdp_sd_truncate_response<S: Read + Write>(
+        &mut self,
+        res: SharedDirectoryTruncateResponse,
+        mcs: &mut mcs::Client<S>,
+    ) -> RdpResult<()> {
+        debug!("received TDP SharedDirectoryTruncateResponse: {:?}", res);
+        self.untrack_irp(res.completion_id);
+        if let Some(tdp_resp_handler) = self
+            .pending_sd_truncate_resp_handlers
+            .remove(&res.completion_id)
+        {
+            let rdp_responses = tdp_resp_handler(self, res)?;
+            let chan = &CHANNEL_NAME.to_string();
+            for resp in rdp_responses {
+                mcs.write(chan, resp)?;
+            }
+            return Ok(());
+        }
+
+        Err(try_error(&format!(
+            "received invalid completion id: {}",
+            res.completion_id
+        )))
+    }
+
+    pub fn handle_tdp_sd_set_timestamps_response<S: Read + Write>(
+        &mut self,
+        res: SharedDirectorySetTimestampsResponse,
+        mcs: &mut mcs::Client<S>,
+    ) -> RdpResult<()> {
+        debug!(
+            "received TDP SharedDirectorySetTimestampsResponse: {:?}",
+            res
+        );
+        self.untrack_irp(res.completion_id);
+        if let Some(tdp_resp_handler) = self
+            .pending_sd_set_timestamps_resp_handlers
+            .remove(&res.completion_id)
+        {
+            let rdp_responses = tdp_resp_handler(self, res)?;
+            let chan = &CHANNEL_NAME.to_string();
+            for resp in rdp_responses {
+                mcs.write(chan, resp)?;
+            }
+            return Ok(());
+        }
+
+        Err(try_error(&format!(
+            "received invalid completion id: {}",
+            res.completion_id
+        )))
+    }
+
+    pub fn handle_tdp_sd_free_space_response<S: Read + Write>(
+        &mut self,
+        res: SharedDirectoryFreeSpaceResponse,
+        mcs: &mut mcs::Client<S>,
+    ) -> RdpResult<()> {
+        debug!("received TDP SharedDirectoryFreeSpaceResponse: {:?}", res);
+        self.untrack_irp(res.completion_id);
+        if let Some(tdp_resp_handler) = self
+            .pending_sd_free_space_resp_handlers
+            .remove(&res.completion_id)
+        {
+            let rdp_responses = tdp_resp_handler(self, res)?;
+            let chan = &CHANNEL_NAME.to_string();
+            for resp in rdp_responses {
+                mcs.write(chan, resp)?;
+            }
+            return Ok(());
+        }
+
+        Err(try_error(&format!(
+            "received invalid completion id: {}",
+            res.completion_id
+        )))
+    }
+
+    /// Releases any outstanding IRP_MN_NOTIFY_CHANGE_DIRECTORY registered against (device_id,
+    /// file_id), completing each with STATUS_CANCELLED: the handle it was watching is gone, so
+    /// the RDP server needs to be told this IRP is retired rather than left hanging forever.
+    fn release_watch<S: Read + Write>(
+        &mut self,
+        device_id: u32,
+        file_id: u32,
+        mcs: &mut mcs::Client<S>,
+    ) -> RdpResult<()> {
+        let stale: Vec<DeviceIoRequest> = self
+            .pending_sd_notify_resp_handlers
+            .iter()
+            .filter(|(_, watch)| {
+                watch.device_io_request.device_id == device_id
+                    && watch.device_io_request.file_id == file_id
+            })
+            .map(|(_, watch)| watch.device_io_request.clone())
+            .collect();
+
+        for device_io_request in stale {
+            self.untrack_irp(device_io_request.completion_id);
+            self.pending_sd_notify_resp_handlers
+                .remove(&device_io_request.completion_id);
+            let resp = self.prep_notify_change_response(
+                &device_io_request,
+                NTSTATUS::STATUS_CANCELLED,
+                None,
+            )?;
+            mcs.write(&CHANNEL_NAME.to_string(), resp)?;
+        }
+
+        Ok(())
+    }
+
+    /// Queues a filesystem change that arrived with no matching outstanding watch, so a future
+    /// IRP_MN_NOTIFY_CHANGE_DIRECTORY on the same directory can be completed from it immediately
+    /// by try_complete_from_queue instead of waiting for the next live change. A change that
+    /// itself failed (err_code != Nil) carries no directory_id-scoped information worth keeping,
+    /// so it's dropped rather than queued.
+    fn queue_notify_event(&mut self, res: &SharedDirectoryChangeNotifyResponse) {
+        if res.err_code != TdpErrCode::Nil {
+            return;
+        }
+
+        let queue = self
+            .pending_notify_events
+            .entry(res.directory_id)
+            .or_insert_with(VecDeque::new);
+
+        if let Some(last) = queue.back() {
+            if last.action == res.action
+                && last.path.to_string() == res.path.to_string()
+                && last.queued_at.elapsed() < NOTIFY_DEBOUNCE_WINDOW
+            {
+                // A near-duplicate of the most recently queued change; collapse it rather than
+                // queuing a second entry for what is really one logical change.
+                return;
+            }
+        }
+
+        if queue.len() >= MAX_QUEUED_NOTIFY_EVENTS {
+            queue.pop_front();
+        }
+        queue.push_back(QueuedNotifyEvent {
+            action: res.action,
+            path: res.path.clone(),
+            queued_at: Instant::now(),
+        });
+    }
+
+    /// Looks for a queued change under watched_path (registered for directory_id) that the new
+    /// watch described by watch_tree/completion_filter cares about, consuming and returning an
+    /// encoded ClientDriveNotifyChangeDirectoryResponse for the oldest one found. Returns None,
+    /// leaving the queue untouched, if nothing queued for this directory currently qualifies.
+    fn try_complete_from_queue(
+        &mut self,
+        directory_id: u32,
+        watched_path: &UnixPath,
+        watch_tree: u8,
+        completion_filter: flags::CompletionFilter,
+        device_io_request: &DeviceIoRequest,
+    ) -> RdpResult<Option<Vec<u8>>> {
+        let queue = match self.pending_notify_events.get_mut(&directory_id) {
+            Some(queue) => queue,
+            None => return Ok(None),
+        };
+
+        let qualifying_index = queue.iter().position(|event| {
+            match relative_to_watch(watched_path, &event.path) {
+                Some((_, is_direct_child)) => {
+                    (watch_tree != 0 || is_direct_child)
+                        && watch_interested_in(completion_filter, event.action)
+                }
+                None => false,
+            }
+        });
+
+        let event = match qualifying_index.and_then(|i| queue.remove(i)) {
+            Some(event) => event,
+            None => return Ok(None),
+        };
+
+        // Already matched against watched_path above, so this can't fail.
+        let (file_name, _) = relative_to_watch(watched_path, &event.path).unwrap();
+
+        let resp = self.prep_notify_change_response(
+            device_io_request,
+            NTSTATUS::STATUS_SUCCESS,
+            Some(FileNotifyInformation::new(
+                file_action_code(event.action),
+                file_name,
+            )),
+        )?;
+        Ok(Some(resp))
+    }
+
+    /// Handles a SharedDirectoryChangeNotifyResponse reporting a single filesystem change. Unlike
+    /// every other handle_tdp_sd_*_response method, this one does not necessarily remove its
+    /// completion_id's entry from its pending_sd_*_resp_handlers map: the browser may report
+    /// changes that this watch didn't ask about (wrong completion_filter bits, or outside the
+    /// watched tree), in which case the watch stays registered and waits for the next one.
+    pub fn handle_tdp_sd_change_notify_response<S: Read + Write>(
+        &mut self,
+        res: SharedDirectoryChangeNotifyResponse,
+        mcs: &mut mcs::Client<S>,
+    ) -> RdpResult<()> {
+        debug!(
+            "received TDP SharedDirectoryChangeNotifyResponse: {:?}",
+            res
+        );
+        let watch = match self.pending_sd_notify_resp_handlers.get(&res.completion_id) {
+            Some(watch) => watch.clone(),
+            None => {
+                // No IRP is currently outstanding for this completion_id - it may have already
+                // been cancelled or its FileId closed, or (most commonly) the watch it was
+                // answering already completed and Windows hasn't issued a new one yet. Queue it
+                // in case a future watch on the same directory can use it.
+                debug!(
+                    "no outstanding watch for completion_id {}; queuing change for directory_id {}",
+                    res.completion_id, res.directory_id
+                );
+                self.queue_notify_event(&res);
+                return Ok(());
+            }
+        };
+
+        if res.err_code != TdpErrCode::Nil {
+            self.untrack_irp(res.completion_id);
+            self.pending_sd_notify_resp_handlers
+                .remove(&res.completion_id);
+            let resp = self.prep_notify_change_response(
+                &watch.device_io_request,
+                tdp_err_to_ntstatus(res.err_code),
+                None,
+            )?;
+            mcs.write(&CHANNEL_NAME.to_string(), resp)?;
+            return Ok(());
+        }
+
+        let (file_name, is_direct_child) = match relative_to_watch(&watch.path, &res.path) {
+            Some(relative) => relative,
+            // Not actually under the watched directory; nothing for this watch to report.
+            None => return Ok(()),
+        };
+
+        if watch.watch_tree == 0 && !is_direct_child {
+            // This watch was only asked to cover the root directory, not its subtree.
+            return Ok(());
+        }
+
+        if !watch_interested_in(watch.completion_filter, res.action) {
+            // RDP didn't set a CompletionFilter bit for this kind of change.
+            return Ok(());
+        }
+
+        self.untrack_irp(res.completion_id);
+        self.pending_sd_notify_resp_handlers
+            .remove(&res.completion_id);
+
+        let resp = self.prep_notify_change_response(
+            &watch.device_io_request,
+            NTSTATUS::STATUS_SUCCESS,
+            Some(FileNotifyInformation::new(
+                file_action_code(res.action),
+                file_name,
+            )),
+        )?;
+        mcs.write(&CHANNEL_NAME.to_string(), resp)?;
+
+        Ok(())
+    }
+
+    fn generate_file_id(&mut self, device_id: u32) -> u32 {
+        let next_file_id = self.next_file_ids.entry(device_id).or_insert(0);
+        *next_file_id = next_file_id.wrapping_add(1);
+        *next_file_id
+    }
+
+    /// Generates a CompletionId for an internal sub-request (currently, one chunk of a split
+    /// IRP_MJ_READ/IRP_MJ_WRITE) that doesn't correspond 1:1 with an RDP IRP. These are drawn
+    /// from the upper half of the u32 space so they can never collide with a real IRP's
+    /// CompletionId, which RDP servers assign starting from 0.
+    fn generate_chunk_completion_id(&mut self) -> u32 {
+        self.next_chunk_completion_id = self.next_chunk_completion_id.wrapping_add(1);
+        0x8000_0000 | self.next_chunk_completion_id
+    }
+
+    /// Returns a CRC32 of data, computed incrementally so it can be reused for large transfers
+    /// without buffering more than one chunk at a time. Only called when verify_integrity is on.
+    fn checksum(data: &[u8]) -> u32 {
+        let mut hasher = Crc32Hasher::new();
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    /// Returns true if res carries a checksum that doesn't match its read_data, logging the
+    /// offending completion_id and offset so the corrupted chunk can be localized. A response
+    /// with no checksum (or verify_integrity disabled) is always considered valid, so peers that
+    /// don't send a checksum field stay compatible.
+    fn read_checksum_failed(&self, offset: u64, res: &SharedDirectoryReadResponse) -> bool {
+        match res.checksum {
+            Some(expected) if self.verify_integrity => {
+                let actual = Self::checksum(&res.read_data);
+                if actual != expected {
+                    error!(
+                        "checksum mismatch for completion_id {} at offset {}: expected {:#010x}, got {:#010x}",
+                        res.completion_id, offset, expected, actual
+                    );
+                    true
+                } else {
+                    false
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// file_cache_for returns the FileCache for device_id, creating one (and a fresh FileId
+    /// namespace) if this is the first time this device_id has been seen. This makes the client
+    /// tolerant of a ServerDeviceAnnounceResponse that arrives after process_irp_create is
+    /// already in flight for the same device.
+    fn file_cache_for(&mut self, device_id: u32) -> &mut FileCache {
+        self.next_file_ids.entry(device_id).or_insert(0);
+        self.file_caches
+            .entry(device_id)
+            .or_insert_with(FileCache::new)
     }
 }
 
@@ -1285,6 +2686,188 @@ impl FileCache {
     }
 }
 
+/// Maps a TdpErrCode reported by the browser to the NTSTATUS we complete the corresponding IRP
+/// with, so a failed TDP operation surfaces as an accurate error to the RDP server (and, in turn,
+/// an accurate dialog in the Windows client) rather than an opaque STATUS_UNSUCCESSFUL.
+fn tdp_err_to_ntstatus(err_code: TdpErrCode) -> NTSTATUS {
+    match err_code {
+        TdpErrCode::Nil => NTSTATUS::STATUS_SUCCESS,
+        TdpErrCode::DoesNotExist => NTSTATUS::STATUS_NO_SUCH_FILE,
+        TdpErrCode::AlreadyExists => NTSTATUS::STATUS_OBJECT_NAME_COLLISION,
+        TdpErrCode::AccessDenied => NTSTATUS::STATUS_ACCESS_DENIED,
+        TdpErrCode::Failed => NTSTATUS::STATUS_UNSUCCESSFUL,
+    }
+}
+
+/// Reports whether `path`, once its `.`/`..` segments are resolved lexically, stays at or below
+/// the shared directory's root rather than escaping it through a leading or unbalanced `..`. This
+/// guards against a malicious or buggy peer walking a path out of the share, mirroring the
+/// root-jail the seneschal share daemon enforces on its side of the TDP connection.
+fn is_confined_to_share(path: &UnixPath) -> bool {
+    let mut depth: i32 = 0;
+    for component in path.to_string().split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                depth -= 1;
+                if depth < 0 {
+                    return false;
+                }
+            }
+            _ => depth += 1,
+        }
+    }
+    true
+}
+
+/// Computes `changed`'s path relative to the watched directory `watched`, returning the relative
+/// path together with whether it's a direct child of `watched` (as opposed to nested somewhere
+/// deeper in its tree). Returns None if `changed` isn't actually under `watched` at all, which can
+/// happen if the browser's view of the share has drifted from what RDP last asked us to watch.
+fn relative_to_watch(watched: &UnixPath, changed: &UnixPath) -> Option<(String, bool)> {
+    let watched = watched.to_string();
+    let changed = changed.to_string();
+    let suffix = changed.strip_prefix(&watched)?;
+    // "/ab/c" starts with "/a" but isn't under it; require the match to end on a '/'.
+    if !watched.is_empty() && !watched.ends_with('/') && !suffix.starts_with('/') {
+        return None;
+    }
+    let suffix = suffix.trim_start_matches('/');
+    if suffix.is_empty() {
+        return None;
+    }
+    Some((suffix.to_string(), !suffix.contains('/')))
+}
+
+/// Reports whether a watch registered with completion_filter should be woken up by a change of
+/// the given action, matching the CompletionFilter bits from the IRP_MN_NOTIFY_CHANGE_DIRECTORY
+/// request against the kind of change being reported.
+fn watch_interested_in(
+    completion_filter: flags::CompletionFilter,
+    action: FileSystemEventAction,
+) -> bool {
+    match action {
+        FileSystemEventAction::Added
+        | FileSystemEventAction::Removed
+        | FileSystemEventAction::RenamedOldName
+        | FileSystemEventAction::RenamedNewName => completion_filter.intersects(
+            flags::CompletionFilter::FILE_NOTIFY_CHANGE_FILE_NAME
+                | flags::CompletionFilter::FILE_NOTIFY_CHANGE_DIR_NAME,
+        ),
+        FileSystemEventAction::Modified => completion_filter.intersects(
+            flags::CompletionFilter::FILE_NOTIFY_CHANGE_SIZE
+                | flags::CompletionFilter::FILE_NOTIFY_CHANGE_LAST_WRITE,
+        ),
+    }
+}
+
+/// Maps a FileSystemEventAction to the FILE_ACTION_* wire value MS-FSCC 2.6.1 expects in a
+/// FILE_NOTIFY_INFORMATION's Action field.
+fn file_action_code(action: FileSystemEventAction) -> u32 {
+    match action {
+        FileSystemEventAction::Added => 0x0000_0001, // FILE_ACTION_ADDED
+        FileSystemEventAction::Removed => 0x0000_0002, // FILE_ACTION_REMOVED
+        FileSystemEventAction::Modified => 0x0000_0003, // FILE_ACTION_MODIFIED
+        FileSystemEventAction::RenamedOldName => 0x0000_0004, // FILE_ACTION_RENAMED_OLD_NAME
+        FileSystemEventAction::RenamedNewName => 0x0000_0005, // FILE_ACTION_RENAMED_NEW_NAME
+    }
+}
+
+/// DOS_STAR, DOS_QM and DOS_DOT are the special wildcard constants Windows' FsRtlIsNameInExpression
+/// recognizes in addition to the ordinary '*' and '?', preserved here for compatibility with
+/// expressions built for 8.3 short names.
+const DOS_STAR: char = '<';
+const DOS_QM: char = '>';
+const DOS_DOT: char = '"';
+
+/// Reports whether `name` matches the DOS/Win32 search expression `pattern`, using the same
+/// semantics as Windows' FsRtlIsNameInExpression: matching is case-insensitive, '?' matches
+/// exactly one character, '*' matches zero or more characters, and the special DOS_STAR/DOS_QM/
+/// DOS_DOT wildcards account for the quirks of matching against 8.3 short names (e.g. an
+/// extension-less name is treated as though it had a trailing '.').
+///
+/// Used to filter the directory contents returned in a SharedDirectoryListResponse down to the
+/// entries RDP actually asked for in an IRP_MN_QUERY_DIRECTORY, rather than handing back (and
+/// caching) every file in the directory for what might be a narrow wildcard query.
+fn is_name_in_expression(name: &str, pattern: &str) -> bool {
+    if pattern.is_empty() {
+        return true;
+    }
+
+    let name: Vec<char> = name.to_uppercase().chars().collect();
+    let pattern: Vec<char> = pattern.to_uppercase().chars().collect();
+    let last_dot = name.iter().rposition(|&c| c == '.');
+
+    is_name_in_expression_from(&pattern, &name, 0, 0, last_dot)
+}
+
+/// Backtracking matcher over (pattern_index, name_index), recursing on every wildcard that has
+/// more than one way to consume `name`. `last_dot` is the index of the final '.' in `name`, which
+/// DOS_STAR/DOS_QM/DOS_DOT all need in order to reason about "the position before an implied
+/// trailing dot at end-of-name".
+fn is_name_in_expression_from(
+    pattern: &[char],
+    name: &[char],
+    mut p: usize,
+    mut n: usize,
+    last_dot: Option<usize>,
+) -> bool {
+    loop {
+        if p == pattern.len() {
+            return n == name.len();
+        }
+
+        match pattern[p] {
+            '*' => {
+                return (n..=name.len())
+                    .any(|i| is_name_in_expression_from(pattern, name, p + 1, i, last_dot));
+            }
+            DOS_STAR => {
+                // Like '*', but may never consume past the last '.' remaining in name.
+                let limit = last_dot.filter(|&d| d >= n).unwrap_or(name.len());
+                return (n..=limit)
+                    .any(|i| is_name_in_expression_from(pattern, name, p + 1, i, last_dot));
+            }
+            '?' => {
+                if n == name.len() {
+                    return false;
+                }
+                p += 1;
+                n += 1;
+            }
+            DOS_QM => {
+                // Matches one character, unless we're at the end of name or on the dot that
+                // separates base name from extension, in which case it matches zero.
+                if n < name.len() && name[n] != '.' {
+                    p += 1;
+                    n += 1;
+                } else {
+                    p += 1;
+                }
+            }
+            DOS_DOT => {
+                // Matches a literal '.', or, if there's none, the implied trailing dot at the
+                // end of an extension-less name.
+                if n < name.len() && name[n] == '.' {
+                    p += 1;
+                    n += 1;
+                } else if n == name.len() {
+                    p += 1;
+                } else {
+                    return false;
+                }
+            }
+            c => {
+                if n == name.len() || name[n] != c {
+                    return false;
+                }
+                p += 1;
+                n += 1;
+            }
+        }
+    }
+}
+
 /// FileCacheObject is an in-memory representation of
 /// of a file or directory holding the metadata necessary
 /// for RDP drive redirection. They are stored in map indexed
@@ -1317,6 +2900,10 @@ pub struct FileCacheObject {
     dot_sent: bool,
     /// Book-keeping variable, see Iterator implementation
     dotdot_sent: bool,
+    /// 8.3 short names generated so far for this directory enumeration (see `next_batch`),
+    /// scoped to this FileCacheObject since short-name uniqueness only matters within one
+    /// directory.
+    short_names: ShortNameTable,
 }
 
 impl FileCacheObject {
@@ -1330,6 +2917,7 @@ impl FileCacheObject {
             contents_i: 0,
             dot_sent: false,
             dotdot_sent: false,
+            short_names: ShortNameTable::default(),
         }
     }
 }
@@ -1364,6 +2952,9 @@ impl Iterator for FileCacheObject {
                 file_type: self.fso.file_type,
                 is_empty: TDP_FALSE,
                 path: UnixPath::from(".".to_string()),
+                symlink_target: UnixPath::from(String::new()),
+                file_id: self.fso.file_id,
+                extended_attributes: self.fso.extended_attributes.clone(),
             })
         } else if !self.dotdot_sent {
             // On the second call to next, return the ".." directory
@@ -1374,6 +2965,9 @@ impl Iterator for FileCacheObject {
                 file_type: FileType::Directory,
                 is_empty: TDP_FALSE,
                 path: UnixPath::from("..".to_string()),
+                symlink_target: UnixPath::from(String::new()),
+                file_id: self.fso.file_id,
+                extended_attributes: self.fso.extended_attributes.clone(),
             })
         } else {
             // "." and ".." have been sent, now start iterating through
@@ -1388,6 +2982,55 @@ impl Iterator for FileCacheObject {
     }
 }
 
+impl FileCacheObject {
+    /// Pulls as many directory entries as fit within `buffer_budget` bytes off this
+    /// FileCacheObject's iterator (see the `Iterator` impl above), converting each to
+    /// `file_info_class_lvl`'s wire representation, stopping once the next entry would push the
+    /// response over budget. Always includes at least one entry if any remain, even if that
+    /// entry alone exceeds the budget, so the enumerator can't get stuck making no progress.
+    ///
+    /// Returns an empty Vec once "." ".." and every entry in `contents` has been handed out, at
+    /// which point the caller should reply with STATUS_NO_MORE_FILES. Like a `walkdir`-style
+    /// streaming iterator, this is safe to call repeatedly across successive
+    /// IRP_MN_QUERY_DIRECTORY requests for the same FileId: the cursor this leaves `next()` at is
+    /// exactly where the following call resumes from.
+    fn next_batch(
+        &mut self,
+        file_info_class_lvl: &FileInformationClassLevel,
+        buffer_budget: u32,
+    ) -> RdpResult<Vec<FileInformationClass>> {
+        let mut entries = Vec::new();
+        let mut budget_used: u32 = 0;
+
+        loop {
+            let checkpoint = (self.dot_sent, self.dotdot_sent, self.contents_i);
+            let fso = match self.next() {
+                Some(fso) => fso,
+                None => break,
+            };
+
+            // Check the budget against the entry's size before actually building it, so that an
+            // entry we end up not using (because it doesn't fit) never consumes a name from
+            // self.short_names.
+            let entry_size = FileInformationClass::aligned_size_for(file_info_class_lvl, &fso.name()?)?;
+            if !entries.is_empty() && budget_used.saturating_add(entry_size) > buffer_budget {
+                // Doesn't fit in this response; put the cursor back so the next
+                // IRP_MN_QUERY_DIRECTORY picks this entry up instead of skipping over it.
+                self.dot_sent = checkpoint.0;
+                self.dotdot_sent = checkpoint.1;
+                self.contents_i = checkpoint.2;
+                break;
+            }
+
+            let entry = FileInformationClass::from_fso(file_info_class_lvl, fso, &mut self.short_names)?;
+            budget_used += entry_size;
+            entries.push(entry);
+        }
+
+        Ok(entries)
+    }
+}
+
 type SharedDirectoryAcknowledgeSender = Box<dyn Fn(SharedDirectoryAcknowledge) -> RdpResult<()>>;
 type SharedDirectoryInfoRequestSender = Box<dyn Fn(SharedDirectoryInfoRequest) -> RdpResult<()>>;
 type SharedDirectoryCreateRequestSender =
@@ -1398,6 +3041,13 @@ type SharedDirectoryListRequestSender = Box<dyn Fn(SharedDirectoryListRequest) -
 type SharedDirectoryReadRequestSender = Box<dyn Fn(SharedDirectoryReadRequest) -> RdpResult<()>>;
 type SharedDirectoryWriteRequestSender = Box<dyn Fn(SharedDirectoryWriteRequest) -> RdpResult<()>>;
 type SharedDirectoryMoveRequestSender = Box<dyn Fn(SharedDirectoryMoveRequest) -> RdpResult<()>>;
+type SharedDirectoryFreeSpaceRequestSender =
+    Box<dyn Fn(SharedDirectoryFreeSpaceRequest) -> RdpResult<()>>;
+type SharedDirectoryTruncateRequestSender =
+    Box<dyn Fn(SharedDirectoryTruncateRequest) -> RdpResult<()>>;
+type SharedDirectorySetTimestampsRequestSender =
+    Box<dyn Fn(SharedDirectorySetTimestampsRequest) -> RdpResult<()>>;
+type SharedDirectoryWatchRequestSender = Box<dyn Fn(SharedDirectoryWatchRequest) -> RdpResult<()>>;
 
 type SharedDirectoryInfoResponseHandler =
     Box<dyn FnOnce(&mut Client, SharedDirectoryInfoResponse) -> RdpResult<Vec<u8>>>;
@@ -1413,3 +3063,9 @@ type SharedDirectoryWriteResponseHandler =
     Box<dyn FnOnce(&mut Client, SharedDirectoryWriteResponse) -> RdpResult<Vec<u8>>>;
 type SharedDirectoryMoveResponseHandler =
     Box<dyn FnOnce(&mut Client, SharedDirectoryMoveResponse) -> RdpResult<Vec<u8>>>;
+type SharedDirectoryFreeSpaceResponseHandler =
+    Box<dyn FnOnce(&mut Client, SharedDirectoryFreeSpaceResponse) -> RdpResult<Vec<u8>>>;
+type SharedDirectoryTruncateResponseHandler =
+    Box<dyn FnOnce(&mut Client, SharedDirectoryTruncateResponse) -> RdpResult<Vec<u8>>>;
+type SharedDirectorySetTimestampsResponseHandler =
+    Box<dyn FnOnce(&mut Client, SharedDirectorySetTimestampsResponse) -> RdpResult<Vec<u8>>>;
@@ -0,0 +1,171 @@
+// Copyright 2026 Gravitational, Inc
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::consts::{DeviceType, NTSTATUS};
+use super::{
+    DeviceAnnounceHeader, DeviceCloseRequest, DeviceCloseResponse, DeviceControlRequest,
+    DeviceControlResponse, DeviceCreateRequest, DeviceCreateResponse, DeviceIoRequest,
+    DeviceWriteRequest, DeviceWriteResponse,
+};
+use crate::util;
+use crate::Payload;
+use byteorder::{LittleEndian, WriteBytesExt};
+use rdp::model::error::RdpResult;
+
+/// Flags carried in a printer DEVICE_ANNOUNCE's DeviceData (see `PrinterDeviceData`). MS-RDPEFS
+/// doesn't assign these their own section, but client and server implementations (e.g. FreeRDP's
+/// printer redirection) agree on this layout.
+const RDPDR_PRINTER_ANNOUNCE_FLAG_ASCII: u32 = 0x0000_0001;
+const RDPDR_PRINTER_ANNOUNCE_FLAG_DEFAULTPRINTER: u32 = 0x0000_0002;
+
+/// Delivers the raw bytes of a single IRP_MJ_WRITE against the redirected printer - one chunk of
+/// a spooled print job, in whatever chunking the RDP server chose - out over TDP so the far side
+/// (browser) can hand them to the local print spooler. Modeled on the `*Sender` closure types
+/// `dir::Config` uses to hand its own TDP requests off to the browser.
+pub type PrinterSpoolDataSender = Box<dyn Fn(Vec<u8>) -> RdpResult<()>>;
+
+pub struct Config {
+    /// The name advertised to the RDP server as this printer's PrinterName.
+    pub printer_name: String,
+    /// The name advertised to the RDP server as this printer's DriverName.
+    pub driver_name: String,
+    /// Whether to advertise this printer as the session's default printer.
+    pub is_default: bool,
+    pub spool_data_sender: PrinterSpoolDataSender,
+}
+
+/// Client implements a single redirected client-side printer, following the same Create/Write/
+/// Close IRP sequence as a redirected drive, but with no path/namespace semantics: every create
+/// opens a new job stream, and every write is forwarded verbatim to `spool_data_sender`.
+pub struct Client {
+    cfg: Config,
+}
+
+impl Client {
+    pub fn new(cfg: Config) -> Self {
+        Self { cfg }
+    }
+
+    /// Builds this printer's DEVICE_ANNOUNCE entry for a Client Device List Announce Request.
+    /// Only called by rdpdr::Client, which owns device_id assignment for every redirected
+    /// device.
+    pub(super) fn announce_header(&self, device_id: u32) -> DeviceAnnounceHeader {
+        let mut flags = RDPDR_PRINTER_ANNOUNCE_FLAG_ASCII;
+        if self.cfg.is_default {
+            flags |= RDPDR_PRINTER_ANNOUNCE_FLAG_DEFAULTPRINTER;
+        }
+
+        let device_data = PrinterDeviceData {
+            flags,
+            printer_name: self.cfg.printer_name.clone(),
+            driver_name: self.cfg.driver_name.clone(),
+        }
+        .encode()
+        // Encoding only fails on write errors against a Vec<u8>, which can't happen.
+        .unwrap_or_default();
+
+        DeviceAnnounceHeader {
+            device_type: DeviceType::RDPDR_DTYP_PRINT,
+            device_id,
+            // This name is arbitrary; printers have no 8.3 path semantics to collide over.
+            preferred_dos_name: "PRN1".to_string(),
+            device_data_length: device_data.len() as u32,
+            device_data,
+        }
+    }
+
+    pub fn process_irp_create(
+        &self,
+        device_io_request: DeviceIoRequest,
+        payload: &mut Payload,
+    ) -> RdpResult<Vec<u8>> {
+        let rdp_req = DeviceCreateRequest::decode(device_io_request, payload)?;
+        debug!("received RDP: {:?}", rdp_req);
+
+        // Printer "files" have no path/namespace semantics, so there's nothing to look up or
+        // allocate: every create opens a new job stream, and file_id is never consulted again.
+        let resp = DeviceCreateResponse::new(&rdp_req, NTSTATUS::STATUS_SUCCESS, 0);
+        debug!("sending RDP: {:?}", resp);
+        resp.encode()
+    }
+
+    pub fn process_irp_write(
+        &self,
+        device_io_request: DeviceIoRequest,
+        payload: &mut Payload,
+    ) -> RdpResult<Vec<u8>> {
+        let rdp_req = DeviceWriteRequest::decode(device_io_request, payload)?;
+        debug!("received RDP: {:?}", rdp_req);
+
+        (self.cfg.spool_data_sender)(rdp_req.write_data.clone())?;
+
+        let resp = DeviceWriteResponse::new(
+            &rdp_req.device_io_request,
+            NTSTATUS::STATUS_SUCCESS,
+            rdp_req.length,
+        );
+        debug!("sending RDP: {:?}", resp);
+        resp.encode()
+    }
+
+    pub fn process_irp_close(&self, device_io_request: DeviceIoRequest) -> RdpResult<Vec<u8>> {
+        let rdp_req = DeviceCloseRequest::decode(device_io_request);
+        debug!("received RDP: {:?}", rdp_req);
+
+        let resp = DeviceCloseResponse::new(rdp_req, NTSTATUS::STATUS_SUCCESS);
+        debug!("sending RDP: {:?}", resp);
+        resp.encode()
+    }
+
+    pub fn process_irp_device_control(&self, ioctl: DeviceControlRequest) -> RdpResult<Vec<u8>> {
+        // No printer-specific IOCTL codes are implemented; mimic FreeRDP's "no-op" response
+        // rather than failing the request.
+        debug!("received RDP: {:?}", ioctl);
+        let resp = DeviceControlResponse::new(&ioctl, NTSTATUS::STATUS_SUCCESS as u32, vec![]);
+        debug!("sending RDP: {:?}", resp);
+        resp.encode()
+    }
+}
+
+/// The `DeviceData` blob of a printer's DEVICE_ANNOUNCE (see `DeviceAnnounceHeader`). Mirrors the
+/// layout client/server implementations (e.g. FreeRDP) use for RDPDR_DTYP_PRINT devices: a flags
+/// word followed by four length-prefixed name fields. We don't cache any driver-specific PNP or
+/// config data, so those fields are always empty.
+struct PrinterDeviceData {
+    flags: u32,
+    printer_name: String,
+    driver_name: String,
+}
+
+impl PrinterDeviceData {
+    fn encode(&self) -> RdpResult<Vec<u8>> {
+        // In the RDP spec, Unicode typically means null-terminated UTF-16LE, however empirically
+        // it appears that these fields expect null-terminated UTF-8 (see
+        // ClientDeviceListAnnounceRequest::new_drive for the same observation about DeviceData),
+        // which is what the RDPDR_PRINTER_ANNOUNCE_FLAG_ASCII flag above declares we're sending.
+        let printer_name = util::to_utf8(&self.printer_name);
+        let driver_name = util::to_utf8(&self.driver_name);
+
+        let mut w = vec![];
+        w.write_u32::<LittleEndian>(self.flags)?;
+        w.write_u32::<LittleEndian>(0)?; // CodePage: unused.
+        w.write_u32::<LittleEndian>(0)?; // PnPNameLen: we don't advertise PNP data.
+        w.write_u32::<LittleEndian>(driver_name.len() as u32)?;
+        w.write_u32::<LittleEndian>(printer_name.len() as u32)?;
+        w.write_u32::<LittleEndian>(0)?; // CachedFieldsLen: no cached driver config.
+        w.extend_from_slice(&driver_name);
+        w.extend_from_slice(&printer_name);
+        Ok(w)
+    }
+}
@@ -0,0 +1,336 @@
+// Copyright 2026 Gravitational, Inc
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub(crate) mod ring;
+
+use crate::errors::invalid_data_error;
+use crate::vchan::{self, ChannelPDUFlags};
+use crate::Payload;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use num_traits::{FromPrimitive, ToPrimitive};
+use rdp::core::{mcs, tpkt};
+use rdp::model::error::*;
+use rdp::try_let;
+use ring::AudioRing;
+use std::io::{Read, Write};
+
+/// Client implements the audio output virtual channel (RDPSND), as defined in
+/// https://winprotocoldoc.blob.core.windows.net/productionwindowsarchives/MS-RDPEA/%5bMS-RDPEA%5d.pdf.
+///
+/// Only playback (server to client) is implemented, since Teleport Desktop Access has no use
+/// for capturing audio on the client side.
+pub const CHANNEL_NAME: &str = "rdpsnd";
+
+/// The size, in bytes, of the shared memory ring used to hand decoded PCM audio frames to Go.
+/// Large enough to absorb a scheduling hiccup on the Go side without dropping frames, without
+/// holding onto an unreasonable amount of memory per session.
+pub const AUDIO_RING_CAPACITY: usize = 1024 * 1024;
+
+/// The wFormatTag for uncompressed PCM audio; the only format this client advertises support
+/// for, so every SharedAudioFormat below uses it.
+const WAVE_FORMAT_PCM: u16 = 1;
+
+/// The client's audio formats, in preference order, sent to the server in the Client Audio
+/// Formats PDU and indexed (by position) by wFormatNo in the server's Wave PDUs.
+const SUPPORTED_FORMATS: &[AudioFormat] = &[
+    AudioFormat {
+        channels: 2,
+        samples_per_sec: 44100,
+        bits_per_sample: 16,
+    },
+    AudioFormat {
+        channels: 2,
+        samples_per_sec: 22050,
+        bits_per_sample: 16,
+    },
+    AudioFormat {
+        channels: 1,
+        samples_per_sec: 22050,
+        bits_per_sample: 16,
+    },
+];
+
+/// A single PCM audio format this client can play back.
+#[derive(Debug, Clone, Copy)]
+pub struct AudioFormat {
+    pub channels: u16,
+    pub samples_per_sec: u32,
+    pub bits_per_sample: u16,
+}
+
+impl AudioFormat {
+    fn block_align(&self) -> u16 {
+        self.channels * (self.bits_per_sample / 8)
+    }
+
+    fn avg_bytes_per_sec(&self) -> u32 {
+        self.samples_per_sec * u32::from(self.block_align())
+    }
+
+    /// Encodes this format as a WAVEFORMATEX structure (MS-RDPEA 2.2.1.1 AUDIO_FORMAT), without
+    /// any format-specific extra data (cbSize is always 0 here, since WAVE_FORMAT_PCM has none).
+    fn encode(&self, w: &mut Vec<u8>) -> RdpResult<()> {
+        w.write_u16::<LittleEndian>(WAVE_FORMAT_PCM)?;
+        w.write_u16::<LittleEndian>(self.channels)?;
+        w.write_u32::<LittleEndian>(self.samples_per_sec)?;
+        w.write_u32::<LittleEndian>(self.avg_bytes_per_sec())?;
+        w.write_u16::<LittleEndian>(self.block_align())?;
+        w.write_u16::<LittleEndian>(self.bits_per_sample)?;
+        w.write_u16::<LittleEndian>(0)?; // cbSize
+        Ok(())
+    }
+}
+
+/// MS-RDPEA 2.2.2 message types, carried in the one-byte msgType field of every RDPSND PDU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+#[allow(non_camel_case_types)]
+enum MessageType {
+    SNDC_CLOSE = 1,
+    SNDC_WAVE = 2,
+    SNDC_SETVOLUME = 3,
+    SNDC_SETPITCH = 4,
+    SNDC_WAVECONFIRM = 5,
+    SNDC_TRAINING = 6,
+    SNDC_FORMATS = 7,
+    SNDC_WAVE2 = 13,
+}
+
+/// Bookkeeping for a WaveInfo PDU (SNDC_WAVE) whose data arrives split across two PDUs: the
+/// WaveInfo PDU itself (which inlines the first 4 bytes of audio data in place of its usual
+/// padding) and a second, header-less PDU carrying the rest.
+struct PendingWave {
+    format_no: u16,
+    block_no: u8,
+    first_four_bytes: [u8; 4],
+}
+
+pub struct Client {
+    /// Sending audio to Go is gated on this; when false the client still negotiates and
+    /// acknowledges PDUs (so the server doesn't retry or tear down the channel) but never
+    /// writes frames into `ring`.
+    allow_audio: bool,
+    ring: AudioRing,
+    pending_wave: Option<PendingWave>,
+}
+
+impl Client {
+    pub fn new(allow_audio: bool, ring: AudioRing) -> Self {
+        Client {
+            allow_audio,
+            ring,
+            pending_wave: None,
+        }
+    }
+
+    /// A raw pointer to the start of the shared memory region backing `ring`, along with its
+    /// length, so the Go side can map the same pages; see ring::AudioRing.
+    pub fn ring_handle(&self) -> (*mut u8, usize) {
+        (self.ring.region_ptr(), self.ring.region_len())
+    }
+
+    pub fn read_and_reply<S: Read + Write>(
+        &mut self,
+        payload: tpkt::Payload,
+        mcs: &mut mcs::Client<S>,
+    ) -> RdpResult<()> {
+        let mut payload = try_let!(tpkt::Payload::Raw, payload)?;
+        let pdu_header = vchan::ChannelPDUHeader::decode(&mut payload)?;
+
+        // The Wave Data PDU that completes a WaveInfo PDU has no SNDPROLOG header of its own —
+        // it's just a one-byte pad followed by the rest of the frame — so it has to be detected
+        // and consumed here, before trying to decode a normal message header.
+        if let Some(pending) = self.pending_wave.take() {
+            return self.handle_wave_data(pending, &mut payload, mcs);
+        }
+
+        let header = PduHeader::decode(&mut payload)?;
+        let responses = match header.msg_type {
+            MessageType::SNDC_FORMATS => self.handle_server_formats(&mut payload)?,
+            MessageType::SNDC_TRAINING => self.handle_training(&mut payload)?,
+            MessageType::SNDC_WAVE => self.handle_wave_info(&mut payload)?,
+            MessageType::SNDC_WAVE2 => self.handle_wave2(&mut payload)?,
+            _ => {
+                debug!(
+                    "RDPSND message {:?} not implemented, ignoring",
+                    header.msg_type
+                );
+                vec![]
+            }
+        };
+
+        let chan = &CHANNEL_NAME.to_string();
+        for resp in responses {
+            mcs.write(chan, resp)?;
+        }
+        Ok(())
+    }
+
+    /// Handles the Server Audio Formats PDU by replying with a Client Audio Formats PDU
+    /// advertising SUPPORTED_FORMATS. We don't bother cross-referencing the server's format
+    /// list against our own, since wFormatNo in subsequent Wave PDUs indexes *our* list, not
+    /// the server's (MS-RDPEA 3.2.5.2.2).
+    fn handle_server_formats(&mut self, _payload: &mut Payload) -> RdpResult<Vec<Vec<u8>>> {
+        let mut body = Vec::new();
+        body.write_u32::<LittleEndian>(0)?; // dwFlags
+        body.write_u32::<LittleEndian>(0xFFFFFFFF)?; // dwVolume: full, unchanged
+        body.write_u32::<LittleEndian>(0xFFFFFFFF)?; // dwPitch: unchanged
+        body.write_u16::<LittleEndian>(0)?; // wDGramPort: unused, we only support the vchan
+        body.write_u16::<LittleEndian>(SUPPORTED_FORMATS.len() as u16)?;
+        body.write_u8(0)?; // cLastBlockConfirmed
+        body.write_u16::<LittleEndian>(6)?; // wVersion: MS-RDPEA 1.0.0.6 client
+        body.write_u8(0)?; // bPad
+        for format in SUPPORTED_FORMATS {
+            format.encode(&mut body)?;
+        }
+
+        Ok(vec![self.wrap(MessageType::SNDC_FORMATS, body)?])
+    }
+
+    /// Echoes back the server's Training PDU timestamp and pack size, as MS-RDPEA 3.2.5.2.3
+    /// requires of every client that wants the server to measure its link speed.
+    fn handle_training(&mut self, payload: &mut Payload) -> RdpResult<Vec<Vec<u8>>> {
+        let time_stamp = payload.read_u16::<LittleEndian>()?;
+        let pack_size = payload.read_u16::<LittleEndian>()?;
+
+        let mut body = Vec::new();
+        body.write_u16::<LittleEndian>(time_stamp)?;
+        body.write_u16::<LittleEndian>(pack_size)?;
+
+        Ok(vec![self.wrap(MessageType::SNDC_TRAINING, body)?])
+    }
+
+    /// Handles the WaveInfo PDU: stashes the frame's format/block number and the first 4 bytes
+    /// of its data (which MS-RDPEA inlines here in place of the usual padding), then waits for
+    /// the header-less PDU that follows with the rest of the frame; see handle_wave_data.
+    fn handle_wave_info(&mut self, payload: &mut Payload) -> RdpResult<Vec<Vec<u8>>> {
+        let _time_stamp = payload.read_u16::<LittleEndian>()?;
+        let format_no = payload.read_u16::<LittleEndian>()?;
+        let block_no = payload.read_u8()?;
+        let mut first_four_bytes = [0u8; 4];
+        payload.read_exact(&mut first_four_bytes)?;
+
+        self.pending_wave = Some(PendingWave {
+            format_no,
+            block_no,
+            first_four_bytes,
+        });
+        Ok(vec![])
+    }
+
+    /// Completes a WaveInfo PDU once its header-less continuation arrives: reassembles the full
+    /// frame (the 4 bytes stashed by handle_wave_info, then everything here but the leading pad
+    /// byte), delivers it to Go, and confirms the block so the server can keep streaming.
+    fn handle_wave_data<S: Read + Write>(
+        &mut self,
+        pending: PendingWave,
+        payload: &mut Payload,
+        mcs: &mut mcs::Client<S>,
+    ) -> RdpResult<()> {
+        let _pad = payload.read_u8()?;
+        let mut rest = Vec::new();
+        payload.read_to_end(&mut rest)?;
+
+        let mut frame = Vec::with_capacity(4 + rest.len());
+        frame.extend_from_slice(&pending.first_four_bytes);
+        frame.extend(rest);
+
+        self.deliver_frame(pending.format_no, &frame);
+
+        let resp = self.wave_confirm(pending.block_no)?;
+        mcs.write(&CHANNEL_NAME.to_string(), resp)
+    }
+
+    /// Handles the Wave2 PDU (MS-RDPEA 2.2.3.3): unlike WaveInfo/Wave, the entire frame is
+    /// carried in a single PDU, so there's no continuation to wait for.
+    fn handle_wave2(&mut self, payload: &mut Payload) -> RdpResult<Vec<Vec<u8>>> {
+        let _time_stamp = payload.read_u16::<LittleEndian>()?;
+        let format_no = payload.read_u16::<LittleEndian>()?;
+        let block_no = payload.read_u8()?;
+        let mut pad = [0u8; 3];
+        payload.read_exact(&mut pad)?;
+        let _audio_time_stamp = payload.read_u32::<LittleEndian>()?;
+
+        let mut frame = Vec::new();
+        payload.read_to_end(&mut frame)?;
+
+        self.deliver_frame(format_no, &frame);
+
+        Ok(vec![self.wave_confirm(block_no)?])
+    }
+
+    /// Pushes a decoded PCM frame into the shared-memory ring for Go to pick up, unless audio
+    /// output is disabled for this session.
+    fn deliver_frame(&mut self, format_no: u16, frame: &[u8]) {
+        if !self.allow_audio {
+            return;
+        }
+        if !self.ring.push(format_no, frame) {
+            debug!("audio ring buffer full, dropping a frame");
+        }
+    }
+
+    fn wave_confirm(&mut self, block_no: u8) -> RdpResult<Vec<u8>> {
+        let mut body = Vec::new();
+        body.write_u16::<LittleEndian>(0)?; // wTimeStamp: server doesn't use the echoed value
+        body.write_u8(block_no)?;
+        body.write_u8(0)?; // bPad
+        self.wrap(MessageType::SNDC_WAVECONFIRM, body)
+    }
+
+    fn wrap(&self, msg_type: MessageType, mut body: Vec<u8>) -> RdpResult<Vec<u8>> {
+        let mut inner = PduHeader::new(msg_type, body.len() as u16).encode()?;
+        inner.append(&mut body);
+
+        let channel_flags = ChannelPDUFlags::CHANNEL_FLAG_FIRST | ChannelPDUFlags::CHANNEL_FLAG_LAST;
+        let mut outer = vchan::ChannelPDUHeader::new(inner.len() as u32, channel_flags).encode()?;
+        outer.extend(inner);
+        Ok(outer)
+    }
+}
+
+/// MS-RDPEA 2.2.1 SNDPROLOG: the 4-byte header prefixing every RDPSND PDU except the header-less
+/// continuation of a WaveInfo PDU.
+struct PduHeader {
+    msg_type: MessageType,
+    body_size: u16,
+}
+
+impl PduHeader {
+    fn new(msg_type: MessageType, body_size: u16) -> Self {
+        PduHeader {
+            msg_type,
+            body_size,
+        }
+    }
+
+    fn decode(payload: &mut Payload) -> RdpResult<Self> {
+        let msg_type = MessageType::from_u8(payload.read_u8()?).ok_or_else(|| {
+            invalid_data_error("received invalid RDPSND message type")
+        })?;
+        let _pad = payload.read_u8()?;
+        let body_size = payload.read_u16::<LittleEndian>()?;
+        Ok(PduHeader {
+            msg_type,
+            body_size,
+        })
+    }
+
+    fn encode(&self) -> RdpResult<Vec<u8>> {
+        let mut w = Vec::new();
+        w.write_u8(self.msg_type.to_u8().unwrap())?;
+        w.write_u8(0)?; // bPad
+        w.write_u16::<LittleEndian>(self.body_size)?;
+        Ok(w)
+    }
+}
@@ -0,0 +1,173 @@
+// Copyright 2026 Gravitational, Inc
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A lock-free single-producer/single-consumer byte ring, mapped into a shared memory region so
+//! decoded PCM audio frames can cross the CGO boundary without a per-packet call into Go (and
+//! without a mutex on the hot path). Rust is always the producer; Go is always the consumer.
+//!
+//! Layout of the mapped region:
+//!   [0..8)    write cursor (u64, written only by the producer)
+//!   [8..16)   read cursor (u64, written only by the consumer)
+//!   [16..)    the data ring itself, `capacity` bytes long
+//!
+//! Every frame pushed into the ring is prefixed with a fixed-size header:
+//!   frame_len: u32, format_id: u16, seq: u64
+//! `seq` increases by one per frame (wrapping), so a consumer that reads two frames with a gap in
+//! `seq` knows frames were dropped rather than silently desynchronizing. A header is never split
+//! across the end of the data ring: if it wouldn't fit contiguously, a pad marker
+//! (frame_len == PAD_FRAME_LEN) is written instead and the producer wraps to offset 0.
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use std::io;
+use std::ptr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// frame_len(4) + format_id(2) + seq(8).
+pub const HEADER_LEN: usize = 14;
+
+/// A frame_len value that can never occur for a real frame (frame_len is a u32 but no PCM frame
+/// remotely approaches u32::MAX bytes); tells the consumer "ignore everything from here to the
+/// end of the ring and resume reading at offset 0".
+const PAD_FRAME_LEN: u32 = u32::MAX;
+
+const CURSOR_REGION_LEN: usize = 16;
+
+/// AudioRing is the producer (Rust) side of the shared-memory ring described above.
+pub struct AudioRing {
+    region: *mut u8,
+    region_len: usize,
+    capacity: usize,
+    next_seq: u64,
+}
+
+// The region is backed by an anonymous MAP_SHARED mapping and all cross-process access to it
+// goes through the atomics at its head, so it's sound to move the handle across threads.
+unsafe impl Send for AudioRing {}
+
+impl AudioRing {
+    /// Maps a fresh, zeroed shared memory region with room for `capacity` bytes of frame data
+    /// (plus the fixed 16-byte cursor header) and returns the producer handle for it.
+    pub fn new(capacity: usize) -> io::Result<Self> {
+        let region_len = CURSOR_REGION_LEN + capacity;
+        let region = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                region_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if region == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(AudioRing {
+            region: region.cast(),
+            region_len,
+            capacity,
+            next_seq: 0,
+        })
+    }
+
+    /// A raw pointer to the start of the mapped region (cursors followed by the data ring),
+    /// suitable for handing to Go so it can `mmap` the same pages into its own address space.
+    pub fn region_ptr(&self) -> *mut u8 {
+        self.region
+    }
+
+    /// The total size, in bytes, of the mapped region (cursors included).
+    pub fn region_len(&self) -> usize {
+        self.region_len
+    }
+
+    fn write_cursor(&self) -> &AtomicU64 {
+        unsafe { &*(self.region.cast::<AtomicU64>()) }
+    }
+
+    fn read_cursor(&self) -> &AtomicU64 {
+        unsafe { &*(self.region.add(8).cast::<AtomicU64>()) }
+    }
+
+    fn data_ptr(&self) -> *mut u8 {
+        unsafe { self.region.add(CURSOR_REGION_LEN) }
+    }
+
+    /// Bytes free between the write and read cursors, reserving one byte so a full ring is
+    /// distinguishable from an empty one.
+    fn free_bytes(&self, read: usize, write: usize) -> usize {
+        if write >= read {
+            self.capacity - (write - read) - 1
+        } else {
+            read - write - 1
+        }
+    }
+
+    fn write_wrapping(&self, mut offset: usize, data: &[u8]) -> usize {
+        for &b in data {
+            unsafe { *self.data_ptr().add(offset) = b };
+            offset = (offset + 1) % self.capacity;
+        }
+        offset
+    }
+
+    /// Pushes one decoded PCM frame into the ring. Returns false (dropping the frame) if there
+    /// isn't room for it; the consumer is expected to keep up with the hot audio path, and
+    /// dropping an occasional frame is preferable to blocking the RDP read loop on it.
+    pub fn push(&mut self, format_id: u16, frame: &[u8]) -> bool {
+        let read = self.read_cursor().load(Ordering::Acquire) as usize;
+        let mut write = self.write_cursor().load(Ordering::Relaxed) as usize;
+
+        let to_end = self.capacity - write;
+        if to_end < HEADER_LEN {
+            // The header can't be written contiguously here. If there's at least room for the
+            // 4-byte frame_len field, mark this spot as padding and wrap; otherwise (an
+            // exceedingly small `to_end`, e.g. near-empty capacity) just drop the frame rather
+            // than writing a torn pad marker.
+            if to_end < 4 || self.free_bytes(read, write) < to_end {
+                return false;
+            }
+            self.write_wrapping(write, &PAD_FRAME_LEN.to_le_bytes());
+            write = 0;
+        }
+
+        let needed = HEADER_LEN + frame.len();
+        if self.free_bytes(read, write) < needed {
+            return false;
+        }
+
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.write_u32::<LittleEndian>(frame.len() as u32).unwrap();
+        header.write_u16::<LittleEndian>(format_id).unwrap();
+        header.write_u64::<LittleEndian>(seq).unwrap();
+
+        let after_header = self.write_wrapping(write, &header);
+        let after_frame = self.write_wrapping(after_header, frame);
+
+        self.write_cursor()
+            .store(after_frame as u64, Ordering::Release);
+        true
+    }
+}
+
+impl Drop for AudioRing {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.region.cast(), self.region_len);
+        }
+    }
+}
@@ -0,0 +1,127 @@
+// todo(isaiah): this is a first pass at validating the routing/association token carried in an
+// RDCleanPath request. It covers signature + exp/nbf/aud + single-use jti, but doesn't yet wire a
+// real provisioner key set in from config - callers build a `TokenValidationConfig` themselves
+// for now.
+
+use crate::devolutions_gateway_utils::AuthorizationError;
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+/// The claims this gateway requires out of a routing/association token. Anything else in the
+/// token is ignored.
+#[derive(Debug, Deserialize)]
+pub(crate) struct RoutingTokenClaims {
+    /// Unique ID for this token, checked against `ReplayCache` so a given token can only be
+    /// redeemed once.
+    pub jti: String,
+    pub aud: String,
+    pub exp: u64,
+    pub nbf: u64,
+}
+
+/// One provisioner's verification key, keyed by `kid` so a token is checked against the specific
+/// key that (claims to have) signed it rather than every key in the set.
+pub(crate) struct ProvisionerKey {
+    pub kid: String,
+    pub decoding_key: DecodingKey,
+    pub algorithm: Algorithm,
+}
+
+/// Config for the token-validation gate that guards `PduTransport::run_cleanpath`.
+pub(crate) struct TokenValidationConfig {
+    pub provisioner_keys: Vec<ProvisionerKey>,
+    pub expected_audience: String,
+    /// Dev-only escape hatch: skips signature and exp/nbf/aud verification; the claims are still
+    /// parsed and the jti replay check still applies. Must never be set in production - any token
+    /// whose claims parse gets through the gate unless its jti was already redeemed.
+    pub disable_token_validation: bool,
+}
+
+/// Tracks `jti`s already redeemed so a captured token can't be replayed. Process-lifetime only,
+/// same as this crate's other in-memory caches - a restart clears it.
+#[derive(Default)]
+pub(crate) struct ReplayCache {
+    seen: Mutex<HashSet<String>>,
+}
+
+impl ReplayCache {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns `Err(Replayed)` if `jti` was already redeemed, otherwise records it and returns
+    /// `Ok`.
+    fn check_and_record(&self, jti: &str) -> Result<(), AuthorizationError> {
+        let mut seen = self.seen.lock().expect("replay cache mutex poisoned");
+        if !seen.insert(jti.to_string()) {
+            return Err(AuthorizationError::Replayed);
+        }
+        Ok(())
+    }
+}
+
+/// Verifies `token`'s signature and standard claims (exp/nbf/aud) against `cfg`'s provisioner key
+/// set, then checks and records its `jti` in `replay_cache`. When
+/// `cfg.disable_token_validation` is set the claims are parsed but not verified; the `jti`
+/// replay check still applies.
+pub(crate) fn validate_routing_token(
+    token: &str,
+    cfg: &TokenValidationConfig,
+    replay_cache: &ReplayCache,
+) -> Result<RoutingTokenClaims, AuthorizationError> {
+    let claims = if cfg.disable_token_validation {
+        decode_claims_unverified(token)?
+    } else {
+        decode_claims_verified(token, cfg)?
+    };
+
+    replay_cache.check_and_record(&claims.jti)?;
+
+    Ok(claims)
+}
+
+fn decode_claims_verified(
+    token: &str,
+    cfg: &TokenValidationConfig,
+) -> Result<RoutingTokenClaims, AuthorizationError> {
+    let header = decode_header(token).map_err(|_| AuthorizationError::BadSignature)?;
+    let kid = header.kid.as_deref().ok_or(AuthorizationError::BadSignature)?;
+    let key = cfg
+        .provisioner_keys
+        .iter()
+        .find(|k| k.kid == kid)
+        .ok_or(AuthorizationError::BadSignature)?;
+
+    let mut validation = Validation::new(key.algorithm);
+    validation.set_audience(&[cfg.expected_audience.clone()]);
+    validation.validate_nbf = true;
+
+    let data = decode::<RoutingTokenClaims>(token, &key.decoding_key, &validation).map_err(|e| {
+        match e.kind() {
+            ErrorKind::ExpiredSignature => AuthorizationError::Expired,
+            ErrorKind::ImmatureSignature => AuthorizationError::NotYetValid,
+            ErrorKind::InvalidAudience => AuthorizationError::AudienceMismatch,
+            _ => AuthorizationError::BadSignature,
+        }
+    })?;
+
+    Ok(data.claims)
+}
+
+/// Parses claims without checking signature or exp/nbf/aud - only reachable via the
+/// `disable_token_validation` dev escape hatch.
+fn decode_claims_unverified(token: &str) -> Result<RoutingTokenClaims, AuthorizationError> {
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.insecure_disable_signature_validation();
+    validation.validate_exp = false;
+    validation.validate_nbf = false;
+    validation.required_spec_claims.clear();
+
+    let data = decode::<RoutingTokenClaims>(token, &DecodingKey::from_secret(&[]), &validation)
+        .map_err(|_| AuthorizationError::BadSignature)?;
+
+    Ok(data.claims)
+}